@@ -0,0 +1,76 @@
+//! Compares path finding over grid points with path finding over polygons
+//! on a large level.
+//!
+//! Usage: cargo bench --bench path_finding
+
+use bevy::prelude::Vec3;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+
+use orkky_pely::navmesh::NavPolygon;
+use orkky_pely::path_finding::find_path;
+use orkky_pely::path_finding::PolygonMesh;
+use orkky_pely::Point;
+
+const SIZE: i32 = 256;
+const BAND: i32 = 16;
+const GAP: i32 = 4;
+
+/// Polygon covering the grid points from `x0`, `z0` to `x1`, `z1`
+fn cells(x0: i32, x1: i32, z0: i32, z1: i32) -> NavPolygon {
+	let (x0, x1, z0, z1) = (x0 as f32 - 0.5, x1 as f32 + 0.5, z0 as f32 - 0.5, z1 as f32 + 0.5);
+
+	NavPolygon {
+		vertices: vec![[x0, 0.0, z0], [x0, 0.0, z1], [x1, 0.0, z1], [x1, 0.0, z0]],
+	}
+}
+
+/// Bands of tiles separated by walls, with a gap at alternating ends of
+/// each wall, so paths across the level wind back and forth
+fn level() -> Vec<NavPolygon> {
+	let mut polygons = Vec::new();
+
+	for band in 0..SIZE / BAND {
+		let x0 = band * BAND;
+		let wall = x0 + BAND - 1;
+
+		for z0 in (0..SIZE).step_by(BAND as usize) {
+			polygons.push(cells(x0, wall - 1, z0, z0 + BAND - 1));
+		}
+
+		if wall < SIZE - 1 {
+			let z0 = if band % 2 == 0 { 0 } else { SIZE - GAP };
+
+			polygons.push(cells(wall, wall, z0, z0 + GAP - 1));
+		}
+	}
+
+	polygons
+}
+
+fn path_finding(c: &mut Criterion) {
+	let polygons = level();
+	let components: Vec<_> = polygons.iter().map(NavPolygon::grid_component).collect();
+	let polygon_mesh = PolygonMesh::new(polygons);
+
+	let (src, dst) = ((1, SIZE / 2), (SIZE - BAND / 2, SIZE / 2));
+
+	let mut group = c.benchmark_group("path_finding");
+	group.sample_size(10);
+
+	group.bench_function("grid", |b| b.iter(|| {
+		find_path(&components, &Point { x: src.0, z: src.1, y: 0 }, &Point { x: dst.0, z: dst.1, y: 0 }).unwrap()
+	}));
+
+	group.bench_function("polygons", |b| b.iter(|| {
+		polygon_mesh.find_path(Vec3::new(src.0 as f32, 0.0, src.1 as f32), Vec3::new(dst.0 as f32, 0.0, dst.1 as f32)).unwrap()
+	}));
+
+	group.bench_function("build polygon mesh", |b| b.iter(|| PolygonMesh::new(level())));
+
+	group.finish();
+}
+
+criterion_group!(benches, path_finding);
+criterion_main!(benches);
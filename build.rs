@@ -0,0 +1,91 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Component;
+use std::path::Path;
+
+/// Map that wasm builds load
+const MAP_FILE: &str = "config/map.json";
+
+/// Lexically normalizes a path like `map_validation::normalize_path`, so
+/// that bundled files are found under the names the game looks them up by.
+fn normalize_path(path: &Path) -> String {
+	let mut parts: Vec<String> = Vec::new();
+
+	for component in path.components() {
+		match component {
+			Component::ParentDir if parts.last().is_some_and(|last| last != "..") => {
+				parts.pop();
+			},
+			Component::ParentDir => parts.push("..".to_string()),
+			Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+			_ => {},
+		}
+	}
+
+	parts.join("/")
+}
+
+/// The `includes` of a map file, in the format of its extension
+fn includes(file: &str, source: &str) -> Vec<String> {
+	let value: Option<serde_json::Value> = match Path::new(file).extension().and_then(|ext| ext.to_str()) {
+		Some("ron") => ron::from_str::<ron::Value>(source).ok().and_then(|value| serde_json::to_value(value).ok()),
+		Some("yaml") | Some("yml") => serde_yaml::from_str(source).ok(),
+		_ => serde_json::from_str(source).ok(),
+	};
+
+	value
+		.as_ref()
+		.and_then(|value| value.get("includes"))
+		.and_then(|includes| includes.as_array())
+		.map(|includes| includes.iter().filter_map(|include| include.as_str().map(str::to_string)).collect())
+		.unwrap_or_default()
+}
+
+/// Collects `file` and the files it includes, so that wasm builds, which
+/// have no file system, can resolve map includes. Files that can't be read
+/// are left out and reported by validation when the game starts.
+fn collect_map_files(file: &str, files: &mut Vec<String>, seen: &mut HashSet<String>) {
+	if !seen.insert(file.to_string()) {
+		return;
+	}
+
+	println!("cargo:rerun-if-changed={}", file);
+
+	let source = match fs::read_to_string(file) {
+		Ok(source) => source,
+		Err(_) => return,
+	};
+
+	files.push(file.to_string());
+
+	let base = Path::new(file).parent().unwrap_or(Path::new(""));
+
+	for include in includes(file, &source) {
+		collect_map_files(&normalize_path(&base.join(include)), files, seen);
+	}
+}
+
+fn main() {
+	let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+	let out_dir = env::var("OUT_DIR").unwrap();
+
+	let mut files = Vec::new();
+	collect_map_files(MAP_FILE, &mut files, &mut HashSet::new());
+
+	let mut bundle = String::from("pub const CONFIG_FILES: &[(&str, &str)] = &[\n");
+
+	for file in files {
+		let full_path = Path::new(&manifest_dir).join(&file);
+
+		bundle.push_str(&format!(
+			"\t({:?}, include_str!({:?})),\n",
+			file,
+			full_path.to_string_lossy()
+		));
+	}
+
+	bundle.push_str("];\n");
+
+	fs::write(Path::new(&out_dir).join("config_bundle.rs"), bundle).unwrap();
+}
@@ -1,32 +1,60 @@
 use bevy::DefaultPlugins;
+use bevy::asset::AssetPlugin;
 use bevy::log::LogPlugin;
 use bevy::prelude::App;
 use bevy::prelude::PluginGroup;
+use bevy::prelude::State;
 
 use crate::cursor::initial_grab_cursor;
 use crate::game_over::GameOverPlugin;
 use crate::game_plugin::GamePlugin;
 use crate::gltf::asset_loading;
+use crate::loading_plugin::LoadingPlugin;
 use crate::map::Map;
+use crate::map_error_plugin::MapErrorPlugin;
+use crate::map_loader::MapChangesReceiver;
+use crate::map_validation::MapDiagnostics;
 use crate::menu_plugin::MenuPlugin;
 use crate::splash_plugin::SplashPlugin;
 use crate::types::GameState;
 
 
-pub fn run_app(map: Map) {
-	App::new()
+pub fn run_app(
+	map: Map,
+	map_diagnostics: MapDiagnostics,
+	map_changes: Option<MapChangesReceiver>
+) {
+	let mut app = App::new();
+
+	if map_diagnostics.has_errors() {
+		// Start straight in the error screen instead of the game
+		app.insert_resource(State(GameState::MapError));
+	}
+
+	if let Some(map_changes) = map_changes {
+		app.insert_resource(map_changes);
+	}
+
+	app
 		.insert_resource(map)
+		.insert_resource(map_diagnostics)
 		.add_plugins(DefaultPlugins.set(LogPlugin {
 			level: bevy::log::Level::INFO,
 			..Default::default()
+		}).set(AssetPlugin {
+			// Re-exported glTF files are reloaded while the game runs
+			watch_for_changes: !cfg!(target_arch = "wasm32"),
+			..Default::default()
 		}))
 		
 		.add_startup_system(initial_grab_cursor)
 		.add_state::<GameState>()	
 		.add_plugin(SplashPlugin)
 		.add_plugin(MenuPlugin)
+		.add_plugin(LoadingPlugin)
 		.add_plugin(GamePlugin)
 		.add_plugin(GameOverPlugin)
+		.add_plugin(MapErrorPlugin)
 		.add_startup_system(asset_loading)
 		// .add_plugin(WorldInspectorPlugin::new())
 		.run();
@@ -0,0 +1,590 @@
+use std::f32::consts::PI;
+use std::mem::discriminant;
+
+use bevy::prelude::*;
+use bevy::utils::FloatOrd;
+use bevy_rapier3d::prelude::*;
+
+use crate::console_plugin::ConsoleCommand;
+use crate::constants::TEXT_COLOR;
+use crate::map::MapBehavior;
+use crate::map::MapBehaviorChild;
+use crate::map::MapBehaviorChildren;
+use crate::map::MapCooldown;
+use crate::map::MapFlee;
+use crate::map::MapHealthBelow;
+use crate::map::MapPlayerInRange;
+use crate::map::MapWait;
+use crate::map::Weapon;
+use crate::map::WeaponType;
+use crate::types::Attacking;
+use crate::types::Behavior;
+use crate::types::BehaviorIntent;
+use crate::types::BehaviorOverlay;
+use crate::types::BehaviorState;
+use crate::types::BulletProperties;
+use crate::types::GameAssets;
+use crate::types::GameEntity;
+use crate::types::GameState;
+use crate::types::Lifetime;
+use crate::types::MeleeHitbox;
+use crate::types::MoveCycle;
+use crate::types::NavigationPath;
+use crate::types::Target;
+use crate::types::TargetPosition;
+use crate::types::NPC;
+
+/// Runs the behavior trees of NPCs and turns what they decide into
+/// movement and attacks
+pub struct BehaviorPlugin;
+
+impl Plugin for BehaviorPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.add_systems((
+				run_behaviors,
+				show_behaviors,
+			).in_set(OnUpdate(GameState::Game)));
+	}
+}
+
+/// Attack range of weapons that don't set one, the melee one matches the
+/// hitbox of the player's attack
+const MELEE_RANGE: f32 = 4.0;
+const RANGED_RANGE: f32 = 20.0;
+
+/// Duration and damage of attacks whose weapon doesn't set them
+const ATTACK_DURATION: f32 = 1.7;
+const BULLET_DAMAGE: f32 = 5.0;
+
+/// How close to home counts as being there
+const HOME_DISTANCE: f32 = 1.0;
+
+/// How far a followed target moves before the path to it is planned again
+const RETARGET_DISTANCE: f32 = 1.0;
+
+/// Result of ticking a node
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Status {
+	Success,
+	Failure,
+	Running,
+}
+
+fn succeeds(condition: bool) -> Status {
+	match condition {
+		true => Status::Success,
+		false => Status::Failure,
+	}
+}
+
+/// What an NPC knows when its tree is ticked
+struct Senses {
+	position: Vec3,
+	home: Vec3,
+	/// Fraction of the maximum health left
+	health: f32,
+	/// Closest player
+	player: Option<Vec3>,
+	attack_range: f32,
+	/// Whether the entity has a `move_cycle` to patrol
+	patrols: bool,
+	time: f32,
+}
+
+impl Senses {
+	fn player_distance(&self) -> Option<(Vec3, f32)> {
+		self.player.map(|player| (player, player.distance(self.position)))
+	}
+}
+
+fn attack_range(weapon: Option<&Weapon>) -> f32 {
+	match weapon {
+		Some(Weapon { range: Some(range), .. }) => *range,
+		Some(Weapon { weapon_type: WeaponType::Ranged, .. }) => RANGED_RANGE,
+		_ => MELEE_RANGE,
+	}
+}
+
+fn children(node: &MapBehavior) -> &[MapBehavior] {
+	match node {
+		MapBehavior::Sequence(MapBehaviorChildren { children }) | MapBehavior::Selector(MapBehaviorChildren { children }) => children,
+		MapBehavior::Invert(MapBehaviorChild { child })
+		| MapBehavior::AlwaysSucceed(MapBehaviorChild { child })
+		| MapBehavior::Cooldown(MapCooldown { child, .. }) => {
+			std::slice::from_ref(child.as_ref())
+		},
+		_ => &[],
+	}
+}
+
+/// Number of nodes in the tree below and including `node`
+fn subtree_size(node: &MapBehavior) -> usize {
+	1 + children(node).iter().map(subtree_size).sum::<usize>()
+}
+
+/// The node with pre-order id `id` in the tree below `node`
+fn find_node(node: &MapBehavior, id: usize) -> Option<&MapBehavior> {
+	if id == 0 {
+		return Some(node);
+	}
+
+	let mut offset = 1;
+
+	for child in children(node) {
+		let size = subtree_size(child);
+
+		if id < offset + size {
+			return find_node(child, id - offset);
+		}
+
+		offset += size;
+	}
+
+	None
+}
+
+fn label(node: &MapBehavior) -> String {
+	match node {
+		MapBehavior::Sequence(_) => "Sequence".to_string(),
+		MapBehavior::Selector(_) => "Selector".to_string(),
+		MapBehavior::Invert(_) => "Invert".to_string(),
+		MapBehavior::AlwaysSucceed(_) => "AlwaysSucceed".to_string(),
+		MapBehavior::Cooldown(MapCooldown { seconds, .. }) => format!("Cooldown {}s", seconds),
+		MapBehavior::PlayerInRange(MapPlayerInRange { range }) => format!("PlayerInRange {}", range),
+		MapBehavior::HealthBelow(MapHealthBelow { fraction }) => format!("HealthBelow {}", fraction),
+		MapBehavior::Patrol => "Patrol".to_string(),
+		MapBehavior::Chase => "Chase".to_string(),
+		MapBehavior::Attack => "Attack".to_string(),
+		MapBehavior::Flee(MapFlee { distance }) => format!("Flee {}", distance),
+		MapBehavior::ReturnHome => "ReturnHome".to_string(),
+		MapBehavior::Wait(MapWait { seconds }) => format!("Wait {}s", seconds),
+	}
+}
+
+/// Ticks `node`, whose pre-order id is `id`, and the nodes below it
+fn tick(node: &MapBehavior, id: usize, senses: &Senses, state: &mut BehaviorState) -> Status {
+	let depth = state.active.len();
+	state.active.push(id);
+
+	match node {
+		MapBehavior::Sequence(MapBehaviorChildren { children }) | MapBehavior::Selector(MapBehaviorChildren { children }) => {
+			// A sequence goes on while its children succeed, a selector while they fail
+			let next = match node {
+				MapBehavior::Sequence(_) => Status::Success,
+				_ => Status::Failure,
+			};
+
+			let mut child_id = id + 1;
+
+			for child in children {
+				state.active.truncate(depth + 1);
+
+				let status = tick(child, child_id, senses, state);
+
+				if status != next {
+					return status;
+				}
+
+				child_id += subtree_size(child);
+			}
+
+			next
+		},
+		MapBehavior::Invert(MapBehaviorChild { child }) => match tick(child, id + 1, senses, state) {
+			Status::Success => Status::Failure,
+			Status::Failure => Status::Success,
+			Status::Running => Status::Running,
+		},
+		MapBehavior::AlwaysSucceed(MapBehaviorChild { child }) => match tick(child, id + 1, senses, state) {
+			Status::Running => Status::Running,
+			_ => Status::Success,
+		},
+		MapBehavior::Cooldown(MapCooldown { seconds, child }) => {
+			if matches!(state.cooldowns.get(&id), Some(ready) if senses.time < *ready) {
+				return Status::Failure;
+			}
+
+			let status = tick(child, id + 1, senses, state);
+
+			if status == Status::Success {
+				state.cooldowns.insert(id, senses.time + seconds);
+			}
+
+			status
+		},
+		MapBehavior::PlayerInRange(MapPlayerInRange { range }) => {
+			succeeds(matches!(senses.player_distance(), Some((_, distance)) if distance <= *range))
+		},
+		MapBehavior::HealthBelow(MapHealthBelow { fraction }) => succeeds(senses.health < *fraction),
+		MapBehavior::Patrol if senses.patrols => {
+			state.intent = BehaviorIntent::Patrol;
+
+			Status::Running
+		},
+		MapBehavior::Patrol => Status::Failure,
+		MapBehavior::Chase => match senses.player_distance() {
+			None => Status::Failure,
+			Some((_, distance)) if distance <= senses.attack_range => Status::Success,
+			Some((player, _)) => {
+				state.intent = BehaviorIntent::MoveTo { target: player, run: true };
+
+				Status::Running
+			},
+		},
+		MapBehavior::Attack => match senses.player_distance() {
+			Some((player, distance)) if distance <= senses.attack_range => {
+				state.intent = BehaviorIntent::Attack { target: player };
+
+				Status::Running
+			},
+			_ => Status::Failure,
+		},
+		MapBehavior::Flee(MapFlee { distance: flee_distance }) => match senses.player_distance() {
+			None => Status::Failure,
+			Some((_, distance)) if distance >= *flee_distance => Status::Success,
+			Some((player, _)) => {
+				let away = Vec3::new(senses.position.x - player.x, 0.0, senses.position.z - player.z);
+				let away = match away.normalize_or_zero() {
+					Vec3::ZERO => Vec3::X,
+					away => away,
+				};
+
+				state.intent = BehaviorIntent::MoveTo {
+					target: player + away * (flee_distance + RETARGET_DISTANCE),
+					run: true,
+				};
+
+				Status::Running
+			},
+		},
+		MapBehavior::ReturnHome if senses.position.distance(senses.home) <= HOME_DISTANCE => Status::Success,
+		MapBehavior::ReturnHome => {
+			state.intent = BehaviorIntent::MoveTo { target: senses.home, run: false };
+
+			Status::Running
+		},
+		MapBehavior::Wait(MapWait { seconds }) => {
+			let started = *state.started.entry(id).or_insert(senses.time);
+
+			if senses.time - started >= *seconds {
+				state.started.remove(&id);
+
+				Status::Success
+			} else {
+				Status::Running
+			}
+		},
+	}
+}
+
+/// Ticks the whole tree, leaving what it decided in `state.intent`
+fn think(tree: &MapBehavior, senses: &Senses, state: &mut BehaviorState) -> Status {
+	state.active.clear();
+	state.intent = BehaviorIntent::Idle;
+
+	let status = tick(tree, 0, senses, state);
+
+	// Waits that were interrupted start over when they are reached again
+	let BehaviorState { active, started, .. } = state;
+	started.retain(|id, _| active.contains(id));
+
+	status
+}
+
+/// Starts an attack on `target` with `weapon`, like the player's attacks
+fn attack(
+	commands: &mut Commands,
+	entity: Entity,
+	game_entity: &mut GameEntity,
+	weapon: Option<&Weapon>,
+	position: Vec3,
+	target: Vec3,
+	bullet_assets: &GameAssets,
+) {
+	game_entity.attacking = true;
+
+	commands.entity(entity).insert(Attacking {
+		timer: Timer::from_seconds(weapon.and_then(|w| w.duration).unwrap_or(ATTACK_DURATION), TimerMode::Once),
+	});
+
+	match weapon {
+		Some(Weapon { weapon_type: WeaponType::Ranged, damage, .. }) => {
+			let origin = position + Vec3::Y * 1.5;
+			let direction = (target - origin).normalize_or_zero();
+
+			commands
+				.spawn(SceneBundle {
+					scene: bullet_assets.bullet_scene.clone(),
+					transform: Transform::from_translation(origin + direction),
+					..default()
+				})
+				.insert(Lifetime {
+					timer: Timer::from_seconds(30.0, TimerMode::Once),
+				})
+				.insert(Collider::ball(0.1))
+				.insert(RigidBody::Dynamic)
+				.insert(BulletProperties {
+					damage: damage.unwrap_or(BULLET_DAMAGE),
+				})
+				.insert(Name::new("Bullet"))
+				.insert(ActiveEvents::COLLISION_EVENTS)
+				.insert(Velocity {
+					linvel: direction * 50.0,
+					..default()
+				});
+		},
+		_ => {
+			commands.entity(entity).with_children(|parent| {
+				parent.spawn((
+					MeleeHitbox {
+						delay: 0.6,
+						dur: 1.0,
+						radius: attack_range(weapon),
+						start_angle: 310.0,
+						end_angle: 130.0,
+					},
+					TransformBundle::from_transform(Transform::from_xyz(0.0, 1.5, 0.0)),
+				));
+			});
+		},
+	}
+}
+
+/// Ticks the tree of every NPC and carries out its intent: paths for
+/// `npc.rs` to follow, the next point of the move cycle, or attacks
+fn run_behaviors(
+	mut commands: Commands,
+	time: Res<Time>,
+	bullet_assets: Res<GameAssets>,
+	players: Query<&GlobalTransform, With<Target>>,
+	mut npcs: Query<(
+		Entity,
+		&mut Behavior,
+		&mut GameEntity,
+		&mut Transform,
+		Option<&mut MoveCycle>,
+		Option<&TargetPosition>,
+		Option<&mut Velocity>,
+	), With<NPC>>,
+) {
+	for (entity, mut behavior, mut game_entity, mut transform, move_cycle, target_position, velocity) in npcs.iter_mut() {
+		let position = transform.translation;
+		let weapon = game_entity.weapons.get(game_entity.current_weapon).cloned();
+
+		let senses = Senses {
+			position,
+			home: behavior.home,
+			health: game_entity.curr_health / game_entity.max_health,
+			player: players
+				.iter()
+				.map(|player| player.translation())
+				.min_by_key(|player| FloatOrd(player.distance(position))),
+			attack_range: attack_range(weapon.as_ref()),
+			patrols: move_cycle.is_some(),
+			time: time.elapsed_seconds(),
+		};
+
+		let previous = behavior.state.intent;
+
+		let Behavior { tree, state, .. } = &mut *behavior;
+		think(tree, &senses, state);
+
+		let intent = behavior.state.intent;
+		let mut target_position = target_position;
+
+		// Whatever the entity was walking to is given up with a new kind of intent
+		if discriminant(&previous) != discriminant(&intent) {
+			log::info!("[{}] behavior {:?} -> {:?}", game_entity.entity_id, previous, intent);
+
+			commands.entity(entity).remove::<(TargetPosition, NavigationPath)>();
+			target_position = None;
+
+			game_entity.move_intent.move_forward = false;
+
+			if let Some(mut velocity) = velocity {
+				velocity.linvel.x = 0.0;
+				velocity.linvel.z = 0.0;
+			}
+		}
+
+		match intent {
+			BehaviorIntent::Idle => {},
+			BehaviorIntent::MoveTo { target, run } => {
+				if game_entity.running != run {
+					game_entity.running = run;
+				}
+
+				let moved = target_position
+					.iter()
+					.all(|t| Vec3::new(t.x, t.y, t.z).distance(target) > RETARGET_DISTANCE);
+
+				if moved {
+					commands.entity(entity).insert(TargetPosition {
+						x: target.x,
+						y: target.y,
+						z: target.z,
+					});
+				}
+			},
+			BehaviorIntent::Patrol => {
+				if game_entity.running {
+					game_entity.running = false;
+				}
+
+				if let (Some(mut cycle), None) = (move_cycle, target_position) {
+					let point = cycle.get_next();
+
+					log::info!("[{}] Moving to {:?}", game_entity.entity_id, point);
+
+					commands.entity(entity).insert(TargetPosition {
+						x: point.x as f32,
+						y: point.y as f32,
+						z: point.z as f32,
+					});
+				}
+			},
+			BehaviorIntent::Attack { target } => {
+				let direction = Vec3::new(target.x - position.x, 0.0, target.z - position.z).normalize_or_zero();
+
+				if direction != Vec3::ZERO {
+					game_entity.yaw = direction.x.atan2(-direction.z);
+					transform.rotation = Quat::from_rotation_y(2.0 * PI - game_entity.yaw);
+				}
+
+				if !game_entity.attacking {
+					attack(&mut commands, entity, &mut game_entity, weapon.as_ref(), position, target, &bullet_assets);
+				}
+			},
+		}
+	}
+}
+
+/// Toggles a list of the active behavior nodes of every NPC with the
+/// `behavior` console command
+fn show_behaviors(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	mut console_commands: EventReader<ConsoleCommand>,
+	mut visible: Local<bool>,
+	npcs: Query<(&GameEntity, &Behavior), With<NPC>>,
+	mut overlays: Query<(Entity, &mut Text), With<BehaviorOverlay>>,
+) {
+	if console_commands.iter().filter(|command| command.name == "behavior").count() % 2 == 1 {
+		*visible = !*visible;
+		log::info!("behavior overlay {}", if *visible { "on" } else { "off" });
+	}
+
+	if !*visible {
+		for (overlay, _) in overlays.iter() {
+			commands.entity(overlay).despawn_recursive();
+		}
+
+		return;
+	}
+
+	let mut lines: Vec<String> = npcs.iter().map(|(game_entity, behavior)| {
+		let labels: Vec<String> = behavior.state.active
+			.iter()
+			.filter_map(|id| find_node(&behavior.tree, *id))
+			.map(label)
+			.collect();
+
+		format!("{}: {}", game_entity.entity_id, labels.join(" > "))
+	}).collect();
+
+	lines.sort();
+
+	let value = lines.join("\n");
+
+	match overlays.get_single_mut() {
+		Ok((_, mut text)) => {
+			if text.sections[0].value != value {
+				text.sections[0].value = value;
+			}
+		},
+		Err(_) => {
+			commands.spawn((
+				TextBundle::from_section(
+					value,
+					TextStyle {
+						font: asset_server.load("FiraSans-Bold.ttf"),
+						font_size: 18.0,
+						color: TEXT_COLOR,
+					},
+				).with_style(Style {
+					position_type: PositionType::Absolute,
+					position: UiRect {
+						top: Val::Px(50.0),
+						left: Val::Px(10.0),
+						..default()
+					},
+					..default()
+				}),
+				BehaviorOverlay,
+			));
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn senses(player: Option<Vec3>, time: f32) -> Senses {
+		Senses {
+			position: Vec3::ZERO,
+			home: Vec3::ZERO,
+			health: 1.0,
+			player,
+			attack_range: 2.0,
+			patrols: true,
+			time,
+		}
+	}
+
+	#[test]
+	fn chases_players_in_range_and_patrols_otherwise() {
+		let tree: MapBehavior = serde_json::from_str(r#"{ "type": "Selector", "children": [
+			{ "type": "Sequence", "children": [
+				{ "type": "PlayerInRange", "range": 10 },
+				{ "type": "Selector", "children": [{ "type": "Attack" }, { "type": "Chase" }] }
+			] },
+			{ "type": "Patrol" }
+		] }"#).unwrap();
+
+		let mut state = BehaviorState::default();
+
+		let player = Vec3::new(0.0, 0.0, 5.0);
+		assert_eq!(think(&tree, &senses(Some(player), 0.0), &mut state), Status::Running);
+		assert_eq!(state.intent, BehaviorIntent::MoveTo { target: player, run: true });
+		assert_eq!(state.active, vec![0, 1, 3, 5]);
+
+		let player = Vec3::new(0.0, 0.0, 1.0);
+		think(&tree, &senses(Some(player), 0.0), &mut state);
+		assert_eq!(state.intent, BehaviorIntent::Attack { target: player });
+		assert_eq!(state.active, vec![0, 1, 3, 4]);
+
+		think(&tree, &senses(Some(Vec3::new(0.0, 0.0, 20.0)), 0.0), &mut state);
+		assert_eq!(state.intent, BehaviorIntent::Patrol);
+		assert_eq!(state.active, vec![0, 6]);
+		assert_eq!(find_node(&tree, 6), Some(&MapBehavior::Patrol));
+	}
+
+	#[test]
+	fn cools_down_after_the_child_succeeded() {
+		let tree = MapBehavior::Cooldown(MapCooldown {
+			seconds: 2.0,
+			child: Box::new(MapBehavior::Wait(MapWait { seconds: 1.0 })),
+		});
+
+		let mut state = BehaviorState::default();
+
+		assert_eq!(think(&tree, &senses(None, 0.0), &mut state), Status::Running);
+		assert_eq!(think(&tree, &senses(None, 1.0), &mut state), Status::Success);
+		assert_eq!(think(&tree, &senses(None, 2.0), &mut state), Status::Failure);
+		// The wait starts over once the cooldown is done
+		assert_eq!(think(&tree, &senses(None, 3.0), &mut state), Status::Running);
+		assert_eq!(think(&tree, &senses(None, 3.5), &mut state), Status::Running);
+	}
+}
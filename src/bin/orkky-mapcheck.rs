@@ -0,0 +1,153 @@
+//! Validates map files without starting the game.
+//!
+//! Usage: orkky-mapcheck [--assets <dir>] [--deny-warnings] [--migrate] <map>...
+//!
+//! Prints a JSON report to stdout and exits with a non-zero status when any
+//! map has errors (or warnings, with `--deny-warnings`). With `--migrate`
+//! the given files are first rewritten in the latest map format.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use orkky_pely::map_format::MapFormat;
+use orkky_pely::map_migration::migrate;
+use orkky_pely::map_migration::MAP_VERSION;
+use orkky_pely::map_validation::load_and_validate;
+use orkky_pely::map_validation::MapDiagnostic;
+use orkky_pely::map_validation::Severity;
+use serde::Serialize;
+
+const USAGE: &str = "usage: orkky-mapcheck [--assets <dir>] [--deny-warnings] [--migrate] <map>...";
+
+#[derive(Serialize)]
+struct FileReport {
+	file: String,
+	valid: bool,
+	diagnostics: Vec<MapDiagnostic>,
+}
+
+#[derive(Serialize)]
+struct Report {
+	errors: usize,
+	warnings: usize,
+	files: Vec<FileReport>,
+}
+
+struct Args {
+	assets_dir: PathBuf,
+	deny_warnings: bool,
+	migrate: bool,
+	files: Vec<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+	let mut args = Args {
+		assets_dir: PathBuf::from("assets"),
+		deny_warnings: false,
+		migrate: false,
+		files: Vec::new(),
+	};
+
+	let mut iter = std::env::args().skip(1);
+
+	while let Some(arg) = iter.next() {
+		match arg.as_str() {
+			"--assets" => {
+				let dir = iter.next().ok_or("--assets needs a directory")?;
+				args.assets_dir = PathBuf::from(dir);
+			},
+			"--deny-warnings" => {
+				args.deny_warnings = true;
+			},
+			"--migrate" => {
+				args.migrate = true;
+			},
+			"-h" | "--help" => {
+				return Err(USAGE.to_string());
+			},
+			_ if arg.starts_with("--") => {
+				return Err(format!("unknown option {}\n{}", arg, USAGE));
+			},
+			_ => {
+				args.files.push(arg);
+			}
+		}
+	}
+
+	if args.files.is_empty() {
+		return Err(USAGE.to_string());
+	}
+
+	Ok(args)
+}
+
+/// Rewrites a map file in the latest format. Files that already are in the
+/// latest format are left untouched.
+fn migrate_file(file: &str) -> anyhow::Result<()> {
+	let format = MapFormat::from_path(file);
+	let source = std::fs::read_to_string(file)?;
+	let mut map = format.parse(&source)?;
+
+	let version = migrate(&mut map)?;
+
+	if version == MAP_VERSION {
+		return Ok(());
+	}
+
+	std::fs::write(file, format.serialize(&map)?)?;
+
+	eprintln!("{}: migrated from version {} to {}", file, version, MAP_VERSION);
+
+	Ok(())
+}
+
+fn main() -> ExitCode {
+	let args = match parse_args() {
+		Ok(args) => args,
+		Err(err) => {
+			eprintln!("{}", err);
+
+			return ExitCode::from(2);
+		}
+	};
+
+	let mut report = Report {
+		errors: 0,
+		warnings: 0,
+		files: Vec::new(),
+	};
+
+	for file in &args.files {
+		if args.migrate {
+			// Errors are reported by the validation below
+			if let Err(err) = migrate_file(file) {
+				eprintln!("{}: could not migrate: {}", file, err);
+			}
+		}
+
+		let diagnostics = load_and_validate(file, Some(&args.assets_dir)).diagnostics;
+
+		let errors = diagnostics.diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+		let warnings = diagnostics.diagnostics.len() - errors;
+
+		for diagnostic in &diagnostics.diagnostics {
+			eprintln!("{}", diagnostic);
+		}
+
+		report.errors += errors;
+		report.warnings += warnings;
+		report.files.push(FileReport {
+			file: file.clone(),
+			valid: errors == 0,
+			diagnostics: diagnostics.diagnostics,
+		});
+	}
+
+	println!("{}", serde_json::to_string_pretty(&report).unwrap());
+
+	if report.errors > 0 || (args.deny_warnings && report.warnings > 0) {
+		ExitCode::FAILURE
+	} else {
+		ExitCode::SUCCESS
+	}
+}
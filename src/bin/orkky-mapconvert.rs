@@ -0,0 +1,47 @@
+//! Converts a map file between JSON, RON and YAML.
+//!
+//! Usage: orkky-mapconvert <input> <output>
+//!
+//! The formats are chosen by the file extensions. The map is migrated to the
+//! latest format version on the way. Includes are not followed, convert each
+//! file of a split map on its own.
+
+use std::process::ExitCode;
+
+use orkky_pely::map::Map;
+use orkky_pely::map_format::MapFormat;
+
+const USAGE: &str = "usage: orkky-mapconvert <input> <output>";
+
+fn convert(input: &str, output: &str) -> anyhow::Result<()> {
+	let source = std::fs::read_to_string(input)?;
+	let map = Map::parse_as(&source, MapFormat::from_path(input))?;
+
+	map.save(output)
+}
+
+fn main() -> ExitCode {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+
+	let (input, output) = match args.as_slice() {
+		[input, output] => (input, output),
+		_ => {
+			eprintln!("{}", USAGE);
+
+			return ExitCode::from(2);
+		}
+	};
+
+	match convert(input, output) {
+		Ok(_) => {
+			eprintln!("wrote {}", output);
+
+			ExitCode::SUCCESS
+		},
+		Err(err) => {
+			eprintln!("could not convert {}: {}", input, err);
+
+			ExitCode::FAILURE
+		}
+	}
+}
@@ -0,0 +1,44 @@
+//! Writes the JSON Schema of the map format.
+//!
+//! Usage: orkky-mapschema [output]
+//!
+//! The schema is written to `config/map.schema.json` unless another path is
+//! given, or to stdout with `-`.
+
+use std::process::ExitCode;
+
+use orkky_pely::map_schema::map_schema_json;
+
+const DEFAULT_OUTPUT: &str = "config/map.schema.json";
+
+fn main() -> ExitCode {
+	let output = std::env::args().nth(1).unwrap_or(DEFAULT_OUTPUT.to_string());
+
+	let schema = match map_schema_json() {
+		Ok(schema) => schema,
+		Err(err) => {
+			eprintln!("could not generate schema: {}", err);
+
+			return ExitCode::FAILURE;
+		}
+	};
+
+	if output == "-" {
+		print!("{}", schema);
+
+		return ExitCode::SUCCESS;
+	}
+
+	match std::fs::write(&output, schema) {
+		Ok(_) => {
+			eprintln!("wrote {}", output);
+
+			ExitCode::SUCCESS
+		},
+		Err(err) => {
+			eprintln!("could not write {}: {}", output, err);
+
+			ExitCode::FAILURE
+		}
+	}
+}
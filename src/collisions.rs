@@ -1,94 +1,346 @@
-use bevy::gltf::Gltf;
-use bevy::gltf::GltfMesh;
-use bevy::gltf::GltfNode;
+use std::sync::Arc;
+
 use bevy::prelude::*;
 use bevy::time::Stopwatch;
 use bevy::utils::HashMap;
 use bevy_rapier3d::prelude::*;
 
+use crate::console_plugin::ConsoleCommand;
+use crate::map::MapCollisionMesh;
+use crate::map::MapCollisionShape;
+use crate::navmesh::mesh_triangles;
+use crate::types::AddBoundingCollider;
 use crate::types::AddCollidingMesh;
+use crate::types::BoundsOverlay;
 use crate::types::BulletProperties;
+use crate::types::CollisionMeshCache;
+use crate::types::ComputedBounds;
 use crate::types::Health;
 use crate::types::MeleeHitbox;
+use crate::types::NavigationSource;
 use crate::types::Target;
 
+/// Matches a glTF name against a pattern where `*` stands for any
+/// characters and `?` for a single one
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+	let pattern: Vec<char> = pattern.chars().collect();
+	let name: Vec<char> = name.chars().collect();
+
+	let mut p = 0;
+	let mut n = 0;
+	// Pattern position after the last `*` and the name position it matched up to
+	let mut star: Option<(usize, usize)> = None;
+
+	while n < name.len() {
+		match pattern.get(p) {
+			Some('*') => {
+				star = Some((p + 1, n));
+				p += 1;
+			},
+			Some(c) if *c == '?' || *c == name[n] => {
+				p += 1;
+				n += 1;
+			},
+			_ => match star {
+				Some((star_p, star_n)) => {
+					star = Some((star_p, star_n + 1));
+					p = star_p;
+					n = star_n + 1;
+				},
+				None => return false,
+			}
+		}
+	}
+
+	pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Colliders for the meshes of a glTF scene that match the settings, with
+/// their transforms relative to the scene and the mesh triangles. Meshes are
+/// matched by their own name or the name of any node above them. None while
+/// meshes are loading.
+fn scene_colliders(
+	world: &World,
+	meshes: &Assets<Mesh>,
+	collision_mesh: &MapCollisionMesh,
+) -> Option<Vec<(Transform, Collider, Arc<Vec<[Vec3; 3]>>)>> {
+	let patterns = collision_mesh.meshes.clone().unwrap_or_else(|| vec!["Landscape".to_string()]);
+
+	let shape = match collision_mesh.shape.unwrap_or(MapCollisionShape::TriMesh) {
+		MapCollisionShape::TriMesh => ComputedColliderShape::TriMesh,
+		MapCollisionShape::ConvexHull => ComputedColliderShape::ConvexHull,
+		MapCollisionShape::ConvexDecomposition => ComputedColliderShape::ConvexDecomposition(VHACDParameters::default()),
+	};
+
+	let mut colliders = Vec::new();
+
+	for entity_ref in world.iter_entities() {
+		let mesh_handle = match entity_ref.get::<Handle<Mesh>>() {
+			Some(mesh_handle) => mesh_handle,
+			None => continue,
+		};
+
+		let mut matched = false;
+		let mut transform = Transform::IDENTITY;
+		let mut current = Some(entity_ref.id());
+
+		// Up the node hierarchy to the root of the scene
+		while let Some(entity) = current {
+			let node = world.entity(entity);
+
+			if let Some(name) = node.get::<Name>() {
+				matched |= patterns.iter().any(|pattern| glob_match(pattern, name.as_str()));
+			}
+
+			if let Some(local) = node.get::<Transform>() {
+				transform = *local * transform;
+			}
+
+			current = node.get::<Parent>().map(|parent| parent.get());
+		}
+
+		if !matched {
+			continue;
+		}
+
+		let mesh = meshes.get(mesh_handle)?;
+
+		match Collider::from_bevy_mesh(mesh, &shape) {
+			Some(collider) => colliders.push((transform, collider, Arc::new(mesh_triangles(mesh)))),
+			None => log::warn!("mesh {:?} can't be turned into a collider", entity_ref.get::<Name>()),
+		}
+	}
+
+	Some(colliders)
+}
+
+/// Builds colliders from the meshes of spawned glTF scenes. They are cached
+/// per scene and settings, so ten castles build their colliders once.
 pub fn add_collisions(
-	assets_gltf: Res<Assets<Gltf>>,
-	assets_gltf_mesh: Res<Assets<GltfMesh>>,
-	assets_gltf_nodes: Res<Assets<GltfNode>>,
-	assets_mesh: Res<Assets<Mesh>>,
+	scenes: Res<Assets<Scene>>,
+	meshes: Res<Assets<Mesh>>,
+	mut cache: ResMut<CollisionMeshCache>,
 	query: Query<(Entity, &AddCollidingMesh)>,
 	mut commands: Commands,
 ) {
-	for (entity, add_collider_mesh) in query.iter() {
-		log::info!("adding collision mesh for {:?}", entity);
+	for (entity, add_colliding_mesh) in query.iter() {
+		let key = (add_colliding_mesh.scene.id(), add_colliding_mesh.collision_mesh.clone());
 
-		let pack = match assets_gltf.get(&add_collider_mesh.glft) {
-			Some(pack) => {
-				pack
-			},
-			None => continue,
-		};
+		if !cache.colliders.contains_key(&key) {
+			let scene = match scenes.get(&add_colliding_mesh.scene) {
+				Some(scene) => scene,
+				None => continue,
+			};
+
+			let colliders = match scene_colliders(&scene.world, &meshes, &add_colliding_mesh.collision_mesh) {
+				Some(colliders) => colliders,
+				None => continue,
+			};
+
+			log::info!("built {} colliders for {:?}", colliders.len(), add_colliding_mesh.collision_mesh);
+
+			if colliders.is_empty() {
+				log::warn!("no meshes match collision mesh {:?}", add_colliding_mesh.collision_mesh);
+			}
+
+			cache.colliders.insert(key.clone(), colliders);
+		}
 
 		let mut entity_commands = commands.entity(entity);
 
-		let named_meshes = &pack.named_meshes;
-		let landscape = match named_meshes.get("Landscape") {
-			Some(mesh) => mesh,
+		entity_commands.with_children(|parent| {
+			for (transform, collider, triangles) in cache.colliders[&key].iter() {
+				parent.spawn((
+					collider.clone(),
+					TransformBundle::from_transform(add_colliding_mesh.scene_transform * *transform),
+					NavigationSource {
+						triangles: triangles.clone(),
+					},
+				));
+			}
+		});
+
+		entity_commands.remove::<AddCollidingMesh>();
+	}
+}
+
+/// Axis aligned bounds of a box after transforming it
+fn transform_bounds(min: Vec3, max: Vec3, transform: &Transform) -> (Vec3, Vec3) {
+	let mut bounds = (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY));
+
+	for corner in 0..8 {
+		let point = Vec3::new(
+			if corner & 1 == 0 { min.x } else { max.x },
+			if corner & 2 == 0 { min.y } else { max.y },
+			if corner & 4 == 0 { min.z } else { max.z },
+		);
+		let point = transform.transform_point(point);
+
+		bounds = (bounds.0.min(point), bounds.1.max(point));
+	}
+
+	bounds
+}
+
+/// Bounds of all meshes of a glTF scene in scene space. None while meshes
+/// are loading, Some(None) if the scene has no meshes.
+fn scene_bounds(world: &World, meshes: &Assets<Mesh>) -> Option<Option<(Vec3, Vec3)>> {
+	let mut bounds: Option<(Vec3, Vec3)> = None;
+
+	for entity_ref in world.iter_entities() {
+		let mesh_handle = match entity_ref.get::<Handle<Mesh>>() {
+			Some(mesh_handle) => mesh_handle,
 			None => continue,
 		};
 
-		for node in &pack.nodes {
-			let node = match assets_gltf_nodes.get(node) {
-				Some(n) => n,
-				None => continue,
-			};	
+		let aabb = match meshes.get(mesh_handle)?.compute_aabb() {
+			Some(aabb) => aabb,
+			None => continue,
+		};
 
-			log::info!("found node {:?}", node.transform);
+		let mut transform = Transform::IDENTITY;
+		let mut current = Some(entity_ref.id());
 
-			let mesh = match &node.mesh {
-				Some(mesh) => mesh,
-				None => continue,
-			};
+		while let Some(entity) = current {
+			let node = world.entity(entity);
 
-			if landscape.id() != mesh.id() {
-				continue;
+			if let Some(local) = node.get::<Transform>() {
+				transform = *local * transform;
 			}
 
-			let mesh = match assets_gltf_mesh.get(mesh) {
-				Some(m) => m,
+			current = node.get::<Parent>().map(|parent| parent.get());
+		}
+
+		let (min, max) = transform_bounds(aabb.min().into(), aabb.max().into(), &transform);
+
+		bounds = Some(match bounds {
+			Some((bounds_min, bounds_max)) => (bounds_min.min(min), bounds_max.max(max)),
+			None => (min, max),
+		});
+	}
+
+	Some(bounds)
+}
+
+/// Grows bounds by `padding` world units on every side of an entity with
+/// the given scale
+fn pad_bounds(min: Vec3, max: Vec3, padding: f32, scale: Vec3) -> (Vec3, Vec3) {
+	let padding = Vec3::splat(padding) / scale.abs().max(Vec3::splat(f32::EPSILON));
+
+	(min - padding, max + padding)
+}
+
+/// Fits cuboid colliders to the meshes of spawned glTF scenes. The collider
+/// is on the entity itself, so rapier scales it with the entity.
+pub fn add_bounding_colliders(
+	scenes: Res<Assets<Scene>>,
+	meshes: Res<Assets<Mesh>>,
+	mut cache: ResMut<CollisionMeshCache>,
+	query: Query<(Entity, &AddBoundingCollider, &Transform)>,
+	mut commands: Commands,
+) {
+	for (entity, add_bounding_collider, transform) in query.iter() {
+		let scene_id = add_bounding_collider.scene.id();
+
+		if !cache.bounds.contains_key(&scene_id) {
+			let scene = match scenes.get(&add_bounding_collider.scene) {
+				Some(scene) => scene,
+				None => continue,
+			};
+
+			let bounds = match scene_bounds(&scene.world, &meshes) {
+				Some(bounds) => bounds,
 				None => continue,
 			};
 
-			log::info!("found mesh {:?}", mesh);
+			log::info!("computed scene bounds {:?}", bounds);
 
-			for primite in &mesh.primitives {
-				let mesh = assets_mesh.get(&primite.mesh).unwrap();
+			cache.bounds.insert(scene_id, bounds);
+		}
 
-				let collider = Collider::from_bevy_mesh(mesh, &ComputedColliderShape::TriMesh);
+		let mut entity_commands = commands.entity(entity);
+		entity_commands.remove::<AddBoundingCollider>();
 
-				match collider {
-					Some(collider) => {
-						log::info!("found collider {:?}", collider);
+		let (min, max) = match cache.bounds[&scene_id] {
+			Some((min, max)) => transform_bounds(min, max, &add_bounding_collider.scene_transform),
+			None => {
+				log::warn!("no meshes to fit a bounding box collider to");
 
-						entity_commands.with_children(|parent| {
-							parent.spawn((
-								collider,
-								TransformBundle {
-									local: node.transform,
-									..Default::default()
-								}
-							));
-						});
-					},
-					None => {
-						log::info!("mesh collider is invalid");
-					}
-				}
+				continue;
 			}
+		};
+
+		let (min, max) = pad_bounds(min, max, add_bounding_collider.padding, transform.scale);
+		let half_extents = (max - min) / 2.0;
+
+		entity_commands.insert((
+			Collider::compound(vec![(
+				(min + max) / 2.0,
+				Quat::IDENTITY,
+				Collider::cuboid(half_extents.x, half_extents.y, half_extents.z),
+			)]),
+			ComputedBounds { min, max },
+		));
+	}
+}
+
+/// Toggles translucent boxes showing the computed `AABB` colliders with the
+/// `bounds` console command
+pub fn show_bounds(
+	mut commands: Commands,
+	mut console_commands: EventReader<ConsoleCommand>,
+	mut visible: Local<bool>,
+	mut overlay_material: Local<Option<Handle<StandardMaterial>>>,
+	mut meshes: ResMut<Assets<Mesh>>,
+	mut materials: ResMut<Assets<StandardMaterial>>,
+	bounds: Query<(Entity, Ref<ComputedBounds>)>,
+	overlays: Query<(Entity, &Parent), With<BoundsOverlay>>,
+) {
+	let toggled = console_commands.iter().filter(|command| command.name == "bounds").count() % 2 == 1;
+
+	if toggled {
+		*visible = !*visible;
+		log::info!("bounds overlay {}", if *visible { "on" } else { "off" });
+	}
+
+	// Overlays of entities whose bounds changed or went away are rebuilt
+	for (overlay, parent) in overlays.iter() {
+		let stale = match bounds.get(parent.get()) {
+			Ok((_, computed_bounds)) => computed_bounds.is_changed(),
+			Err(_) => true,
+		};
+
+		if !*visible || stale {
+			commands.entity(overlay).despawn_recursive();
 		}
+	}
 
-		commands.entity(entity).remove::<AddCollidingMesh>();
+	if !*visible {
+		return;
+	}
+
+	let material = overlay_material.get_or_insert_with(|| materials.add(StandardMaterial {
+		base_color: Color::rgba(1.0, 0.2, 0.8, 0.25),
+		alpha_mode: AlphaMode::Blend,
+		unlit: true,
+		..default()
+	})).clone();
+
+	for (entity, computed_bounds) in bounds.iter() {
+		if !toggled && !computed_bounds.is_changed() {
+			continue;
+		}
+
+		let overlay = commands.spawn((
+			BoundsOverlay,
+			PbrBundle {
+				mesh: meshes.add(Mesh::from(shape::Box::from_corners(computed_bounds.min, computed_bounds.max))),
+				material: material.clone(),
+				..default()
+			},
+		)).id();
+
+		commands.entity(entity).add_child(overlay);
 	}
 }
 
@@ -189,3 +441,35 @@ pub fn move_melee_hitbox(
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn matches_glob_patterns() {
+		assert!(glob_match("Landscape", "Landscape"));
+		assert!(!glob_match("Landscape", "Landscape.001"));
+		assert!(glob_match("Wall*", "Wall.003"));
+		assert!(glob_match("*_col", "tower_col"));
+		assert!(glob_match("Tower?_*", "Tower2_roof"));
+		assert!(glob_match("*", ""));
+		assert!(glob_match("a*b*c", "axxbyybc"));
+		assert!(!glob_match("Tower?", "Tower"));
+	}
+
+	#[test]
+	fn fits_bounds_to_transformed_and_scaled_boxes() {
+		let transform = Transform::from_xyz(0.0, 1.0, 0.0)
+			.with_rotation(Quat::from_rotation_y(std::f32::consts::FRAC_PI_2));
+		let (min, max) = transform_bounds(Vec3::new(-2.0, 0.0, -1.0), Vec3::new(2.0, 1.0, 1.0), &transform);
+
+		assert!(min.abs_diff_eq(Vec3::new(-1.0, 1.0, -2.0), 1e-5));
+		assert!(max.abs_diff_eq(Vec3::new(1.0, 2.0, 2.0), 1e-5));
+
+		let (min, max) = pad_bounds(Vec3::ZERO, Vec3::ONE, 0.5, Vec3::new(1.0, 2.0, -0.5));
+
+		assert_eq!(min, Vec3::new(-0.5, -0.25, -1.0));
+		assert_eq!(max, Vec3::new(1.5, 1.25, 2.0));
+	}
+}
@@ -10,6 +10,13 @@ pub struct Console {
 	pub new_lines: Vec<String>
 }
 
+/// A line entered in the console, split into a lowercase command name and
+/// its arguments.
+pub struct ConsoleCommand {
+	pub name: String,
+	pub args: Vec<String>
+}
+
 #[derive(Component)]
 pub struct ConsoleUI;
 
@@ -27,6 +34,7 @@ impl Plugin for ConsolePlugin {
 	fn build(&self, app: &mut App) {
 		app
 			.insert_resource(Console::default())
+			.add_event::<ConsoleCommand>()
 			.add_system(toggle_console)
 			.add_system(console_keyboard_handler)
 			.add_system(update_active_line);
@@ -158,6 +166,7 @@ fn console_keyboard_handler(
 	asset_server: Res<AssetServer>,
 	keyboard: Res<Input<KeyCode>>,
 	console_lines: Query<Entity, With<ConsoleHistory>>,
+	mut console_commands: EventWriter<ConsoleCommand>,
 ) {
 	if !console.active {
 		return;
@@ -178,6 +187,15 @@ fn console_keyboard_handler(
                 console.new_lines.push(current_line.clone());
                 console.current_line.clear();
 
+                let mut words = current_line.split_whitespace().map(|word| word.to_lowercase());
+
+                if let Some(name) = words.next() {
+                    console_commands.send(ConsoleCommand {
+                        name,
+                        args: words.collect()
+                    });
+                }
+
                 let mut console_lines = match console_lines.get_single() {
                     Ok(entity) => commands.entity(entity),
                     Err(_) => continue,
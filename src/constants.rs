@@ -1,3 +1,5 @@
 use bevy::prelude::Color;
 
 pub const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+
+pub const ASSETS_DIR: &str = "assets";
@@ -9,8 +9,11 @@ use crate::animations::handle_stop_animation;
 use crate::animations::detect_animation_players;
 use crate::animations::link_animation_players;
 use crate::attack::handle_attack;
+use crate::behavior::BehaviorPlugin;
 use crate::bullet::BulletPlugin;
+use crate::collisions::add_bounding_colliders;
 use crate::collisions::add_collisions;
+use crate::collisions::show_bounds;
 use crate::collisions::move_melee_hitbox;
 use crate::console_plugin::ConsolePlugin;
 use crate::death::TargetPlugin;
@@ -21,10 +24,16 @@ use crate::gltf::unpack_gltf;
 use crate::ingame_menu::GameMenuPlugin;
 use crate::input_handling::keyboard_handler;
 use crate::input_handling::mouse_handlers;
+use crate::map_error_plugin::map_is_valid;
+use crate::map_materials::MapMaterialsPlugin;
+use crate::map_saver::MapSaverPlugin;
+use crate::map_loader::MapChangesReceiver;
 use crate::map_spawner::*;
+use crate::navmesh::NavmeshPlugin;
 use crate::npc::NpcPlugin;
 use crate::npc::handle_cycle;
 use crate::player_control::*;
+use crate::terrain::TerrainPlugin;
 use crate::throw::TowerPlugin;
 use crate::types::*;
 
@@ -41,16 +50,24 @@ impl Plugin for GamePlugin {
 			.add_plugin(RapierDebugRenderPlugin::default())
 			.add_plugin(GameUiPlugin::default())
 			.add_plugin(ConsolePlugin::default())
+			.add_plugin(MapSaverPlugin)
+			.add_plugin(MapMaterialsPlugin)
+			.add_plugin(TerrainPlugin)
+			.add_plugin(NavmeshPlugin)
 			.add_plugin(GameMenuPlugin::default())
 			.add_plugin(NpcPlugin)
+			.add_plugin(BehaviorPlugin)
 			.add_plugin(TowerPlugin)
 			.add_plugin(TargetPlugin)
 			.add_plugin(BulletPlugin)
 			.insert_resource(RapierConfiguration::default())
 			.insert_resource(MapTemplates::default())
+			.insert_resource(MapPrefabs::default())
+			.insert_resource(CollisionMeshCache::default())
 			.insert_resource(GltfRegister::default())
 			.insert_resource(AssetPacks::default())
 			.insert_resource(PlayerIds::default())
+			.add_event::<AssetPackReloaded>()
 			.add_system(setup.in_schedule(OnEnter(GameState::Game)))
 			.add_systems((
 				handle_needs_template,
@@ -68,7 +85,23 @@ impl Plugin for GamePlugin {
 				ensure_animation,
 				handle_attack,
 			).in_set(OnUpdate(GameState::Game)))
-			.add_system(spawn_map_entites.in_schedule(OnEnter(GameState::Game)))
+			.add_system(
+				spawn_map_entites
+					.run_if(not(resource_exists::<MapChangesReceiver>()))
+					.run_if(map_is_valid)
+					.in_schedule(OnEnter(GameState::Game))
+			)
+			.add_system(
+				handle_map_changes
+					.run_if(resource_exists::<MapChangesReceiver>())
+					.in_set(OnUpdate(GameState::Game))
+			)
+			.add_systems((
+				add_bounding_colliders,
+				show_bounds,
+				give_placeholders,
+				reload_entity_scenes,
+			).in_set(OnUpdate(GameState::Game)))
 			.add_systems((
 				handle_start_animation,
 				handle_stop_animation,
@@ -2,52 +2,89 @@ use bevy::gltf::Gltf;
 use bevy::prelude::*;
 
 use crate::types::AssetPack;
+use crate::types::AssetPackReloaded;
 use crate::types::AssetPacks;
 use crate::types::GameAssets;
 use crate::types::GltfRegister;
 
+fn asset_pack(asset: &str, handle: &Handle<Gltf>, gltf: &Gltf) -> AssetPack {
+	let mut asset_pack = AssetPack { 
+		gltf: handle.clone(),
+		..Default::default()
+	};
+
+	gltf.scenes.iter().enumerate().for_each(|(index, scene)| {
+		log::info!("{} unnamed scene {}", asset, index);
+
+		asset_pack.scenes.push(scene.clone());
+	});
+
+	gltf.named_scenes.iter().for_each(|(scene_name, scene)| {
+		log::info!("{} named scene {}", asset, scene_name);
+
+		asset_pack.named_scenes.insert(scene_name.to_string(), scene.to_owned());
+	});
+
+	gltf.named_animations.iter().for_each(|(name, animation)| {
+		log::info!("{} named animation {}", asset, name);
+		asset_pack.named_animations.insert(name.to_string(), animation.to_owned());
+	});
+
+	gltf.animations.iter().enumerate().for_each(|(index, animation)| {
+		log::info!("{} unnamed animation {}", asset, index);
+
+		asset_pack.animations.push(animation.clone());
+	});
+
+	asset_pack
+}
+
+/// Turns loaded glTF files into asset packs, and rebuilds the packs of files
+/// that changed on disk
 pub fn unpack_gltf(
 	mut gltf_register: ResMut<GltfRegister>,
 	gltf_assets: Res<Assets<Gltf>>,
-	mut asset_packs: ResMut<AssetPacks>
+	mut asset_packs: ResMut<AssetPacks>,
+	mut gltf_events: EventReader<AssetEvent<Gltf>>,
+	mut reloaded: EventWriter<AssetPackReloaded>,
 ) {
 	gltf_register.unloaded.retain(|gltf_asset| {
 		if let Some(gltf) = gltf_assets.get(&gltf_asset.gltf) {
-			let mut asset_pack = AssetPack { 
-				gltf: gltf_asset.gltf.clone(),
-				..Default::default()
-			};
+			let asset_pack = asset_pack(&gltf_asset.asset, &gltf_asset.gltf, gltf);
 
-			gltf.scenes.iter().enumerate().for_each(|(index, scene)| {
-				log::info!("{} unnamed scene {}", gltf_asset.asset, index);
+			asset_packs.asset_packs.insert(gltf_asset.asset.clone(), asset_pack);
 
-				asset_pack.scenes.push(scene.clone());
-			});
+			return false;
+		}
 
-			gltf.named_scenes.iter().for_each(|(scene_name, scene)| {
-				log::info!("{} named scene {}", gltf_asset.asset, scene_name);
+		true
+	});
 
-				asset_pack.named_scenes.insert(scene_name.to_string(), scene.to_owned());
-			});
+	for event in gltf_events.iter() {
+		let handle = match event {
+			AssetEvent::Modified { handle } => handle,
+			_ => continue,
+		};
 
-			gltf.named_animations.iter().for_each(|(name, animation)| {
-				log::info!("{} named animation {}", gltf_asset.asset, name);
-				asset_pack.named_animations.insert(name.to_string(), animation.to_owned());
-			});
+		let gltf = match gltf_assets.get(handle) {
+			Some(gltf) => gltf,
+			None => continue,
+		};
 
-			gltf.animations.iter().enumerate().for_each(|(index, animation)| {
-				log::info!("{} unnamed animation {}", gltf_asset.asset, index);
+		for (asset, asset_pack) in asset_packs.asset_packs.iter_mut() {
+			if asset_pack.gltf != *handle {
+				continue;
+			}
 
-				asset_pack.animations.push(animation.clone());
-			});
+			log::info!("{} changed, rebuilding its asset pack", asset);
 
-			asset_packs.asset_packs.insert(gltf_asset.asset.clone(), asset_pack);
+			*asset_pack = self::asset_pack(asset, handle, gltf);
 
-			return false;
+			reloaded.send(AssetPackReloaded {
+				asset: asset.clone(),
+			});
 		}
-
-		true
-	});
+	}
 }
 
 pub fn asset_loading(mut commands: Commands, assets: Res<AssetServer>) {
@@ -1,42 +1,112 @@
+// The map modules build without the game, for tools such as orkky-mapcheck
+#[cfg(feature = "game")]
 mod game_ui_plugin;
+#[cfg(feature = "game")]
 mod gltf;
+#[cfg(feature = "game")]
 mod animations;
+#[cfg(feature = "game")]
 mod npc;
+#[cfg(feature = "game")]
+mod behavior;
+#[cfg(feature = "game")]
 mod types;
+#[cfg(feature = "game")]
 mod player;
-mod map;
+pub mod map;
+pub mod map_format;
+pub mod map_ids;
+#[cfg(feature = "game")]
 mod map_loader;
+#[cfg(feature = "game")]
+pub mod map_materials;
+pub mod map_migration;
+pub mod map_schema;
+#[cfg(feature = "game")]
+pub mod map_saver;
+pub mod map_validation;
+#[cfg(feature = "game")]
+mod map_error_plugin;
+#[cfg(feature = "game")]
+mod loading_plugin;
+#[cfg(feature = "game")]
 mod map_spawner;
+#[cfg(feature = "game")]
 mod collisions;
+#[cfg(feature = "game")]
 mod keymap;
+#[cfg(feature = "game")]
 mod input_handling;
+#[cfg(feature = "game")]
 mod splash_plugin;
+#[cfg(feature = "game")]
 mod menu_plugin;
+#[cfg(feature = "game")]
 mod game_plugin;
+#[cfg(feature = "game")]
 mod despawn;
+#[cfg(feature = "game")]
 mod cursor;
+#[cfg(feature = "game")]
 mod player_control;
+#[cfg(feature = "game")]
 mod math;
-mod path_finding;
+#[cfg(feature = "game")]
+pub mod path_finding;
+#[cfg(feature = "game")]
+pub mod navmesh;
+#[cfg(feature = "game")]
+mod terrain;
+#[cfg(feature = "game")]
 mod console_plugin;
+#[cfg(feature = "game")]
 mod attack;
+#[cfg(feature = "game")]
 mod death;
+#[cfg(feature = "game")]
 mod bullet;
+#[cfg(feature = "game")]
 mod throw;
+#[cfg(feature = "game")]
 mod game_over;
+#[cfg(feature = "game")]
 mod ingame_menu;
+#[cfg(feature = "game")]
 mod app;
+#[cfg(feature = "game")]
 mod constants;
 
+#[cfg(feature = "game")]
 use wasm_bindgen::prelude::*;
 
+#[cfg(feature = "game")]
 pub use app::run_app;
-use map::Map;
+#[cfg(feature = "game")]
+pub use types::NavigationMeshComponent;
+#[cfg(feature = "game")]
+pub use types::Point;
+#[cfg(feature = "game")]
+use map_validation::validate_map;
 
+/// Map files embedded at build time by build.rs
+#[cfg(feature = "game")]
+mod config_bundle {
+	include!(concat!(env!("OUT_DIR"), "/config_bundle.rs"));
+}
+
+#[cfg(feature = "game")]
+fn read_bundled_file(path: &str) -> std::io::Result<String> {
+	config_bundle::CONFIG_FILES
+		.iter()
+		.find(|(file, _)| *file == path)
+		.map(|(_, contents)| contents.to_string())
+		.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "file is not bundled"))
+}
+
+#[cfg(feature = "game")]
 #[wasm_bindgen]
 pub fn run_orkkypely() {
-	let map = include_str!("../config/map.json");
-	let map = Map::parse(map).unwrap();
+	let validated = validate_map("config/map.json", &read_bundled_file, None);
 
-	app::run_app(map);
+	app::run_app(validated.map.unwrap_or_default(), validated.diagnostics, None);
 }
@@ -0,0 +1,304 @@
+use bevy::asset::LoadState;
+use bevy::gltf::Gltf;
+use bevy::prelude::*;
+
+use crate::constants::TEXT_COLOR;
+use crate::despawn::despawn_screen;
+use crate::gltf::unpack_gltf;
+use crate::map::Map;
+use crate::map::MapAsset;
+use crate::map::MapAssetKind;
+use crate::types::AssetPacks;
+use crate::types::GameState;
+use crate::types::GltfRegister;
+use crate::types::LoadingAssets;
+use crate::types::UnloadedGltfAsset;
+
+// This plugin loads every asset of the map before the game starts, so the world doesn't pop in
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.insert_resource(LoadingAssets::default())
+			.add_systems((
+				start_loading,
+				loading_setup,
+			).in_schedule(OnEnter(GameState::Loading)))
+			.add_systems((
+				unpack_gltf,
+				track_loading,
+				update_loading_screen,
+				continue_without_failed,
+			).chain().in_set(OnUpdate(GameState::Loading)))
+			.add_system(despawn_screen::<OnLoadingScreen>.in_schedule(OnExit(GameState::Loading)));
+	}
+}
+
+// Tag component used to tag entities added on the loading screen
+#[derive(Component)]
+struct OnLoadingScreen;
+
+#[derive(Component)]
+struct LoadingProgressBar;
+
+#[derive(Component)]
+struct LoadingStatus;
+
+#[derive(Component)]
+struct LoadingFailures;
+
+/// Starts loading every asset of the map. glTF files go through the
+/// `GltfRegister` like the ones of templates added while playing.
+fn start_loading(
+	map: Res<Map>,
+	asset_server: Res<AssetServer>,
+	asset_packs: Res<AssetPacks>,
+	mut gltf_register: ResMut<GltfRegister>,
+	mut loading_assets: ResMut<LoadingAssets>,
+) {
+	let assets = map.assets();
+
+	log::info!("loading {} assets", assets.len());
+
+	let pending = assets.into_iter().map(|asset| {
+		let handle = match asset.kind {
+			MapAssetKind::Gltf => {
+				let gltf: Handle<Gltf> = asset_server.load(asset.path.as_str());
+
+				let registered = asset_packs.asset_packs.contains_key(&asset.path)
+					|| gltf_register.unloaded.iter().any(|unloaded| unloaded.asset == asset.path);
+
+				if !registered {
+					gltf_register.unloaded.push(UnloadedGltfAsset {
+						asset: asset.path.clone(),
+						gltf: gltf.clone(),
+					});
+				}
+
+				gltf.clone_untyped()
+			},
+			MapAssetKind::Image => asset_server.load::<Image, _>(asset.path.as_str()).clone_untyped(),
+		};
+
+		(asset, handle)
+	}).collect::<Vec<_>>();
+
+	*loading_assets = LoadingAssets {
+		total: pending.len(),
+		pending,
+		..default()
+	};
+}
+
+enum AssetStatus {
+	Pending,
+	Ready,
+	Failed(String),
+}
+
+fn asset_status(
+	asset: &MapAsset,
+	handle: &HandleUntyped,
+	asset_server: &AssetServer,
+	asset_packs: &AssetPacks,
+	gltf_assets: &Assets<Gltf>,
+) -> AssetStatus {
+	let load_state = asset_server.get_load_state(handle);
+
+	if load_state == LoadState::Failed {
+		return AssetStatus::Failed("the file is missing or can't be read".to_string());
+	}
+
+	match asset.kind {
+		// Ready once `unpack_gltf` has turned it into an asset pack
+		MapAssetKind::Gltf if asset_packs.asset_packs.contains_key(&asset.path) => AssetStatus::Ready,
+		MapAssetKind::Gltf if load_state == LoadState::Loaded && gltf_assets.get(&handle.clone().typed::<Gltf>()).is_none() => {
+			AssetStatus::Failed("it is not a glTF document, leave out labels such as `#Scene0`".to_string())
+		},
+		MapAssetKind::Gltf => AssetStatus::Pending,
+		MapAssetKind::Image if load_state == LoadState::Loaded => AssetStatus::Ready,
+		MapAssetKind::Image => AssetStatus::Pending,
+	}
+}
+
+/// Moves finished assets out of `pending` and starts the game once nothing
+/// is pending and nothing failed
+fn track_loading(
+	asset_server: Res<AssetServer>,
+	asset_packs: Res<AssetPacks>,
+	gltf_assets: Res<Assets<Gltf>>,
+	mut loading_assets: ResMut<LoadingAssets>,
+	mut game_state: ResMut<NextState<GameState>>,
+) {
+	let pending = std::mem::take(&mut loading_assets.pending);
+
+	for (asset, handle) in pending {
+		match asset_status(&asset, &handle, &asset_server, &asset_packs, &gltf_assets) {
+			AssetStatus::Pending => loading_assets.pending.push((asset, handle)),
+			AssetStatus::Ready => loading_assets.loaded.push((asset, handle)),
+			AssetStatus::Failed(reason) => {
+				log::error!("failed to load `{}` used by {}: {}", asset.path, asset.used_by.join(", "), reason);
+
+				loading_assets.failed.push((asset, reason));
+			},
+		}
+	}
+
+	if loading_assets.pending.is_empty() && loading_assets.failed.is_empty() {
+		log::info!("loaded {} assets", loading_assets.loaded.len());
+
+		game_state.set(GameState::Game);
+	}
+}
+
+/// Lets the player start without the assets that failed to load
+fn continue_without_failed(
+	keyboard: Res<Input<KeyCode>>,
+	loading_assets: Res<LoadingAssets>,
+	mut game_state: ResMut<NextState<GameState>>,
+) {
+	if loading_assets.pending.is_empty() && !loading_assets.failed.is_empty() && keyboard.just_pressed(KeyCode::Return) {
+		log::warn!("starting without {} assets", loading_assets.failed.len());
+
+		game_state.set(GameState::Game);
+	}
+}
+
+fn loading_setup(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+) {
+	let font = asset_server.load("FiraSans-Bold.ttf");
+
+	commands.spawn((Camera2dBundle::default(), OnLoadingScreen));
+
+	commands.spawn((
+		NodeBundle {
+			style: Style {
+				size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+				flex_direction: FlexDirection::Column,
+				align_items: AlignItems::Center,
+				justify_content: JustifyContent::Center,
+				..default()
+			},
+			background_color: Color::rgb(0.05, 0.05, 0.05).into(),
+			..default()
+		},
+		OnLoadingScreen,
+	)).with_children(|parent| {
+		parent.spawn(
+			TextBundle::from_section(
+				"Loading",
+				TextStyle {
+					font: font.clone(),
+					font_size: 40.0,
+					color: TEXT_COLOR,
+				},
+			).with_style(Style {
+				margin: UiRect::bottom(Val::Px(20.0)),
+				..default()
+			})
+		);
+
+		parent.spawn(NodeBundle {
+			style: Style {
+				size: Size::new(Val::Px(400.0), Val::Px(24.0)),
+				..default()
+			},
+			background_color: Color::rgb(0.2, 0.2, 0.2).into(),
+			..default()
+		}).with_children(|parent| {
+			parent.spawn((
+				NodeBundle {
+					style: Style {
+						size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+						..default()
+					},
+					background_color: Color::rgb(0.35, 0.75, 0.35).into(),
+					..default()
+				},
+				LoadingProgressBar,
+			));
+		});
+
+		parent.spawn((
+			TextBundle::from_section(
+				"",
+				TextStyle {
+					font: font.clone(),
+					font_size: 18.0,
+					color: TEXT_COLOR,
+				},
+			).with_style(Style {
+				margin: UiRect::top(Val::Px(10.0)),
+				..default()
+			}),
+			LoadingStatus,
+		));
+
+		parent.spawn((
+			NodeBundle {
+				style: Style {
+					flex_direction: FlexDirection::Column,
+					margin: UiRect::top(Val::Px(20.0)),
+					..default()
+				},
+				..default()
+			},
+			LoadingFailures,
+		));
+	});
+}
+
+fn update_loading_screen(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	loading_assets: Res<LoadingAssets>,
+	mut progress_bars: Query<&mut Style, With<LoadingProgressBar>>,
+	mut statuses: Query<&mut Text, With<LoadingStatus>>,
+	failures: Query<(Entity, Option<&Children>), With<LoadingFailures>>,
+) {
+	if !loading_assets.is_changed() {
+		return;
+	}
+
+	let done = loading_assets.loaded.len() + loading_assets.failed.len();
+	let progress = match loading_assets.total {
+		0 => 1.0,
+		total => done as f32 / total as f32,
+	};
+
+	for mut style in progress_bars.iter_mut() {
+		style.size.width = Val::Percent(progress * 100.0);
+	}
+
+	for mut text in statuses.iter_mut() {
+		text.sections[0].value = match loading_assets.pending.is_empty() && !loading_assets.failed.is_empty() {
+			true => format!("{} assets could not be loaded, press Enter to play without them", loading_assets.failed.len()),
+			false => format!("{} / {} assets", done, loading_assets.total),
+		};
+	}
+
+	let font = asset_server.load("FiraSans-Bold.ttf");
+
+	for (entity, children) in failures.iter() {
+		// Only failures that aren't listed yet are added
+		let listed = children.map(|children| children.len()).unwrap_or(0);
+
+		commands.entity(entity).with_children(|parent| {
+			for (asset, reason) in loading_assets.failed.iter().skip(listed) {
+				parent.spawn(
+					TextBundle::from_section(
+						format!("`{}` used by {}: {}", asset.path, asset.used_by.join(", "), reason),
+						TextStyle {
+							font: font.clone(),
+							font_size: 18.0,
+							color: Color::rgb(0.9, 0.4, 0.4),
+						},
+					)
+				);
+			}
+		});
+	}
+}
@@ -1,4 +1,7 @@
+use std::path::Path;
+
 use app::run_app;
+use constants::ASSETS_DIR;
 use bevy::{prelude::*, utils::FloatOrd};
 use bevy::DefaultPlugins;
 use bevy::log::LogPlugin;
@@ -10,6 +13,7 @@ use game_plugin::GamePlugin;
 use keymap::Keymap;
 use map::Map;
 use map_loader::create_map_loader;
+use map_validation::load_and_validate;
 use menu_plugin::MenuPlugin;
 use splash_plugin::SplashPlugin;
 use types::GameState;
@@ -19,10 +23,19 @@ mod game_ui_plugin;
 mod gltf;
 mod animations;
 mod npc;
+mod behavior;
 mod types;
 mod player;
 mod map;
+mod map_format;
+mod map_ids;
 mod map_loader;
+mod map_materials;
+mod map_migration;
+mod map_saver;
+mod map_validation;
+mod map_error_plugin;
+mod loading_plugin;
 mod map_spawner;
 mod collisions;
 mod keymap;
@@ -35,6 +48,8 @@ mod cursor;
 mod player_control;
 mod math;
 mod path_finding;
+mod navmesh;
+mod terrain;
 mod console_plugin;
 mod attack;
 mod death;
@@ -48,9 +63,16 @@ mod constants;
 fn main() {
 	let keymap = Keymap::load("./config/keymap.json");
 	
-	let map = Map::load("./config/map.json").unwrap();
+	// The map can be given as JSON, RON or YAML
+	let map_path = std::env::args().nth(1).unwrap_or("./config/map.json".to_string());
+
+	let validated = load_and_validate(
+		&map_path,
+		Some(Path::new(ASSETS_DIR))
+	);
+	let map_changes = create_map_loader(&map_path);
 
-	run_app(map);
+	run_app(validated.map.unwrap_or_default(), validated.diagnostics, Some(map_changes));
 }
 
 
@@ -1,31 +1,67 @@
+use std::collections::BTreeMap;
+
+#[cfg(feature = "game")]
+use bevy::pbr::CascadeShadowConfigBuilder;
+use bevy::prelude::EulerRot;
+use bevy::prelude::Quat;
 use bevy::prelude::Resource;
+use bevy::prelude::Transform;
+use bevy::prelude::Vec3;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-// #[serde(tag = "type")]
+use crate::map_format::MapFormat;
+use crate::map_migration::migrate;
+use crate::map_migration::MAP_VERSION;
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+	*value == T::default()
+}
+
+/// Physics collider of a template
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type")]
 pub enum MapEntityCollider {
-	AABB,
-	Capsule {
-		a: f32,
-		b: f32,
-		radius: f32
-	},
-	Cuboid {
-		x: f32,
-		y: f32,
-		z: f32,
+	/// Bounding box of the asset's meshes
+	AABB(MapAabbCollider),
+	/// Capsule along the y axis
+	Capsule(MapCapsuleCollider),
+	/// Box with half extents
+	Cuboid(MapCuboidCollider),
+}
 
-	},
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapAabbCollider {
+	/// Grows the box on every side
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub padding: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapCapsuleCollider {
+	/// Height of one end point
+	pub a: f32,
+	/// Height of the other end point
+	pub b: f32,
+	pub radius: f32
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapCuboidCollider {
+	pub x: f32,
+	pub y: f32,
+	pub z: f32,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum WeaponType {
 	Melee,
 	Ranged
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// A weapon an entity can attack with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Weapon {
 	pub weapon_type: WeaponType,
 	pub animation: Option<String>,
@@ -35,21 +71,83 @@ pub struct Weapon {
 	pub ammo: Option<usize>
 }
 
-#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+/// Rotation in radians around the x, y and z axes, applied in y, x, z
+/// order like the rotation of templates, or a quaternion `[x, y, z, w]`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum MapRotation {
+	Euler([f32; 3]),
+	Quaternion([f32; 4])
+}
+
+impl MapRotation {
+	pub fn to_quat(self) -> Quat {
+		match self {
+			MapRotation::Euler(rotation) => Quat::from_euler(EulerRot::YXZ, rotation[1], rotation[0], rotation[2]),
+			MapRotation::Quaternion(quaternion) => Quat::from_array(quaternion).normalize(),
+		}
+	}
+}
+
+/// The same scale on every axis, or one for each of x, y and z
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum MapScale {
+	Uniform(f32),
+	PerAxis([f32; 3])
+}
+
+impl MapScale {
+	pub fn to_vec3(self) -> Vec3 {
+		match self {
+			MapScale::Uniform(scale) => Vec3::splat(scale),
+			MapScale::PerAxis(scale) => Vec3::from_array(scale),
+		}
+	}
+}
+
+/// Position, rotation and scale of an entity or a shape
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapTransform {
+	pub position: Option<[f32; 3]>,
+	pub rotation: Option<MapRotation>,
+	pub scale: Option<MapScale>
+}
+
+impl MapTransform {
+	pub fn to_transform(self) -> Transform {
+		Transform {
+			translation: self.position.map(Vec3::from_array).unwrap_or_default(),
+			rotation: self.rotation.map(|rotation| rotation.to_quat()).unwrap_or_default(),
+			scale: self.scale.map(|scale| scale.to_vec3()).unwrap_or(Vec3::ONE),
+		}
+	}
+}
+
+/// An instance of a template placed in the map
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct MapEntity {
-	#[serde(default)]
+	/// Unique id, generated when left out
+	#[serde(default, skip_serializing_if = "is_default")]
 	pub entity_id: String,
+	/// Name of the template the entity is made from
 	pub template: String,
-	pub initial_position: Option<[f32; 3]>,
-	pub initial_rotation: Option<[f32; 3]>,
-	pub scale: Option<f32>,
+	pub transform: Option<MapTransform>,
+	/// Controlled by the player
 	pub player: Option<bool>,
+	/// Controlled by the game, walks through `move_cycle`
 	pub npc: Option<bool>,
+	/// Points an NPC walks through in a loop
 	pub move_cycle: Option<Vec<[f32; 3]>>,
+	/// Defaults to 100
 	pub max_health: Option<f32>,
+	/// Per-instance values for any template field, applied on top of the
+	/// resolved template.
+	pub overrides: Option<MapTemplate>,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// How the physics engine moves entities of a template
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum MapEntityPhysics {
 	Static,
 	Dynamic,
@@ -62,12 +160,123 @@ impl Default for MapEntityPhysics {
 	}
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// How a template's `weapons` combine with the ones it inherits.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum WeaponsMerge {
+	/// Use the template's own weapons if it has any, the inherited ones otherwise
+	Replace,
+	/// Inherited weapons followed by the template's own
+	Append
+}
+
+impl Default for WeaponsMerge {
+	fn default() -> Self {
+		WeaponsMerge::Replace
+	}
+}
+
+/// How the meshes of an asset are turned into colliders
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum MapCollisionShape {
+	/// The exact triangles, for static level geometry
+	TriMesh,
+	/// The smallest convex shape around each mesh
+	ConvexHull,
+	/// Convex parts approximating each mesh, slow to build
+	ConvexDecomposition
+}
+
+/// Meshes of a template's glTF asset that become colliders
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct MapCollisionMesh {
+	/// Names of glTF meshes or nodes, where `*` matches any characters and
+	/// `?` a single one. Matching a node includes every mesh below it.
+	/// Defaults to `Landscape`.
+	pub meshes: Option<Vec<String>>,
+	/// Defaults to `TriMesh`
+	pub shape: Option<MapCollisionShape>
+}
+
+/// Node of an NPC behavior tree. Every tick a node succeeds, fails or keeps
+/// running.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type")]
+pub enum MapBehavior {
+	/// Runs its children in order until one doesn't succeed
+	Sequence(MapBehaviorChildren),
+	/// Runs its children in order until one doesn't fail
+	Selector(MapBehaviorChildren),
+	/// Swaps the success and failure of its child
+	Invert(MapBehaviorChild),
+	/// Succeeds whenever its child doesn't keep running
+	AlwaysSucceed(MapBehaviorChild),
+	/// Fails for `seconds` after its child succeeded
+	Cooldown(MapCooldown),
+	/// Succeeds while a player is within `range`
+	PlayerInRange(MapPlayerInRange),
+	/// Succeeds while health is below `fraction` of the maximum
+	HealthBelow(MapHealthBelow),
+	/// Walks through the entity's `move_cycle`
+	Patrol,
+	/// Runs to the closest player until it is within range of the current weapon
+	Chase,
+	/// Attacks the closest player with the current weapon while it is in range
+	Attack,
+	/// Runs away from the closest player until it is `distance` away
+	Flee(MapFlee),
+	/// Walks back to where the entity was placed in the map
+	ReturnHome,
+	/// Keeps running for `seconds`
+	Wait(MapWait),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapBehaviorChildren {
+	pub children: Vec<MapBehavior>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapBehaviorChild {
+	pub child: Box<MapBehavior>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapCooldown {
+	pub seconds: f32,
+	pub child: Box<MapBehavior>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapPlayerInRange {
+	pub range: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapHealthBelow {
+	pub fraction: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapFlee {
+	pub distance: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapWait {
+	pub seconds: f32,
+}
+
+/// Shared settings of entities: asset, animations, physics and weapons
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct MapTemplate {
+	#[serde(default, skip_serializing_if = "is_default")]
 	pub name: String,
+	/// Template whose fields this template inherits when it does not set them
+	pub extends: Option<String>,
+	/// glTF file in the assets directory
 	pub asset: Option<String>,
 	pub player_controllable: Option<bool>,
-	pub iddle_animation: Option<String>,
+	pub idle_animation: Option<String>,
 	pub walk_animation: Option<String>,
 	pub run_animation: Option<String>,
 	pub jump_animation: Option<String>,
@@ -75,9 +284,9 @@ pub struct MapTemplate {
 	pub shoot_animation: Option<String>,
 	pub fps_camera_location: Option<[f32; 3]>,
 	pub third_person_camera_location: Option<[f32; 3]>,
-	pub initial_rotation_y: Option<f32>,
-	pub initial_rotation_x: Option<f32>,
-	pub initial_rotation_z: Option<f32>,
+	/// Rotation of the asset in radians
+	pub initial_rotation: Option<[f32; 3]>,
+	/// Offset of the asset from the entity
 	pub initial_transform: Option<[f32; 3]>,
 	pub walk_speed: Option<f32>,
 	pub run_speed: Option<f32>,
@@ -85,36 +294,184 @@ pub struct MapTemplate {
 	pub mass: Option<f32>,
 	pub friction: Option<f32>,
 	pub physics: Option<MapEntityPhysics>,
+	/// Builds colliders from the meshes of the asset, see `collision_mesh`.
+	/// Defaults to whether `collision_mesh` is set.
 	pub automatic_collision_mesh: Option<bool>,
-	#[serde(default)]
+	pub collision_mesh: Option<MapCollisionMesh>,
+	/// Map materials replacing the materials of the glTF scene, by glTF
+	/// material name. `*` replaces all other materials.
+	pub materials: Option<BTreeMap<String, String>>,
+	/// Shows a debug mesh for templates without an asset in dev builds,
+	/// true by default
+	pub placeholder: Option<bool>,
+	#[serde(default, skip_serializing_if = "is_default")]
 	pub weapons: Vec<Weapon>,
+	#[serde(default, skip_serializing_if = "is_default")]
+	pub weapons_merge: WeaponsMerge,
 	pub death_sound_effect: Option<String>,
+	/// Behavior tree run by NPC entities instead of shooting at players
+	pub behavior: Option<MapBehavior>,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+impl MapTemplate {
+	/// Fills every field that is not set on this template from `parent`.
+	pub fn inherit(&self, parent: &MapTemplate) -> MapTemplate {
+		let weapons = match self.weapons_merge {
+			WeaponsMerge::Replace if self.weapons.is_empty() => parent.weapons.clone(),
+			WeaponsMerge::Replace => self.weapons.clone(),
+			WeaponsMerge::Append => {
+				parent.weapons.iter().chain(self.weapons.iter()).cloned().collect()
+			}
+		};
+
+		MapTemplate {
+			name: self.name.clone(),
+			extends: self.extends.clone(),
+			asset: self.asset.clone().or_else(|| parent.asset.clone()),
+			player_controllable: self.player_controllable.or(parent.player_controllable),
+			idle_animation: self.idle_animation.clone().or_else(|| parent.idle_animation.clone()),
+			walk_animation: self.walk_animation.clone().or_else(|| parent.walk_animation.clone()),
+			run_animation: self.run_animation.clone().or_else(|| parent.run_animation.clone()),
+			jump_animation: self.jump_animation.clone().or_else(|| parent.jump_animation.clone()),
+			reload_animation: self.reload_animation.clone().or_else(|| parent.reload_animation.clone()),
+			shoot_animation: self.shoot_animation.clone().or_else(|| parent.shoot_animation.clone()),
+			fps_camera_location: self.fps_camera_location.or(parent.fps_camera_location),
+			third_person_camera_location: self.third_person_camera_location.or(parent.third_person_camera_location),
+			initial_rotation: self.initial_rotation.or(parent.initial_rotation),
+			initial_transform: self.initial_transform.or(parent.initial_transform),
+			walk_speed: self.walk_speed.or(parent.walk_speed),
+			run_speed: self.run_speed.or(parent.run_speed),
+			collider: self.collider.clone().or_else(|| parent.collider.clone()),
+			mass: self.mass.or(parent.mass),
+			friction: self.friction.or(parent.friction),
+			physics: self.physics.clone().or_else(|| parent.physics.clone()),
+			automatic_collision_mesh: self.automatic_collision_mesh.or(parent.automatic_collision_mesh),
+			collision_mesh: self.collision_mesh.clone().or_else(|| parent.collision_mesh.clone()),
+			materials: self.materials.clone().or_else(|| parent.materials.clone()),
+			placeholder: self.placeholder.or(parent.placeholder),
+			weapons: weapons,
+			weapons_merge: self.weapons_merge,
+			death_sound_effect: self.death_sound_effect.clone().or_else(|| parent.death_sound_effect.clone()),
+			behavior: self.behavior.clone().or_else(|| parent.behavior.clone()),
+		}
+	}
+
+	/// The template as used by one entity, with the entity's overrides applied.
+	pub fn for_entity(&self, entity: &MapEntity) -> MapTemplate {
+		match &entity.overrides {
+			Some(overrides) => {
+				let mut template = overrides.inherit(self);
+				template.name = self.name.clone();
+				template.extends = self.extends.clone();
+				template
+			},
+			None => self.clone()
+		}
+	}
+
+	/// Collision mesh settings, if the template wants colliders built from
+	/// its asset
+	pub fn collision_mesh(&self) -> Option<MapCollisionMesh> {
+		match self.automatic_collision_mesh.unwrap_or(self.collision_mesh.is_some()) {
+			true => Some(self.collision_mesh.clone().unwrap_or_default()),
+			false => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateResolveError {
+	UnknownTemplate(String),
+	MissingParent {
+		template: String,
+		parent: String
+	},
+	Cycle(Vec<String>)
+}
+
+impl std::fmt::Display for TemplateResolveError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			TemplateResolveError::UnknownTemplate(name) => {
+				write!(f, "unknown template `{}`", name)
+			},
+			TemplateResolveError::MissingParent { template, parent } => {
+				write!(f, "template `{}` extends unknown template `{}`", template, parent)
+			},
+			TemplateResolveError::Cycle(chain) => {
+				write!(f, "templates extend each other in a cycle: {}", chain.join(" -> "))
+			},
+		}
+	}
+}
+
+/// Follows the `extends` chain of a template and merges it into a single
+/// template, closest ancestor first.
+pub fn resolve_template<'a>(
+	name: &str,
+	lookup: impl Fn(&str) -> Option<&'a MapTemplate>,
+) -> Result<MapTemplate, TemplateResolveError> {
+	let template = lookup(name)
+		.ok_or_else(|| TemplateResolveError::UnknownTemplate(name.to_string()))?;
+
+	let mut chain = vec![template];
+
+	while let Some(parent_name) = &chain[chain.len() - 1].extends {
+		if chain.iter().any(|t| &t.name == parent_name) {
+			let mut names: Vec<String> = chain.iter().map(|t| t.name.clone()).collect();
+			names.push(parent_name.clone());
+
+			return Err(TemplateResolveError::Cycle(names));
+		}
+
+		let parent = lookup(parent_name).ok_or_else(|| TemplateResolveError::MissingParent {
+			template: chain[chain.len() - 1].name.clone(),
+			parent: parent_name.clone(),
+		})?;
+
+		chain.push(parent);
+	}
+
+	let mut resolved = chain.pop().unwrap().clone();
+
+	while let Some(child) = chain.pop() {
+		resolved = child.inherit(&resolved);
+	}
+
+	Ok(resolved)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct MapCube {
-	pub size: f32
+	pub size: f32,
+	/// Name of a map material
+	pub material: Option<String>
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct MapPlane {
 	pub size: f32,
-	pub material: Option<String>,
-	pub location: Option<[f32; 3]>
+	/// Name of a map material
+	pub material: Option<String>
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct MapQuad {
-	pub size: [f32; 2]
+	pub size: [f32; 2],
+	/// Name of a map material
+	pub material: Option<String>
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct MapCircle {
 	pub radius: f32,
-	pub vertices: Option<usize>
+	pub vertices: Option<usize>,
+	/// Name of a map material
+	pub material: Option<String>
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Axis aligned box, optionally with a static collider
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct MapBox {
 	pub min_x: f32,
 	pub max_x: f32,
@@ -122,28 +479,136 @@ pub struct MapBox {
 	pub max_y: f32,
 	pub min_z: f32,
 	pub max_z: f32,
-	pub collider: Option<bool>
+	pub collider: Option<bool>,
+	/// Name of a map material
+	pub material: Option<String>
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Ground built from a grayscale heightmap, centered on the shape's position
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapTerrain {
+	/// Image in the assets directory, black is the lowest and white the
+	/// highest point
+	pub heightmap: String,
+	/// Width along x and depth along z
+	pub size: [f32; 2],
+	/// Height of white pixels
+	pub height_scale: f32,
+	/// Vertices along each side, the width of the heightmap up to 512 by default
+	pub resolution: Option<u32>,
+	/// Name of a map material
+	pub material: Option<String>,
+	/// Image in the assets directory whose red, green, blue and alpha
+	/// channels weight the `layers`
+	pub splat_map: Option<String>,
+	/// Names of up to four map materials, whose colors are blended by the
+	/// splat map when the terrain is built
+	pub layers: Option<Vec<String>>,
+	/// Static heightfield collider, true by default
+	pub collider: Option<bool>,
+	/// Steepest slope NPCs can walk on in radians, 45 degrees by default
+	pub max_slope: Option<f32>
+}
+
+/// Mesh of a shape, tagged with a `type` field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum MapShapeType {
 	Cube(MapCube),
 	Plane(MapPlane),
 	Quad(MapQuad),
 	Circle(MapCircle),
-	Box(MapBox)
+	Box(MapBox),
+	Terrain(MapTerrain)
+}
+
+impl MapShapeType {
+	/// Name of the map material of the shape
+	pub fn material(&self) -> Option<&str> {
+		match self {
+			MapShapeType::Cube(cube) => cube.material.as_deref(),
+			MapShapeType::Plane(plane) => plane.material.as_deref(),
+			MapShapeType::Quad(quad) => quad.material.as_deref(),
+			MapShapeType::Circle(circle) => circle.material.as_deref(),
+			MapShapeType::Box(box_shape) => box_shape.material.as_deref(),
+			MapShapeType::Terrain(terrain) => terrain.material.as_deref(),
+		}
+	}
+}
+
+/// How the alpha of a material is used
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum MapAlphaMode {
+	Opaque,
+	/// Fully transparent below `alpha_cutoff`, opaque above
+	Mask,
+	Blend,
+	Premultiplied,
+	Add,
+	Multiply
+}
+
+/// A material that shapes and templates refer to by name
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapMaterial {
+	#[serde(default, skip_serializing_if = "is_default")]
+	pub name: String,
+	/// Hex color, e.g. `ffffff`, multiplied with the texture
+	pub base_color: Option<String>,
+	/// Image in the assets directory
+	pub base_color_texture: Option<String>,
+	/// Image in the assets directory
+	pub normal_map_texture: Option<String>,
+	/// From 0 (dielectric) to 1 (metal)
+	pub metallic: Option<f32>,
+	/// From 0 (smooth) to 1 (rough)
+	pub roughness: Option<f32>,
+	/// Image in the assets directory, roughness in green and metallic in blue
+	pub metallic_roughness_texture: Option<String>,
+	/// Hex color of the light the material emits
+	pub emissive: Option<String>,
+	/// Image in the assets directory
+	pub emissive_texture: Option<String>,
+	pub alpha_mode: Option<MapAlphaMode>,
+	/// Alpha below which `Mask` materials are transparent, 0.5 by default
+	pub alpha_cutoff: Option<f32>,
+	/// How many times textures repeat across a shape
+	pub uv_scale: Option<[f32; 2]>,
+	pub double_sided: Option<bool>,
+	pub unlit: Option<bool>
+}
+
+impl MapMaterial {
+	/// Texture paths of the material with their field names
+	pub fn textures(&self) -> Vec<(&'static str, &str)> {
+		[
+			("base_color_texture", &self.base_color_texture),
+			("normal_map_texture", &self.normal_map_texture),
+			("metallic_roughness_texture", &self.metallic_roughness_texture),
+			("emissive_texture", &self.emissive_texture),
+		]
+			.into_iter()
+			.filter_map(|(field, texture)| texture.as_deref().map(|texture| (field, texture)))
+			.collect()
+	}
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// A primitive mesh placed in the map
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct MapShape {
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "is_default")]
 	pub id: String,
-	pub shape: MapShapeType
+	pub shape: MapShapeType,
+	pub transform: Option<MapTransform>,
+	/// Collider matching the mesh, static unless `physics` says otherwise
+	pub collider: Option<bool>,
+	/// Rigid body of the shape, implies a collider
+	pub physics: Option<MapEntityPhysics>
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct PointMapLight {
+	/// Hex color, e.g. `ffffff`
 	pub color: String,
 	pub intensity: Option<f32>,
 	pub range: Option<f32>,
@@ -152,58 +617,504 @@ pub struct PointMapLight {
 	pub location: Option<[f32; 3]>
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Shadow cascades of a directional light. Unset fields keep the Bevy
+/// defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapShadowCascades {
+	pub num_cascades: Option<usize>,
+	/// Distance from the camera where shadows start
+	pub minimum_distance: Option<f32>,
+	/// Distance from the camera where shadows end
+	pub maximum_distance: Option<f32>,
+	/// Far bound of the first, most detailed cascade
+	pub first_cascade_far_bound: Option<f32>,
+	/// Overlap between cascades, from 0 up to but not including 1
+	pub overlap_proportion: Option<f32>
+}
+
+/// Resolved shadow cascade settings, the fields of Bevy's
+/// `CascadeShadowConfigBuilder`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowCascades {
+	pub num_cascades: usize,
+	pub minimum_distance: f32,
+	pub maximum_distance: f32,
+	pub first_cascade_far_bound: f32,
+	pub overlap_proportion: f32,
+}
+
+impl Default for ShadowCascades {
+	/// Bevy's defaults, which need its renderer to look up
+	fn default() -> Self {
+		ShadowCascades {
+			num_cascades: 4,
+			minimum_distance: 0.1,
+			maximum_distance: 1000.0,
+			first_cascade_far_bound: 5.0,
+			overlap_proportion: 0.2,
+		}
+	}
+}
+
+impl MapShadowCascades {
+	/// The given settings over the defaults, or an error if Bevy would
+	/// reject them.
+	pub fn resolve(&self) -> Result<ShadowCascades, String> {
+		let default = ShadowCascades::default();
+
+		let cascades = ShadowCascades {
+			num_cascades: self.num_cascades.unwrap_or(default.num_cascades),
+			minimum_distance: self.minimum_distance.unwrap_or(default.minimum_distance),
+			maximum_distance: self.maximum_distance.unwrap_or(default.maximum_distance),
+			first_cascade_far_bound: self.first_cascade_far_bound.unwrap_or(default.first_cascade_far_bound),
+			overlap_proportion: self.overlap_proportion.unwrap_or(default.overlap_proportion),
+		};
+
+		if cascades.num_cascades == 0 {
+			return Err("num_cascades must be at least 1".to_string());
+		}
+
+		if !cascades.minimum_distance.is_finite() || cascades.minimum_distance < 0.0 {
+			return Err(format!("minimum_distance must not be negative, got {}", cascades.minimum_distance));
+		}
+
+		if cascades.maximum_distance.is_nan() || cascades.maximum_distance <= cascades.minimum_distance {
+			return Err(format!(
+				"maximum_distance {} must be greater than minimum_distance {}",
+				cascades.maximum_distance,
+				cascades.minimum_distance
+			));
+		}
+
+		let far_bound = cascades.first_cascade_far_bound;
+
+		if cascades.num_cascades > 1 && (far_bound.is_nan() || far_bound <= cascades.minimum_distance) {
+			return Err(format!(
+				"first_cascade_far_bound {} must be greater than minimum_distance {}",
+				cascades.first_cascade_far_bound,
+				cascades.minimum_distance
+			));
+		}
+
+		if !(0.0..1.0).contains(&cascades.overlap_proportion) {
+			return Err(format!("overlap_proportion must be at least 0 and less than 1, got {}", cascades.overlap_proportion));
+		}
+
+		Ok(cascades)
+	}
+
+	/// Builder with the given settings, or an error if Bevy would reject them.
+	#[cfg(feature = "game")]
+	pub fn to_builder(&self) -> Result<CascadeShadowConfigBuilder, String> {
+		let cascades = self.resolve()?;
+
+		Ok(CascadeShadowConfigBuilder {
+			num_cascades: cascades.num_cascades,
+			minimum_distance: cascades.minimum_distance,
+			maximum_distance: cascades.maximum_distance,
+			first_cascade_far_bound: cascades.first_cascade_far_bound,
+			overlap_proportion: cascades.overlap_proportion,
+		})
+	}
+}
+
+/// Light that shines in one direction from infinitely far away, like the sun
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DirectionalMapLight {
+	/// Hex color, e.g. `ffffff`
+	pub color: String,
+	/// Illuminance in lux
+	pub illuminance: Option<f32>,
+	pub shadows_enabled: Option<bool>,
+	/// Rotation in radians around the x, y and z axes. Without rotation the
+	/// light shines along -z.
+	pub rotation: Option<[f32; 3]>,
+	pub shadow_cascades: Option<MapShadowCascades>
+}
+
+/// Cone of light shining from `location` towards `direction`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SpotMapLight {
+	/// Hex color, e.g. `ffffff`
+	pub color: String,
+	pub intensity: Option<f32>,
+	pub range: Option<f32>,
+	pub radius: Option<f32>,
+	pub shadows_enabled: Option<bool>,
+	pub location: Option<[f32; 3]>,
+	/// Direction the light points to, straight down by default
+	pub direction: Option<[f32; 3]>,
+	/// Angle in radians from the center of the cone where the light starts
+	/// to fade out
+	pub inner_angle: Option<f32>,
+	/// Angle in radians from the center of the cone to its edge, at most
+	/// a quarter turn
+	pub outer_angle: Option<f32>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum LightType {
-	Point(PointMapLight)
+	Point(PointMapLight),
+	Directional(DirectionalMapLight),
+	Spot(SpotMapLight)
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Light {
-	#[serde(default)]
+	#[serde(default, skip_serializing_if = "is_default")]
 	pub id: String,
 	#[serde(rename = "type")]
 	pub light_type: LightType
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Light that reaches every surface evenly
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct AmbientLight {
+	/// Hex color, e.g. `ffffff`
 	pub color: String,
 	pub brightness: f32
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum CameraType {
 	FPS,
 	ThirdPerson
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// The camera follows the entity with `entity_id`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct MapCamera {
 	pub camera_type: Option<CameraType>,
 	pub entity_id: String
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default, Resource)]
+/// A reusable group of entities, shapes and lights. Their transforms are
+/// relative to the instance.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapPrefab {
+	pub name: String,
+	pub entities: Option<Vec<MapEntity>>,
+	pub shapes: Option<Vec<MapShape>>,
+	pub lights: Option<Vec<Light>>
+}
+
+/// A prefab placed in the map
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapPrefabInstance {
+	/// Unique id, generated when left out. The parts of the instance get
+	/// ids such as `<instance id>/<part id>`.
+	#[serde(default, skip_serializing_if = "is_default")]
+	pub id: String,
+	/// Name of the prefab
+	pub prefab: String,
+	pub transform: Option<MapTransform>,
+	/// Fields to change in the parts of this instance, by part id. Objects
+	/// are merged into the part, other values replace the part's value.
+	pub overrides: Option<BTreeMap<String, serde_json::Value>>
+}
+
+/// The parts of one prefab instance, with its overrides applied and ids
+/// prefixed with the instance id
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MapPrefabParts {
+	pub entities: Vec<MapEntity>,
+	pub shapes: Vec<MapShape>,
+	pub lights: Vec<Light>
+}
+
+impl MapPrefab {
+	/// Ids of the entities, shapes and lights of the prefab
+	pub fn part_ids(&self) -> impl Iterator<Item = &str> {
+		let entities = self.entities.iter().flatten().map(|e| e.entity_id.as_str());
+		let shapes = self.shapes.iter().flatten().map(|s| s.id.as_str());
+		let lights = self.lights.iter().flatten().map(|l| l.id.as_str());
+
+		entities.chain(shapes).chain(lights)
+	}
+
+	/// The parts of the prefab as placed by `instance`. Fails if an override
+	/// does not fit the part it changes.
+	pub fn instantiate(&self, instance: &MapPrefabInstance) -> anyhow::Result<MapPrefabParts> {
+		let overrides = instance.overrides.as_ref();
+		let part_id = |id: &str| format!("{}/{}", instance.id, id);
+
+		let mut parts = MapPrefabParts::default();
+
+		for entity in self.entities.iter().flatten() {
+			let mut entity = apply_override(entity, overrides.and_then(|o| o.get(&entity.entity_id)))?;
+			entity.entity_id = part_id(&entity.entity_id);
+
+			parts.entities.push(entity);
+		}
+
+		for shape in self.shapes.iter().flatten() {
+			let mut shape = apply_override(shape, overrides.and_then(|o| o.get(&shape.id)))?;
+			shape.id = part_id(&shape.id);
+
+			parts.shapes.push(shape);
+		}
+
+		for light in self.lights.iter().flatten() {
+			let mut light = apply_override(light, overrides.and_then(|o| o.get(&light.id)))?;
+			light.id = part_id(&light.id);
+
+			parts.lights.push(light);
+		}
+
+		Ok(parts)
+	}
+}
+
+/// Merges `patch` into a serialized value: objects field by field, anything
+/// else by replacing the value.
+fn merge_value(value: &mut serde_json::Value, patch: &serde_json::Value) {
+	match (value, patch) {
+		(serde_json::Value::Object(fields), serde_json::Value::Object(patch_fields)) => {
+			for (key, patch_field) in patch_fields {
+				merge_value(fields.entry(key.clone()).or_insert(serde_json::Value::Null), patch_field);
+			}
+		},
+		(value, patch) => *value = patch.clone(),
+	}
+}
+
+fn apply_override<T>(part: &T, patch: Option<&serde_json::Value>) -> anyhow::Result<T>
+where
+	T: Clone + Serialize + serde::de::DeserializeOwned,
+{
+	let patch = match patch {
+		Some(patch) => patch,
+		None => return Ok(part.clone()),
+	};
+
+	let mut value = serde_json::to_value(part)?;
+	merge_value(&mut value, patch);
+
+	Ok(serde_json::from_value(value)?)
+}
+
+/// Agent settings for baking the navigation mesh from the map's static
+/// colliders
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MapNavigation {
+	/// Distance agents keep from walls and edges, 0.4 by default
+	pub agent_radius: Option<f32>,
+	/// Headroom agents need above the ground, 1.8 by default
+	pub agent_height: Option<f32>,
+	/// Steepest walkable slope in radians, 45 degrees by default
+	pub max_slope: Option<f32>,
+	/// Highest ledge agents step up or down, 0.5 by default
+	pub step_height: Option<f32>
+}
+
+/// A level: templates, the entities made from them, shapes and lights
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, Default, Resource)]
 pub struct Map {
+	/// Format version. Maps without one are version 1 and older maps are
+	/// migrated when loaded.
+	pub version: Option<u32>,
+	/// Other map files merged into this one, relative to this file
+	pub includes: Option<Vec<String>>,
 	pub entities: Option<Vec<MapEntity>>,
 	pub templates: Option<Vec<MapTemplate>>,
+	pub materials: Option<Vec<MapMaterial>>,
 	pub shapes: Option<Vec<MapShape>>,
 	pub lights: Option<Vec<Light>>,
+	pub prefabs: Option<Vec<MapPrefab>>,
+	/// Prefabs placed in the map
+	pub instances: Option<Vec<MapPrefabInstance>>,
 	pub ambient_light: Option<AmbientLight>,
-	pub camera: Option<MapCamera>
+	pub camera: Option<MapCamera>,
+	/// Bakes a navigation mesh from the static geometry when set
+	pub navigation: Option<MapNavigation>
 }
 
 impl Map {
+	/// Loads a map together with the files it includes.
 	pub fn load(path: &str) -> anyhow::Result<Map> {
-		let map = std::fs::read_to_string(path)?;
-		Map::parse(&map)
+		let validated = crate::map_validation::load_and_validate(path, None);
+
+		match validated.map {
+			Some(map) => Ok(map),
+			None => match validated.diagnostics.errors().next() {
+				Some(error) => anyhow::bail!("{}", error),
+				None => anyhow::bail!("could not load map {}", path),
+			}
+		}
 	}
 
+	/// Parses a single JSON map document, migrating it to the current version.
 	pub fn parse(json_str: &str) -> anyhow::Result<Map> {
-		let map: Map = serde_json::from_str(&json_str)?;
+		Map::parse_as(json_str, MapFormat::Json)
+	}
+
+	/// Parses a single map document in the given format, migrating it to the
+	/// current version.
+	pub fn parse_as(source: &str, format: MapFormat) -> anyhow::Result<Map> {
+		let mut value = format.parse(source)?;
+
+		let map: Map = match migrate(&mut value)? {
+			MAP_VERSION => format.deserialize(source)?,
+			_ => serde_json::from_value(value)?,
+		};
 		Ok(map)
 	}
+
+	/// Serializes the map in the same layout as the hand written maps,
+	/// leaving out unset fields.
+	pub fn to_json(&self) -> anyhow::Result<String> {
+		let mut value = serde_json::to_value(self)?;
+		strip_nulls(&mut value);
+
+		to_json_string(&value)
+	}
+
+	/// Serializes the map in the given format, leaving out unset fields.
+	pub fn to_format(&self, format: MapFormat) -> anyhow::Result<String> {
+		let mut value = serde_json::to_value(self)?;
+		strip_nulls(&mut value);
+
+		format.serialize(&value)
+	}
+
+	/// Writes the map in the format of the file extension.
+	pub fn save(&self, path: &str) -> anyhow::Result<()> {
+		std::fs::write(path, self.to_format(MapFormat::from_path(path))?)?;
+		Ok(())
+	}
+}
+
+/// How an asset of the map is loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapAssetKind {
+	Gltf,
+	Image,
+}
+
+/// A file in the assets directory that the map refers to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapAsset {
+	pub path: String,
+	pub kind: MapAssetKind,
+	/// What refers to the asset, e.g. "template `orc`"
+	pub used_by: Vec<String>,
+}
+
+impl Map {
+	/// Every asset the map refers to, once per path in the order they
+	/// first appear
+	pub fn assets(&self) -> Vec<MapAsset> {
+		let mut assets: Vec<MapAsset> = Vec::new();
+
+		let mut add = |path: &str, kind: MapAssetKind, user: String| {
+			match assets.iter_mut().find(|asset| asset.path == path) {
+				Some(asset) => asset.used_by.push(user),
+				None => assets.push(MapAsset {
+					path: path.to_string(),
+					kind,
+					used_by: vec![user],
+				}),
+			}
+		};
+
+		for template in self.templates.iter().flatten() {
+			if let Some(asset) = &template.asset {
+				add(asset, MapAssetKind::Gltf, format!("template `{}`", template.name));
+			}
+		}
+
+		for material in self.materials.iter().flatten() {
+			for (_, texture) in material.textures() {
+				add(texture, MapAssetKind::Image, format!("material `{}`", material.name));
+			}
+		}
+
+		let prefab_shapes = self.prefabs.iter().flatten().flat_map(|prefab| prefab.shapes.iter().flatten());
+
+		for shape in self.shapes.iter().flatten().chain(prefab_shapes) {
+			if let MapShapeType::Terrain(terrain) = &shape.shape {
+				let user = match shape.id.is_empty() {
+					true => "terrain".to_string(),
+					false => format!("terrain `{}`", shape.id),
+				};
+
+				add(&terrain.heightmap, MapAssetKind::Image, user.clone());
+
+				if let Some(splat_map) = &terrain.splat_map {
+					add(splat_map, MapAssetKind::Image, user);
+				}
+			}
+		}
+
+		assets
+	}
+}
+
+/// Pretty prints JSON like the hand written maps: indented with tabs and
+/// with arrays of numbers, such as positions, on one line.
+pub fn to_json_string(value: &serde_json::Value) -> anyhow::Result<String> {
+	let mut json = String::new();
+	write_json(&mut json, value, 0)?;
+
+	Ok(json)
+}
+
+fn write_json(json: &mut String, value: &serde_json::Value, depth: usize) -> anyhow::Result<()> {
+	let indent = |depth: usize| "\t".repeat(depth);
+
+	match value {
+		serde_json::Value::Object(fields) if !fields.is_empty() => {
+			json.push_str("{\n");
+
+			for (index, (key, field)) in fields.iter().enumerate() {
+				json.push_str(&indent(depth + 1));
+				json.push_str(&serde_json::to_string(key)?);
+				json.push_str(": ");
+				write_json(json, field, depth + 1)?;
+				json.push_str(if index + 1 < fields.len() { ",\n" } else { "\n" });
+			}
+
+			json.push_str(&indent(depth));
+			json.push('}');
+		},
+		serde_json::Value::Array(items) if items.iter().any(|item| item.is_object() || item.is_array()) => {
+			json.push_str("[\n");
+
+			for (index, item) in items.iter().enumerate() {
+				json.push_str(&indent(depth + 1));
+				write_json(json, item, depth + 1)?;
+				json.push_str(if index + 1 < items.len() { ",\n" } else { "\n" });
+			}
+
+			json.push_str(&indent(depth));
+			json.push(']');
+		},
+		serde_json::Value::Array(items) => {
+			let items = items
+				.iter()
+				.map(serde_json::to_string)
+				.collect::<Result<Vec<_>, _>>()?;
+
+			json.push('[');
+			json.push_str(&items.join(", "));
+			json.push(']');
+		},
+		_ => json.push_str(&serde_json::to_string(value)?),
+	}
+
+	Ok(())
+}
+
+fn strip_nulls(value: &mut serde_json::Value) {
+	match value {
+		serde_json::Value::Object(fields) => {
+			fields.retain(|_, field| !field.is_null());
+			fields.values_mut().for_each(strip_nulls);
+		},
+		serde_json::Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+		_ => {}
+	}
 }
 
 #[derive(Debug, Clone)]
@@ -212,14 +1123,23 @@ pub enum MapChange {
 	UpdateMapEntity(MapEntity),
 	RemoveMapEntity(String),
 	NewMapTemplate(MapTemplate),
-	UpdateMaptemplate(MapTemplate),
+	UpdateMapTemplate(MapTemplate),
 	RemoveMapTemplate(String),
+	NewMaterial(MapMaterial),
+	UpdateMaterial(MapMaterial),
+	RemoveMaterial(String),
 	NewMapShape(MapShape),
 	UpdateMapShape(MapShape),
 	RemoveMapShape(String),
 	NewLight(Light),
 	UpdateLight(Light),
 	RemoveLight(String),
+	NewPrefab(MapPrefab),
+	UpdatePrefab(MapPrefab),
+	RemovePrefab(String),
+	NewPrefabInstance(MapPrefabInstance),
+	UpdatePrefabInstance(MapPrefabInstance),
+	RemovePrefabInstance(String),
 	NewAmbientLight(AmbientLight),
 	UpdateAmbientLight(AmbientLight),
 	RemoveAmbientLight,
@@ -241,3 +1161,194 @@ impl MapChanges {
 	}
 }
 
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn template(name: &str, extends: Option<&str>) -> MapTemplate {
+		MapTemplate {
+			name: name.to_string(),
+			extends: extends.map(|e| e.to_string()),
+			..Default::default()
+		}
+	}
+
+	fn weapon(animation: &str) -> Weapon {
+		Weapon {
+			weapon_type: WeaponType::Melee,
+			animation: Some(animation.to_string()),
+			damage: None,
+			range: None,
+			duration: None,
+			ammo: None,
+		}
+	}
+
+	fn resolve(templates: &[MapTemplate], name: &str) -> Result<MapTemplate, TemplateResolveError> {
+		resolve_template(name, |n| templates.iter().find(|t| t.name == n))
+	}
+
+	#[test]
+	fn inherits_fields_from_ancestors() {
+		let mut base = template("base", None);
+		base.physics = Some(MapEntityPhysics::Dynamic);
+		base.walk_speed = Some(1.0);
+		base.asset = Some("base.glb".to_string());
+
+		let mut orc = template("orc", Some("base"));
+		orc.walk_speed = Some(2.0);
+
+		let mut big_orc = template("big_orc", Some("orc"));
+		big_orc.asset = Some("big_orc.glb".to_string());
+
+		let templates = vec![base, orc, big_orc];
+
+		let resolved = resolve(&templates, "big_orc").unwrap();
+
+		assert_eq!(resolved.name, "big_orc");
+		assert_eq!(resolved.extends, Some("orc".to_string()));
+		assert_eq!(resolved.asset, Some("big_orc.glb".to_string()));
+		assert_eq!(resolved.walk_speed, Some(2.0));
+		assert_eq!(resolved.physics, Some(MapEntityPhysics::Dynamic));
+	}
+
+	#[test]
+	fn merges_weapons() {
+		let mut base = template("base", None);
+		base.weapons = vec![weapon("hit")];
+
+		let inherited = template("inherited", Some("base"));
+
+		let mut replaced = template("replaced", Some("base"));
+		replaced.weapons = vec![weapon("shoot")];
+
+		let mut appended = template("appended", Some("base"));
+		appended.weapons = vec![weapon("shoot")];
+		appended.weapons_merge = WeaponsMerge::Append;
+
+		let templates = vec![base, inherited, replaced, appended];
+
+		assert_eq!(resolve(&templates, "inherited").unwrap().weapons, vec![weapon("hit")]);
+		assert_eq!(resolve(&templates, "replaced").unwrap().weapons, vec![weapon("shoot")]);
+		assert_eq!(resolve(&templates, "appended").unwrap().weapons, vec![weapon("hit"), weapon("shoot")]);
+	}
+
+	#[test]
+	fn reports_missing_parents_and_cycles() {
+		let templates = vec![
+			template("orphan", Some("nobody")),
+			template("a", Some("b")),
+			template("b", Some("a")),
+		];
+
+		assert_eq!(resolve(&templates, "orphan"), Err(TemplateResolveError::MissingParent {
+			template: "orphan".to_string(),
+			parent: "nobody".to_string(),
+		}));
+		assert_eq!(resolve(&templates, "a"), Err(TemplateResolveError::Cycle(vec![
+			"a".to_string(),
+			"b".to_string(),
+			"a".to_string(),
+		])));
+	}
+
+	#[test]
+	fn round_trips_through_json() {
+		let map = Map::parse(include_str!("../config/map.json")).unwrap();
+		let json = map.to_json().unwrap();
+
+		assert!(!json.contains("null"));
+		assert_eq!(Map::parse(&json).unwrap().to_json().unwrap(), json);
+	}
+
+	#[test]
+	fn applies_entity_overrides() {
+		let mut orc = template("orc", None);
+		orc.walk_speed = Some(1.0);
+		orc.mass = Some(5.0);
+
+		let entity = MapEntity {
+			template: "orc".to_string(),
+			overrides: Some(MapTemplate {
+				walk_speed: Some(3.0),
+				..Default::default()
+			}),
+			..Default::default()
+		};
+
+		let resolved = orc.for_entity(&entity);
+
+		assert_eq!(resolved.name, "orc");
+		assert_eq!(resolved.walk_speed, Some(3.0));
+		assert_eq!(resolved.mass, Some(5.0));
+	}
+
+	#[test]
+	fn instantiates_prefabs_with_overrides() {
+		let map = Map::parse(r#"{
+			"prefabs": [{
+				"name": "torch",
+				"shapes": [{ "id": "post", "shape": { "type": "Cube", "size": 0.2 } }],
+				"lights": [{ "id": "flame", "type": { "type": "Point", "color": "ffaa00", "location": [0, 1, 0] } }]
+			}],
+			"instances": [{
+				"id": "torch-1",
+				"prefab": "torch",
+				"overrides": { "flame": { "type": { "color": "ff0000" } } }
+			}]
+		}"#).unwrap();
+
+		let prefab = &map.prefabs.as_ref().unwrap()[0];
+		let instance = &map.instances.as_ref().unwrap()[0];
+
+		let parts = prefab.instantiate(instance).unwrap();
+
+		assert_eq!(parts.shapes[0].id, "torch-1/post");
+		assert_eq!(parts.lights[0].id, "torch-1/flame");
+
+		match &parts.lights[0].light_type {
+			LightType::Point(point) => {
+				assert_eq!(point.color, "ff0000");
+				assert_eq!(point.location, Some([0.0, 1.0, 0.0]));
+			},
+			light_type => panic!("unexpected light {:?}", light_type),
+		}
+
+		let mut invalid = instance.clone();
+		invalid.overrides = Some(BTreeMap::from([("post".to_string(), serde_json::json!({ "shape": { "size": "big" } }))]));
+
+		assert!(prefab.instantiate(&invalid).is_err());
+	}
+
+	#[test]
+	fn lists_every_asset_once() {
+		let map = Map::parse(r#"{
+			"templates": [{ "name": "orc", "asset": "orkki.glb" }, { "name": "big_orc", "asset": "orkki.glb" }],
+			"materials": [{ "name": "stone", "base_color_texture": "stone.png", "normal_map_texture": "stone_normal.png" }],
+			"shapes": [{ "id": "hills", "shape": { "type": "Terrain", "heightmap": "hills.png", "size": [10, 10], "height_scale": 2, "splat_map": "stone.png" } }]
+		}"#).unwrap();
+
+		let assets = map.assets();
+		let paths: Vec<&str> = assets.iter().map(|asset| asset.path.as_str()).collect();
+
+		assert_eq!(paths, vec!["orkki.glb", "stone.png", "stone_normal.png", "hills.png"]);
+		assert_eq!(assets[0].kind, MapAssetKind::Gltf);
+		assert_eq!(assets[0].used_by, vec!["template `orc`", "template `big_orc`"]);
+		assert_eq!(assets[1].kind, MapAssetKind::Image);
+		assert_eq!(assets[1].used_by, vec!["material `stone`", "terrain `hills`"]);
+	}
+
+	#[test]
+	#[cfg(feature = "game")]
+	fn uses_the_shadow_cascade_defaults_of_bevy() {
+		let cascades = MapShadowCascades::default().to_builder().unwrap();
+		let bevy = CascadeShadowConfigBuilder::default();
+
+		assert_eq!(cascades.num_cascades, bevy.num_cascades);
+		assert_eq!(cascades.minimum_distance, bevy.minimum_distance);
+		assert_eq!(cascades.maximum_distance, bevy.maximum_distance);
+		assert_eq!(cascades.first_cascade_far_bound, bevy.first_cascade_far_bound);
+		assert_eq!(cascades.overlap_proportion, bevy.overlap_proportion);
+	}
+}
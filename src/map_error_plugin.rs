@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+
+use crate::constants::TEXT_COLOR;
+use crate::despawn::despawn_screen;
+use crate::map_validation::MapDiagnostics;
+use crate::types::GameState;
+
+// This plugin shows the map diagnostics instead of the game when the map failed validation
+pub struct MapErrorPlugin;
+
+impl Plugin for MapErrorPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.add_startup_system(log_map_diagnostics)
+			.add_system(leave_game_on_map_errors.in_schedule(OnEnter(GameState::Loading)))
+			.add_system(map_error_setup.in_schedule(OnEnter(GameState::MapError)))
+			.add_system(despawn_screen::<OnMapErrorScreen>.in_schedule(OnExit(GameState::MapError)));
+	}
+}
+
+// Tag component used to tag entities added on the map error screen
+#[derive(Component)]
+struct OnMapErrorScreen;
+
+pub fn map_is_valid(diagnostics: Option<Res<MapDiagnostics>>) -> bool {
+	match diagnostics {
+		Some(diagnostics) => !diagnostics.has_errors(),
+		None => true,
+	}
+}
+
+fn log_map_diagnostics(diagnostics: Option<Res<MapDiagnostics>>) {
+	if let Some(diagnostics) = diagnostics {
+		diagnostics.log();
+	}
+}
+
+fn leave_game_on_map_errors(
+	diagnostics: Option<Res<MapDiagnostics>>,
+	mut game_state: ResMut<NextState<GameState>>,
+) {
+	if let Some(diagnostics) = diagnostics {
+		if diagnostics.has_errors() {
+			game_state.set(GameState::MapError);
+		}
+	}
+}
+
+fn map_error_setup(
+	mut commands: Commands,
+	asset_server: Res<AssetServer>,
+	diagnostics: Res<MapDiagnostics>,
+) {
+	let font = asset_server.load("FiraSans-Bold.ttf");
+
+	commands.spawn((Camera2dBundle::default(), OnMapErrorScreen));
+
+	commands.spawn((
+		NodeBundle {
+			style: Style {
+				size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+				flex_direction: FlexDirection::Column,
+				padding: UiRect::all(Val::Px(40.0)),
+				..default()
+			},
+			background_color: Color::rgb(0.15, 0.05, 0.05).into(),
+			..default()
+		},
+		OnMapErrorScreen,
+	)).with_children(|parent| {
+		parent.spawn(
+			TextBundle::from_section(
+				"The map could not be loaded",
+				TextStyle {
+					font: font.clone(),
+					font_size: 40.0,
+					color: TEXT_COLOR,
+				},
+			).with_style(Style {
+				margin: UiRect::bottom(Val::Px(20.0)),
+				..default()
+			})
+		);
+
+		for diagnostic in diagnostics.errors() {
+			parent.spawn(
+				TextBundle::from_section(
+					diagnostic.to_string(),
+					TextStyle {
+						font: font.clone(),
+						font_size: 18.0,
+						color: TEXT_COLOR,
+					},
+				)
+			);
+		}
+	});
+}
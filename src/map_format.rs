@@ -0,0 +1,415 @@
+use std::fmt;
+use std::path::Path;
+
+use ron::extensions::Extensions;
+use ron::ser::PrettyConfig;
+use serde::de;
+use serde::de::DeserializeOwned;
+use serde::de::DeserializeSeed;
+use serde::de::Deserializer;
+use serde::de::IgnoredAny;
+use serde::de::MapAccess;
+use serde::de::SeqAccess;
+use serde::de::Visitor;
+use serde_json::Value;
+
+use crate::map::to_json_string;
+use crate::map::Map;
+use crate::map_migration::map_version;
+use crate::map_migration::MAP_VERSION;
+
+/// File formats maps can be written in. All of them are read into the JSON
+/// data model, so migrations and validation work the same for each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapFormat {
+	Json,
+	Ron,
+	Yaml
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatError {
+	pub message: String,
+	/// 0 when the error has no position
+	pub line: usize,
+	pub column: usize,
+}
+
+impl fmt::Display for FormatError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.message)
+	}
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<serde_json::Error> for FormatError {
+	fn from(err: serde_json::Error) -> Self {
+		FormatError {
+			message: err.to_string(),
+			line: err.line(),
+			column: err.column(),
+		}
+	}
+}
+
+impl From<serde_yaml::Error> for FormatError {
+	fn from(err: serde_yaml::Error) -> Self {
+		let (line, column) = match err.location() {
+			Some(location) => (location.line(), location.column()),
+			None => (0, 0)
+		};
+
+		FormatError {
+			message: err.to_string(),
+			line,
+			column,
+		}
+	}
+}
+
+impl From<ron::error::SpannedError> for FormatError {
+	fn from(err: ron::error::SpannedError) -> Self {
+		FormatError {
+			message: format!("{} at line {} column {}", err.code, err.position.line, err.position.col),
+			line: err.position.line,
+			column: err.position.col,
+		}
+	}
+}
+
+impl MapFormat {
+	/// Format of a map file by its extension, JSON unless it is `.ron`,
+	/// `.yaml` or `.yml`.
+	pub fn from_path(path: &str) -> MapFormat {
+		match Path::new(path).extension().and_then(|e| e.to_str()) {
+			Some("ron") => MapFormat::Ron,
+			Some("yaml") | Some("yml") => MapFormat::Yaml,
+			_ => MapFormat::Json,
+		}
+	}
+
+	/// Parses a document into a JSON value. RON enum values written as bare
+	/// identifiers have no name without the map types and read as null.
+	pub fn parse(&self, source: &str) -> Result<Value, FormatError> {
+		match self {
+			MapFormat::Json => Ok(serde_json::from_str(source)?),
+			MapFormat::Ron => {
+				// Struct fields are identifiers that don't deserialize as
+				// strings, so go through the RON data model
+				let ron: ron::Value = ron::Options::default().from_str(source)?;
+				let value = serde_json::to_value(&ron)?;
+
+				// Older maps are deserialized from the migrated value, which
+				// would lose the enum values
+				if has_unit(&ron) && map_version(&value).is_ok_and(|version| version < MAP_VERSION) {
+					return Err(FormatError {
+						message: format!(
+							"RON maps older than version {} need enum values as strings, e.g. `physics: \"Dynamic\"`",
+							MAP_VERSION
+						),
+						line: 0,
+						column: 0,
+					});
+				}
+
+				Ok(value)
+			},
+			MapFormat::Yaml => Ok(serde_yaml::from_str(source)?),
+		}
+	}
+
+	/// Deserializes a document from its source, so that errors have a
+	/// position.
+	pub fn deserialize<T: DeserializeOwned>(&self, source: &str) -> Result<T, FormatError> {
+		match self {
+			MapFormat::Json => Ok(serde_json::from_str(source)?),
+			MapFormat::Ron => Ok(ron::Options::default().from_str(source)?),
+			MapFormat::Yaml => Ok(serde_yaml::from_str(source)?),
+		}
+	}
+
+	/// Line and column the parser reports for the value at a JSON pointer, or
+	/// for its key in an object.
+	pub fn position(&self, source: &str, pointer: &str) -> Option<(usize, usize)> {
+		let segments = pointer_segments(pointer);
+		let seek = Seek(&segments);
+
+		match self {
+			MapFormat::Json => {
+				let err = seek.deserialize(&mut serde_json::Deserializer::from_str(source)).err()?;
+				found_at(&err, err.line(), err.column())
+			},
+			MapFormat::Ron => {
+				let err = ron::Options::default().from_str_seed(source, seek).err()?;
+				found_at(&err.code, err.position.line, err.position.col)
+			},
+			MapFormat::Yaml => {
+				let err = seek.deserialize(serde_yaml::Deserializer::from_str(source)).err()?;
+				let location = err.location()?;
+				found_at(&err, location.line(), location.column())
+			}
+		}
+	}
+
+	/// Writes a document in this format, JSON and RON indented with tabs.
+	pub fn serialize(&self, value: &Value) -> anyhow::Result<String> {
+		match self {
+			MapFormat::Json => Ok(to_json_string(value)? + "\n"),
+			MapFormat::Ron => Ok(to_ron_string(value)? + "\n"),
+			MapFormat::Yaml => Ok(serde_yaml::to_string(value)?),
+		}
+	}
+}
+
+pub(crate) fn escape_pointer(key: &str) -> String {
+	key.replace('~', "~0").replace('/', "~1")
+}
+
+/// Bare identifiers, such as enum values, read as units without the map
+/// types
+fn has_unit(value: &ron::Value) -> bool {
+	match value {
+		ron::Value::Unit => true,
+		ron::Value::Option(Some(value)) => has_unit(value),
+		ron::Value::Seq(items) => items.iter().any(has_unit),
+		ron::Value::Map(fields) => fields.iter().any(|(_, field)| has_unit(field)),
+		_ => false,
+	}
+}
+
+/// Writes a map as RON with tabs, structs in parentheses and enum values as
+/// bare identifiers.
+pub fn to_ron_string(value: &Value) -> anyhow::Result<String> {
+	let map: Map = serde_json::from_value(value.clone())?;
+	let config = PrettyConfig::new()
+		.indentor("\t".to_string())
+		.extensions(Extensions::IMPLICIT_SOME);
+	let ron = ron::ser::to_string_pretty(&map, config)?;
+
+	// Unset fields are written as `field: None`, leave them out like in the
+	// other formats
+	let lines: Vec<&str> = ron.lines().filter(|line| !is_unset_field(line)).collect();
+	Ok(lines.join("\n"))
+}
+
+fn is_unset_field(line: &str) -> bool {
+	line.trim_start().strip_suffix(": None,")
+		.is_some_and(|field| field.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// Expected value of `Found`, to tell its error from errors in the document
+const FOUND: &str = "the value at the pointer";
+
+/// Follows the segments of a JSON pointer through a document and fails at
+/// the value they point to, so that the parser reports its position.
+struct Seek<'a>(&'a [String]);
+
+impl<'de, 'a> DeserializeSeed<'de> for Seek<'a> {
+	type Value = ();
+
+	fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+		match self.0.is_empty() {
+			true => deserializer.deserialize_any(Found),
+			false => deserializer.deserialize_any(self),
+		}
+	}
+}
+
+impl<'de, 'a> Visitor<'de> for Seek<'a> {
+	type Value = ();
+
+	fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("an object or an array")
+	}
+
+	fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+		// The last segment fails at the key, where the member starts
+		let last = match self.0.len() {
+			1 => Some(self.0[0].as_str()),
+			_ => None,
+		};
+
+		while let Some(key) = map.next_key_seed(Key(last))? {
+			if key == self.0[0] {
+				return map.next_value_seed(Seek(&self.0[1..]));
+			}
+			map.next_value::<IgnoredAny>()?;
+		}
+		Ok(())
+	}
+
+	fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+		let Ok(index) = self.0[0].parse::<usize>() else {
+			return Ok(());
+		};
+		for _ in 0..index {
+			if seq.next_element::<IgnoredAny>()?.is_none() {
+				return Ok(());
+			}
+		}
+		seq.next_element_seed(Seek(&self.0[1..]))?;
+		Ok(())
+	}
+
+	fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+		deserializer.deserialize_any(self)
+	}
+
+	fn visit_newtype_struct<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+		deserializer.deserialize_any(self)
+	}
+}
+
+/// Key of an object or a RON struct field, failing like `Found` if it is
+/// the given one
+struct Key<'a>(Option<&'a str>);
+
+impl<'de, 'a> DeserializeSeed<'de> for Key<'a> {
+	type Value = String;
+
+	fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<String, D::Error> {
+		deserializer.deserialize_str(self)
+	}
+}
+
+impl<'de, 'a> Visitor<'de> for Key<'a> {
+	type Value = String;
+
+	fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("a key")
+	}
+
+	fn visit_str<E: de::Error>(self, key: &str) -> Result<String, E> {
+		match self.0 == Some(key) {
+			true => Err(E::custom(format_args!("expected {}", FOUND))),
+			false => Ok(key.to_string()),
+		}
+	}
+}
+
+/// Rejects any value with an error expecting `FOUND`
+struct Found;
+
+impl<'de> Visitor<'de> for Found {
+	type Value = ();
+
+	fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(FOUND)
+	}
+}
+
+/// Position of the error `Seek` failed with, if it reached the value
+fn found_at(error: impl fmt::Display, line: usize, column: usize) -> Option<(usize, usize)> {
+	error.to_string().contains(FOUND).then_some((line, column))
+}
+
+fn pointer_segments(pointer: &str) -> Vec<String> {
+	pointer.split('/').skip(1).map(|segment| segment.replace("~1", "/").replace("~0", "~")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use serde_json::json;
+
+	use crate::map::MapCuboidCollider;
+	use crate::map::MapEntityCollider;
+	use crate::map::MapEntityPhysics;
+
+	#[test]
+	fn parses_ron() {
+		let source = r#"#![enable(implicit_some)]
+(
+	// Comments are allowed
+	version: 5,
+	templates: [
+		MapTemplate(
+			name: "orc",
+			physics: Dynamic,
+			collider: (type: "Cuboid", x: 0.5, y: 1, z: 0.5),
+			initial_transform: (0, -1.0, 0),
+		),
+	],
+	/* and block comments */
+	camera: (camera_type: None, entity_id: r"player"),
+)"#;
+
+		let map: Map = MapFormat::Ron.deserialize(source).unwrap();
+		let template = &map.templates.as_ref().unwrap()[0];
+
+		assert_eq!(template.physics, Some(MapEntityPhysics::Dynamic));
+		assert_eq!(template.collider, Some(MapEntityCollider::Cuboid(MapCuboidCollider { x: 0.5, y: 1.0, z: 0.5 })));
+		assert_eq!(template.initial_transform, Some([0.0, -1.0, 0.0]));
+		assert_eq!(map.camera.unwrap().entity_id, "player");
+		assert_eq!(MapFormat::Ron.parse(source).unwrap()["templates"][0]["name"], json!("orc"));
+	}
+
+	#[test]
+	fn reports_ron_syntax_errors() {
+		let err = MapFormat::Ron.parse("(\n\tversion: 4\n\ttemplates: [],\n)").unwrap_err();
+
+		assert_eq!((err.line, err.column), (3, 2));
+	}
+
+	#[test]
+	fn rejects_bare_enum_values_in_older_ron_maps() {
+		assert!(MapFormat::Ron.parse("(version: 4, templates: [(name: \"orc\", physics: Dynamic)])").is_err());
+		assert!(MapFormat::Ron.parse("(version: 4, templates: [(name: \"orc\", physics: \"Dynamic\")])").is_ok());
+		assert!(MapFormat::Ron.parse("(version: 5, templates: [(name: \"orc\", physics: Dynamic)])").is_ok());
+	}
+
+	#[test]
+	fn writes_ron_that_reads_back() {
+		let value = json!({
+			"version": 5,
+			"templates": [{
+				"name": "orc \"the\" one",
+				"initial_transform": [0, -1.5, 0],
+				"physics": "Kinematic",
+				"collider": { "type": "AABB", "padding": 0.5 }
+			}],
+			"entities": [{ "template": "orc", "move_cycle": [[0, 0, 0], [1, 0, 1]] }]
+		});
+
+		let ron = to_ron_string(&value).unwrap();
+
+		assert!(ron.contains("\n\ttemplates: [\n\t\t(\n\t\t\tname: "));
+		assert!(ron.contains("physics: Kinematic,"));
+		assert!(!ron.contains("None"));
+
+		let map: Map = MapFormat::Ron.deserialize(&ron).unwrap();
+		assert_eq!(map, serde_json::from_value(value).unwrap());
+	}
+
+	#[test]
+	fn finds_positions_in_every_format() {
+		let json = "{\n\t\"version\": 5,\n\t\"templates\": [\n\t\t{ \"name\": \"orc\", \"speed\": 2 }\n\t]\n}";
+		let ron = "(\n\tversion: 5,\n\ttemplates: [\n\t\t(name: \"orc\", speed: 2),\n\t],\n)";
+		let yaml = "\
+# A map
+version: 5
+templates:
+- name: orc
+  description: |
+    speed: not a key
+  speed: 2
+";
+
+		assert_eq!(MapFormat::Json.position(json, "/templates/0/speed"), Some((4, 26)));
+		assert_eq!(MapFormat::Ron.position(ron, "/templates/0/speed"), Some((4, 22)));
+		assert_eq!(MapFormat::Yaml.position(yaml, "/templates/0/speed"), Some((7, 3)));
+		assert_eq!(MapFormat::Yaml.position(yaml, "/version"), Some((2, 1)));
+		assert_eq!(MapFormat::Json.position(json, "/templates/1"), None);
+		assert_eq!(MapFormat::Yaml.position(yaml, "/templates/0/weapon"), None);
+	}
+
+	#[test]
+	fn detects_format_by_extension() {
+		assert_eq!(MapFormat::from_path("config/map.json"), MapFormat::Json);
+		assert_eq!(MapFormat::from_path("config/map.ron"), MapFormat::Ron);
+		assert_eq!(MapFormat::from_path("levels/cave.yml"), MapFormat::Yaml);
+		assert_eq!(MapFormat::from_path("levels/cave.yaml"), MapFormat::Yaml);
+	}
+}
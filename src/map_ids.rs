@@ -0,0 +1,279 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::map::Light;
+use crate::map::Map;
+use crate::map::MapEntity;
+use crate::map::MapPrefab;
+use crate::map::MapPrefabInstance;
+use crate::map::MapShape;
+
+const ENTITY_PREFIX: &str = "entity-";
+const SHAPE_PREFIX: &str = "shape-";
+const LIGHT_PREFIX: &str = "light-";
+const INSTANCE_PREFIX: &str = "instance-";
+
+/// FNV-1a, which unlike the std hashers is guaranteed to give the same hash
+/// on every platform and Rust version
+fn fnv1a(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+		(hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+	})
+}
+
+fn content_hash<T: Serialize>(value: &T) -> String {
+	let json = serde_json::to_string(value).unwrap_or_default();
+
+	format!("{:08x}", fnv1a(json.as_bytes()) as u32)
+}
+
+/// Whether an id was generated by `assign_ids`, e.g. `entity-1f3a09bc` or
+/// `shape-1f3a09bc-2`
+pub fn is_generated_id(id: &str) -> bool {
+	let hash = [ENTITY_PREFIX, SHAPE_PREFIX, LIGHT_PREFIX, INSTANCE_PREFIX]
+		.iter()
+		.find_map(|prefix| id.strip_prefix(prefix));
+
+	let hash = match hash {
+		Some(hash) => hash,
+		None => return false,
+	};
+
+	let (hash, suffix) = match hash.split_once('-') {
+		Some((hash, suffix)) => (hash, suffix),
+		None => (hash, "1"),
+	};
+
+	hash.len() == 8
+		&& hash.chars().all(|c| c.is_ascii_hexdigit())
+		&& !suffix.is_empty()
+		&& suffix.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Gives the first free id of `prefix` and the content hash, numbering
+/// identical objects `-2`, `-3`, ...
+fn unique_id(used_ids: &mut HashSet<String>, prefix: &str, hash: &str) -> String {
+	let mut id = format!("{}{}", prefix, hash);
+	let mut number = 1;
+
+	while used_ids.contains(&id) {
+		number += 1;
+		id = format!("{}{}-{}", prefix, hash, number);
+	}
+
+	used_ids.insert(id.clone());
+
+	id
+}
+
+/// Gives ids to the parts of a prefab that have none. They only need to be
+/// unique within the prefab.
+fn assign_part_ids(prefab: &mut MapPrefab) {
+	let mut used_ids: HashSet<String> = prefab.part_ids().map(|id| id.to_string()).collect();
+
+	for entity in prefab.entities.iter_mut().flatten() {
+		if entity.entity_id.is_empty() {
+			entity.entity_id = unique_id(&mut used_ids, ENTITY_PREFIX, &content_hash(entity));
+		}
+	}
+
+	for shape in prefab.shapes.iter_mut().flatten() {
+		if shape.id.is_empty() {
+			shape.id = unique_id(&mut used_ids, SHAPE_PREFIX, &content_hash(shape));
+		}
+	}
+
+	for light in prefab.lights.iter_mut().flatten() {
+		if light.id.is_empty() {
+			light.id = unique_id(&mut used_ids, LIGHT_PREFIX, &content_hash(light));
+		}
+	}
+}
+
+/// Gives ids to the entities, shapes, lights and prefab instances of the map
+/// that have none. The ids are derived from their content, so that they stay
+/// the same when objects are reordered or others are added or removed. They
+/// never collide with the ids given in the map, of any kind of object.
+pub fn assign_ids(map: &mut Map) {
+	let mut used_ids: HashSet<String> = HashSet::new();
+
+	used_ids.extend(map.entities.iter().flatten().map(|e| e.entity_id.clone()));
+	used_ids.extend(map.shapes.iter().flatten().map(|s| s.id.clone()));
+	used_ids.extend(map.lights.iter().flatten().map(|l| l.id.clone()));
+	used_ids.extend(map.instances.iter().flatten().map(|i| i.id.clone()));
+
+	for entity in map.entities.iter_mut().flatten() {
+		if entity.entity_id.is_empty() {
+			entity.entity_id = unique_id(&mut used_ids, ENTITY_PREFIX, &content_hash(entity));
+		}
+	}
+
+	for shape in map.shapes.iter_mut().flatten() {
+		if shape.id.is_empty() {
+			shape.id = unique_id(&mut used_ids, SHAPE_PREFIX, &content_hash(shape));
+		}
+	}
+
+	for light in map.lights.iter_mut().flatten() {
+		if light.id.is_empty() {
+			light.id = unique_id(&mut used_ids, LIGHT_PREFIX, &content_hash(light));
+		}
+	}
+
+	for instance in map.instances.iter_mut().flatten() {
+		if instance.id.is_empty() {
+			instance.id = unique_id(&mut used_ids, INSTANCE_PREFIX, &content_hash(instance));
+		}
+	}
+
+	for prefab in map.prefabs.iter_mut().flatten() {
+		assign_part_ids(prefab);
+	}
+}
+
+/// Content hashes change when an object is edited. Pairs the objects with
+/// generated ids that disappeared from the last map with the new ones that
+/// appeared, in order and if they are the same kind of object, and keeps
+/// their last id so that the edit is an update instead of a remove and add.
+fn carry_over<T>(
+	last: &[T],
+	new: &mut [T],
+	id: impl Fn(&T) -> &str,
+	set_id: impl Fn(&mut T, String),
+	same_kind: impl Fn(&T, &T) -> bool,
+) {
+	let new_ids: HashSet<String> = new.iter().map(|o| id(o).to_string()).collect();
+	let last_ids: HashSet<String> = last.iter().map(|o| id(o).to_string()).collect();
+
+	let mut vanished = last
+		.iter()
+		.filter(|o| is_generated_id(id(o)) && !new_ids.contains(id(o)))
+		.collect::<Vec<_>>();
+
+	for object in new.iter_mut() {
+		if !is_generated_id(id(object)) || last_ids.contains(id(object)) {
+			continue;
+		}
+
+		if let Some(index) = vanished.iter().position(|last| same_kind(last, object)) {
+			let last = vanished.remove(index);
+
+			set_id(object, id(last).to_string());
+		}
+	}
+}
+
+/// Keeps the generated ids of edited objects from the last map, see
+/// `carry_over`.
+pub fn carry_over_ids(last_map: &Map, new_map: &mut Map) {
+	if let (Some(last), Some(new)) = (&last_map.entities, &mut new_map.entities) {
+		carry_over(
+			last,
+			new,
+			|e: &MapEntity| &e.entity_id,
+			|e, id| e.entity_id = id,
+			|a, b| a.template == b.template,
+		);
+	}
+
+	if let (Some(last), Some(new)) = (&last_map.shapes, &mut new_map.shapes) {
+		carry_over(
+			last,
+			new,
+			|s: &MapShape| &s.id,
+			|s, id| s.id = id,
+			|a, b| std::mem::discriminant(&a.shape) == std::mem::discriminant(&b.shape),
+		);
+	}
+
+	if let (Some(last), Some(new)) = (&last_map.lights, &mut new_map.lights) {
+		carry_over(
+			last,
+			new,
+			|l: &Light| &l.id,
+			|l, id| l.id = id,
+			|a, b| std::mem::discriminant(&a.light_type) == std::mem::discriminant(&b.light_type),
+		);
+	}
+
+	if let (Some(last), Some(new)) = (&last_map.instances, &mut new_map.instances) {
+		carry_over(
+			last,
+			new,
+			|i: &MapPrefabInstance| &i.id,
+			|i, id| i.id = id,
+			|a, b| a.prefab == b.prefab,
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(source: &str) -> Map {
+		let mut map = Map::parse(source).unwrap();
+		assign_ids(&mut map);
+
+		map
+	}
+
+	fn entity_ids(map: &Map) -> Vec<&str> {
+		map.entities.iter().flatten().map(|e| e.entity_id.as_str()).collect()
+	}
+
+	#[test]
+	fn keeps_ids_when_reordered_or_inserted() {
+		let map = parse(r#"{"entities": [
+			{ "template": "orc", "initial_position": [0, 0, 0] },
+			{ "template": "troll", "initial_position": [1, 0, 0] }
+		]}"#);
+		let reordered = parse(r#"{"entities": [
+			{ "template": "goblin" },
+			{ "template": "troll", "initial_position": [1, 0, 0] },
+			{ "template": "orc", "initial_position": [0, 0, 0] }
+		]}"#);
+
+		let ids = entity_ids(&map);
+
+		assert!(ids.iter().all(|id| is_generated_id(id)));
+		assert_eq!(entity_ids(&reordered)[1..], [ids[1], ids[0]]);
+	}
+
+	#[test]
+	fn numbers_duplicates_and_avoids_given_ids() {
+		let first = parse(r#"{"shapes": [{ "shape": { "type": "Cube", "size": 1 } }]}"#);
+		let generated = first.shapes.as_ref().unwrap()[0].id.clone();
+
+		let map = parse(&format!(r#"{{
+			"entities": [{{ "entity_id": "{}", "template": "orc" }}],
+			"shapes": [
+				{{ "shape": {{ "type": "Cube", "size": 1 }} }},
+				{{ "shape": {{ "type": "Cube", "size": 1 }} }}
+			]
+		}}"#, generated));
+
+		let shape_ids = map.shapes.iter().flatten().map(|s| s.id.as_str()).collect::<Vec<_>>();
+
+		assert_eq!(shape_ids, [format!("{}-2", generated), format!("{}-3", generated)]);
+		assert!(is_generated_id(shape_ids[0]));
+		assert!(!is_generated_id("entity-player"));
+	}
+
+	#[test]
+	fn carries_ids_of_edited_objects() {
+		let last = parse(r#"{"entities": [
+			{ "template": "orc", "initial_position": [0, 0, 0] },
+			{ "template": "troll", "initial_position": [1, 0, 0] }
+		]}"#);
+		let mut new = parse(r#"{"entities": [
+			{ "template": "troll", "initial_position": [1, 5, 0] },
+			{ "template": "orc", "initial_position": [0, 0, 0] }
+		]}"#);
+
+		carry_over_ids(&last, &mut new);
+
+		assert_eq!(entity_ids(&new), [entity_ids(&last)[1], entity_ids(&last)[0]]);
+	}
+}
@@ -9,35 +9,23 @@ use notify::RecursiveMode;
 use notify::Watcher;
 
 use crate::map::Map;
+use crate::constants::ASSETS_DIR;
 use crate::map::MapChange;
+use crate::map_ids::carry_over_ids;
+use crate::map_validation::load_and_validate;
+use crate::map_validation::Severity;
 
 fn emit_changes(
 	last_map: &Map, 
 	new_map: &mut Map,
 	tx: &mpsc::Sender<MapChange>
 ) {
-	match new_map.entities {
-		Some(ref mut entities) => {
-			let mut used_ids = HashSet::new();
-
-			entities.iter().for_each(|e| {
-				if e.entity_id != "" {
-					used_ids.insert(e.entity_id.clone());
-				}
-			});
-			
-			for (index, entity) in entities.iter_mut().enumerate() {
-				if entity.entity_id == "" {
-					loop {
-						let new_id = (index + 1).to_string();
-
-						if !used_ids.contains(&new_id) {
-							entity.entity_id = new_id;
-							break;
-						}
-					}
-				}
+	// Objects without an id got one from their content during validation
+	carry_over_ids(last_map, new_map);
 
+	match new_map.entities {
+		Some(ref entities) => {
+			for entity in entities.iter() {
 				let last_map_entity = match &last_map.entities {
 					Some(last_entities) => {
 						last_entities.iter().find(|e| e.entity_id == entity.entity_id)
@@ -60,17 +48,29 @@ fn emit_changes(
 					}
 				}
 			}
+
+			if let Some(last_entities) = &last_map.entities {
+				for last_entity in last_entities {
+					if !entities.iter().any(|e| e.entity_id == last_entity.entity_id) {
+						log::debug!("emit entity remove {}", last_entity.entity_id);
+
+						tx.send(MapChange::RemoveMapEntity(last_entity.entity_id.clone())).unwrap();
+					}
+				}
+			}
 		},
 		None => {
-			for entity in last_map.entities.as_ref().unwrap() {
-				tx.send(MapChange::RemoveMapEntity(entity.entity_id.clone())).unwrap();
+			if let Some(last_entities) = &last_map.entities {
+				for entity in last_entities {
+					tx.send(MapChange::RemoveMapEntity(entity.entity_id.clone())).unwrap();
+				}
 			}
 		}
 	}
 
 	match new_map.templates {
 		Some(ref mut templates) => {
-			for template in templates {
+			for template in templates.iter() {
 				let last_map_template = match &last_map.templates {
 					Some(last_templates) => {
 						last_templates.iter().find(|t| t.name == template.name)
@@ -81,7 +81,7 @@ fn emit_changes(
 				match last_map_template {
 					Some(last_template) => {
 						if last_template != template {
-							tx.send(MapChange::UpdateMaptemplate(template.clone())).unwrap();
+							tx.send(MapChange::UpdateMapTemplate(template.clone())).unwrap();
 						}
 					},
 					None => {
@@ -89,36 +89,66 @@ fn emit_changes(
 					}
 				}
 			}
+
+			if let Some(last_templates) = &last_map.templates {
+				for last_template in last_templates {
+					if !templates.iter().any(|t| t.name == last_template.name) {
+						tx.send(MapChange::RemoveMapTemplate(last_template.name.clone())).unwrap();
+					}
+				}
+			}
 		},
 		None => {
-			for template in last_map.templates.as_ref().unwrap() {
-				tx.send(MapChange::RemoveMapTemplate(template.name.clone())).unwrap();
+			if let Some(last_templates) = &last_map.templates {
+				for template in last_templates {
+					tx.send(MapChange::RemoveMapTemplate(template.name.clone())).unwrap();
+				}
 			}
 		}
 	}
 
-	match new_map.shapes {
-		Some(ref mut shapes) => {
-			let mut used_ids = HashSet::new();
+	match new_map.materials {
+		Some(ref materials) => {
+			for material in materials.iter() {
+				let last_material = match &last_map.materials {
+					Some(last_materials) => {
+						last_materials.iter().find(|m| m.name == material.name)
+					},
+					None => None
+				};
 
-			shapes.iter().for_each(|s| {
-				if s.id != "" {
-					used_ids.insert(s.id.clone());
+				match last_material {
+					Some(last_material) => {
+						if last_material != material {
+							tx.send(MapChange::UpdateMaterial(material.clone())).unwrap();
+						}
+					},
+					None => {
+						tx.send(MapChange::NewMaterial(material.clone())).unwrap();
+					}
 				}
-			});
-
-			for (index, shape) in shapes.iter_mut().enumerate() {
-				if shape.id == "" {
-					loop {
-						let new_id = (index + 1).to_string();
+			}
 
-						if !used_ids.contains(&new_id) {
-							shape.id = new_id;
-							break;
-						}
+			if let Some(last_materials) = &last_map.materials {
+				for last_material in last_materials {
+					if !materials.iter().any(|m| m.name == last_material.name) {
+						tx.send(MapChange::RemoveMaterial(last_material.name.clone())).unwrap();
 					}
 				}
+			}
+		},
+		None => {
+			if let Some(last_materials) = &last_map.materials {
+				for material in last_materials {
+					tx.send(MapChange::RemoveMaterial(material.name.clone())).unwrap();
+				}
+			}
+		}
+	}
 
+	match new_map.shapes {
+		Some(ref shapes) => {
+			for shape in shapes.iter() {
 				let existing_shape = match &last_map.shapes {
 					Some(last_shapes) => {
 						last_shapes.iter().find(|s| s.id == shape.id)
@@ -137,36 +167,27 @@ fn emit_changes(
 					}
 				}
 			}
+
+			if let Some(last_shapes) = &last_map.shapes {
+				for last_shape in last_shapes {
+					if !shapes.iter().any(|s| s.id == last_shape.id) {
+						tx.send(MapChange::RemoveMapShape(last_shape.id.clone())).unwrap();
+					}
+				}
+			}
 		},
 		None => {
-			for shape in last_map.shapes.as_ref().unwrap() {
-				tx.send(MapChange::RemoveMapShape(shape.id.clone())).unwrap();
+			if let Some(last_shapes) = &last_map.shapes {
+				for shape in last_shapes {
+					tx.send(MapChange::RemoveMapShape(shape.id.clone())).unwrap();
+				}
 			}
 		}
 	}
 
 	match new_map.lights {
-		Some(ref mut lights) => {
-			let mut used_ids = HashSet::new();
-
-			lights.iter().for_each(|l| {
-				if l.id != "" {
-					used_ids.insert(l.id.clone());
-				}
-			});
-
-			for (index, light) in lights.iter_mut().enumerate() {
-				if light.id == "" {
-					loop {
-						let new_id = (index + 1).to_string();
-
-						if !used_ids.contains(&new_id) {
-							light.id = new_id;
-							break;
-						}
-					}
-				}
-
+		Some(ref lights) => {
+			for light in lights.iter() {
 				let existing_light = match &last_map.lights {
 					Some(last_lights) => {
 						last_lights.iter().find(|l| l.id == light.id)
@@ -185,30 +206,120 @@ fn emit_changes(
 					}
 				}
 			}
+
+			if let Some(last_lights) = &last_map.lights {
+				for last_light in last_lights {
+					if !lights.iter().any(|l| l.id == last_light.id) {
+						tx.send(MapChange::RemoveLight(last_light.id.clone())).unwrap();
+					}
+				}
+			}
 		},
 		None => {
-			for light in last_map.lights.as_ref().unwrap() {
-				tx.send(MapChange::RemoveLight(light.id.clone())).unwrap();
+			if let Some(last_lights) = &last_map.lights {
+				for light in last_lights {
+					tx.send(MapChange::RemoveLight(light.id.clone())).unwrap();
+				}
 			}
 		}
 	};
 
+	match new_map.prefabs {
+		Some(ref prefabs) => {
+			for prefab in prefabs.iter() {
+				let last_prefab = match &last_map.prefabs {
+					Some(last_prefabs) => {
+						last_prefabs.iter().find(|p| p.name == prefab.name)
+					},
+					None => None
+				};
+
+				match last_prefab {
+					Some(last_prefab) => {
+						if last_prefab != prefab {
+							tx.send(MapChange::UpdatePrefab(prefab.clone())).unwrap();
+						}
+					},
+					None => {
+						tx.send(MapChange::NewPrefab(prefab.clone())).unwrap();
+					}
+				}
+			}
+
+			if let Some(last_prefabs) = &last_map.prefabs {
+				for last_prefab in last_prefabs {
+					if !prefabs.iter().any(|p| p.name == last_prefab.name) {
+						tx.send(MapChange::RemovePrefab(last_prefab.name.clone())).unwrap();
+					}
+				}
+			}
+		},
+		None => {
+			if let Some(last_prefabs) = &last_map.prefabs {
+				for prefab in last_prefabs {
+					tx.send(MapChange::RemovePrefab(prefab.name.clone())).unwrap();
+				}
+			}
+		}
+	}
+
+	match new_map.instances {
+		Some(ref instances) => {
+			for instance in instances.iter() {
+				let last_instance = match &last_map.instances {
+					Some(last_instances) => {
+						last_instances.iter().find(|i| i.id == instance.id)
+					},
+					None => None
+				};
+
+				match last_instance {
+					Some(last_instance) => {
+						if last_instance != instance {
+							tx.send(MapChange::UpdatePrefabInstance(instance.clone())).unwrap();
+						}
+					},
+					None => {
+						tx.send(MapChange::NewPrefabInstance(instance.clone())).unwrap();
+					}
+				}
+			}
+
+			if let Some(last_instances) = &last_map.instances {
+				for last_instance in last_instances {
+					if !instances.iter().any(|i| i.id == last_instance.id) {
+						tx.send(MapChange::RemovePrefabInstance(last_instance.id.clone())).unwrap();
+					}
+				}
+			}
+		},
+		None => {
+			if let Some(last_instances) = &last_map.instances {
+				for instance in last_instances {
+					tx.send(MapChange::RemovePrefabInstance(instance.id.clone())).unwrap();
+				}
+			}
+		}
+	}
+
 
 	match new_map.ambient_light {
 		Some(ref mut light) => {
 			match &last_map.ambient_light {
 				Some(last_ambient_light) => {
 					if last_ambient_light != light {
-						tx.send(MapChange::UpdateAmbientLight(light.clone()));
+						tx.send(MapChange::UpdateAmbientLight(light.clone())).unwrap();
 					}
 				},
 				None => {
-					tx.send(MapChange::NewAmbientLight(light.clone()));
+					tx.send(MapChange::NewAmbientLight(light.clone())).unwrap();
 				}
 			}
 		},
 		None =>  {
-			tx.send(MapChange::RemoveAmbientLight);
+			if last_map.ambient_light.is_some() {
+				tx.send(MapChange::RemoveAmbientLight).unwrap();
+			}
 		},
 	}
 
@@ -218,20 +329,81 @@ fn emit_changes(
 			match &last_map.camera {
 				Some(last_camera) => {
 					if last_camera != camera {
-						tx.send(MapChange::UpdateCamera(camera.clone()));
+						tx.send(MapChange::UpdateCamera(camera.clone())).unwrap();
 					}
 				},
 				None => {
-					tx.send(MapChange::NewCamera(camera.clone()));
+					tx.send(MapChange::NewCamera(camera.clone())).unwrap();
 				}
 			}
 		},
 		None =>  {
-			tx.send(MapChange::RemoveCamera);
+			if last_map.camera.is_some() {
+				tx.send(MapChange::RemoveCamera).unwrap();
+			}
 		},
 	}
 }
 
+/// Loads and validates the map, printing its diagnostics. Maps with errors
+/// are rejected so that the running world keeps the last valid state.
+/// Also returns every file of the include graph so they can be watched.
+fn load_valid_map(path: &str) -> (Option<Map>, Vec<String>) {
+	let validated = load_and_validate(path, Some(Path::new(ASSETS_DIR)));
+
+	for diagnostic in &validated.diagnostics.diagnostics {
+		match diagnostic.severity {
+			Severity::Error => log::error!("{}", diagnostic),
+			Severity::Warning => log::warn!("{}", diagnostic),
+		}
+	}
+
+	if validated.diagnostics.has_errors() {
+		log::warn!("map has errors, ignoring changes");
+
+		return (None, validated.files);
+	}
+
+	(validated.map, validated.files)
+}
+
+/// Watches the files that were added to the include graph and stops watching
+/// the ones that were removed from it.
+fn update_watched_files(
+	watcher: &mut impl Watcher,
+	watched: &mut HashSet<String>,
+	files: Vec<String>
+) {
+	let files: HashSet<String> = files.into_iter().collect();
+
+	for file in watched.difference(&files) {
+		println!("removing path from watcher: {}", file);
+
+		if let Err(err) = watcher.unwatch(Path::new(file)) {
+			println!("failed to unwatch {}: {}", file, err);
+		}
+	}
+
+	watched.retain(|file| files.contains(file));
+
+	for file in files {
+		if watched.contains(&file) {
+			continue;
+		}
+
+		println!("adding path to watcher: {}", file);
+
+		// Missing includes are reported by validation, the file is watched
+		// once it appears in a later valid version of the graph.
+		match watcher.watch(Path::new(&file), RecursiveMode::NonRecursive) {
+			Ok(_) => {
+				watched.insert(file);
+			},
+			Err(err) => println!("failed to watch {}: {}", file, err),
+		}
+	}
+}
+
 pub fn create_map_loader(path: &str) -> MapChangesReceiver {
 	let (tx, rx) = std::sync::mpsc::channel();
 
@@ -244,41 +416,34 @@ pub fn create_map_loader(path: &str) -> MapChangesReceiver {
 
 		let path = path;
 
-		match Map::load(&path) {
-			Ok(mut new_map) => {
-				emit_changes(&last_map, &mut new_map, &tx);
+		let (new_map, files) = load_valid_map(&path);
 
-				last_map = new_map;
-			},
-			Err(err) => {
-				println!("error loading map: {}", err);
-			}
-		};
+		if let Some(mut new_map) = new_map {
+			emit_changes(&last_map, &mut new_map, &tx);
+
+			last_map = new_map;
+		}
 
 		println!("starting map loader worker {}", path);
 
 		let (fs_change_tx, fs_change_rx) = std::sync::mpsc::channel();
 		let mut watcher = notify::recommended_watcher(fs_change_tx).unwrap();
+		let mut watched = HashSet::new();
 
-		println!("adding path to watcher: {}", path);
-
-		// Add a path to be watched. All files and directories at that path and
-		// below will be monitored for changes.
-		watcher.watch(Path::new(&path), RecursiveMode::Recursive).unwrap();
+		update_watched_files(&mut watcher, &mut watched, files);
 
 		for fs_change in fs_change_rx {
 			println!("fs change: {:?}", fs_change);
 
-			match Map::load(&path) {
-				Ok(mut new_map) => {
-					emit_changes(&last_map, &mut new_map, &tx);
+			let (new_map, files) = load_valid_map(&path);
 
-					last_map = new_map;
-				},
-				Err(err) => {
-					println!("error loading map: {}", err);
-				}
-			};
+			update_watched_files(&mut watcher, &mut watched, files);
+
+			if let Some(mut new_map) = new_map {
+				emit_changes(&last_map, &mut new_map, &tx);
+
+				last_map = new_map;
+			}
 		}
 	});
 
@@ -291,3 +456,95 @@ pub fn create_map_loader(path: &str) -> MapChangesReceiver {
 pub struct MapChangesReceiver {
 	pub rx: Mutex<mpsc::Receiver<MapChange>>
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::map_ids::assign_ids;
+
+	fn parse(source: &str) -> Map {
+		let mut map = Map::parse(source).unwrap();
+		assign_ids(&mut map);
+
+		map
+	}
+
+	fn changes(last_map: &Map, new_map: &mut Map) -> Vec<MapChange> {
+		let (tx, rx) = mpsc::channel();
+
+		emit_changes(last_map, new_map, &tx);
+
+		rx.try_iter().collect()
+	}
+
+	#[test]
+	fn reordering_changes_nothing() {
+		let last_map = parse(r#"{
+			"entities": [{ "template": "orc" }, { "template": "troll" }],
+			"shapes": [{ "shape": { "type": "Cube", "size": 1 } }, { "shape": { "type": "Cube", "size": 2 } }]
+		}"#);
+		let mut new_map = parse(r#"{
+			"entities": [{ "template": "troll" }, { "template": "orc" }],
+			"shapes": [{ "shape": { "type": "Cube", "size": 2 } }, { "shape": { "type": "Cube", "size": 1 } }]
+		}"#);
+
+		assert!(changes(&last_map, &mut new_map).is_empty());
+	}
+
+	#[test]
+	fn inserting_adds_only_the_new_object() {
+		let last_map = parse(r#"{"entities": [{ "template": "orc" }, { "template": "troll" }]}"#);
+		let mut new_map = parse(r#"{"entities": [{ "template": "goblin" }, { "template": "orc" }, { "template": "troll" }]}"#);
+
+		match changes(&last_map, &mut new_map).as_slice() {
+			[MapChange::NewMapEntity(entity)] => assert_eq!(entity.template, "goblin"),
+			changes => panic!("unexpected changes {:?}", changes),
+		}
+	}
+
+	#[test]
+	fn editing_updates_under_the_same_id() {
+		let last_map = parse(r#"{"lights": [{ "type": { "type": "Point", "color": "ffffff" } }]}"#);
+		let mut new_map = parse(r#"{"lights": [{ "type": { "type": "Point", "color": "ff0000" } }]}"#);
+
+		let last_id = last_map.lights.as_ref().unwrap()[0].id.clone();
+
+		match changes(&last_map, &mut new_map).as_slice() {
+			[MapChange::UpdateLight(light)] => assert_eq!(light.id, last_id),
+			changes => panic!("unexpected changes {:?}", changes),
+		}
+	}
+
+	#[test]
+	fn duplicates_are_told_apart() {
+		let last_map = parse(r#"{"entities": [{ "template": "orc" }, { "template": "orc" }]}"#);
+		let mut new_map = parse(r#"{"entities": [{ "template": "orc" }]}"#);
+
+		let ids = last_map.entities.iter().flatten().map(|e| e.entity_id.clone()).collect::<Vec<_>>();
+
+		assert_ne!(ids[0], ids[1]);
+
+		match changes(&last_map, &mut new_map).as_slice() {
+			[MapChange::RemoveMapEntity(id)] => assert_eq!(id, &ids[1]),
+			changes => panic!("unexpected changes {:?}", changes),
+		}
+	}
+
+	#[test]
+	fn editing_a_prefab_updates_only_the_prefab() {
+		let last_map = parse(r#"{
+			"prefabs": [{ "name": "torch", "shapes": [{ "shape": { "type": "Cube", "size": 1 } }] }],
+			"instances": [{ "prefab": "torch" }, { "prefab": "torch", "transform": { "position": [5, 0, 0] } }]
+		}"#);
+		let mut new_map = parse(r#"{
+			"prefabs": [{ "name": "torch", "shapes": [{ "shape": { "type": "Cube", "size": 2 } }] }],
+			"instances": [{ "prefab": "torch" }, { "prefab": "torch", "transform": { "position": [5, 0, 0] } }]
+		}"#);
+
+		match changes(&last_map, &mut new_map).as_slice() {
+			[MapChange::UpdatePrefab(prefab)] => assert_eq!(prefab.name, "torch"),
+			changes => panic!("unexpected changes {:?}", changes),
+		}
+	}
+}
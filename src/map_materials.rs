@@ -0,0 +1,224 @@
+use bevy::asset::LoadState;
+use bevy::gltf::Gltf;
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+use bevy::render::render_resource::AddressMode;
+use bevy::render::render_resource::SamplerDescriptor;
+use bevy::render::texture::ImageSampler;
+use bevy::utils::HashMap;
+
+use crate::map::MapAlphaMode;
+use crate::map::MapMaterial;
+use crate::types::MapMaterials;
+use crate::types::MaterialOverrides;
+
+pub struct MapMaterialsPlugin;
+
+impl Plugin for MapMaterialsPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.insert_resource(MapMaterials::default())
+			.add_system(apply_material_overrides)
+			.add_system(repeat_material_textures);
+	}
+}
+
+/// Parses a hex color from the map. Invalid colors are reported by map
+/// validation, here they are logged and replaced with white.
+pub fn map_color(hex: &str) -> Color {
+	match Color::hex(hex) {
+		Ok(color) => color,
+		Err(err) => {
+			log::error!("invalid hex color `{}`: {:?}", hex, err);
+
+			Color::WHITE
+		}
+	}
+}
+
+fn standard_material(material: &MapMaterial, asset_server: &AssetServer) -> StandardMaterial {
+	let mut standard_material = StandardMaterial::default();
+
+	if let Some(base_color) = &material.base_color {
+		standard_material.base_color = map_color(base_color);
+	}
+
+	if let Some(emissive) = &material.emissive {
+		standard_material.emissive = map_color(emissive);
+	}
+
+	if let Some(metallic) = material.metallic {
+		standard_material.metallic = metallic;
+	}
+
+	if let Some(roughness) = material.roughness {
+		standard_material.perceptual_roughness = roughness;
+	}
+
+	if let Some(double_sided) = material.double_sided {
+		standard_material.double_sided = double_sided;
+
+		if double_sided {
+			standard_material.cull_mode = None;
+		}
+	}
+
+	if let Some(unlit) = material.unlit {
+		standard_material.unlit = unlit;
+	}
+
+	if let Some(alpha_mode) = material.alpha_mode {
+		standard_material.alpha_mode = match alpha_mode {
+			MapAlphaMode::Opaque => AlphaMode::Opaque,
+			MapAlphaMode::Mask => AlphaMode::Mask(material.alpha_cutoff.unwrap_or(0.5)),
+			MapAlphaMode::Blend => AlphaMode::Blend,
+			MapAlphaMode::Premultiplied => AlphaMode::Premultiplied,
+			MapAlphaMode::Add => AlphaMode::Add,
+			MapAlphaMode::Multiply => AlphaMode::Multiply,
+		};
+	}
+
+	let load = |texture: &Option<String>| texture.as_ref().map(|path| asset_server.load(path.as_str()));
+
+	standard_material.base_color_texture = load(&material.base_color_texture);
+	standard_material.normal_map_texture = load(&material.normal_map_texture);
+	standard_material.metallic_roughness_texture = load(&material.metallic_roughness_texture);
+	standard_material.emissive_texture = load(&material.emissive_texture);
+
+	standard_material
+}
+
+/// Adds a material, or updates it in place so that every shape and entity
+/// using it changes too.
+pub fn insert_material(
+	map_materials: &mut MapMaterials,
+	materials: &mut Assets<StandardMaterial>,
+	asset_server: &AssetServer,
+	material: &MapMaterial,
+) {
+	let standard_material = standard_material(material, asset_server);
+
+	if material.uv_scale.is_some() {
+		map_materials.pending_repeat.extend(
+			[
+				&standard_material.base_color_texture,
+				&standard_material.normal_map_texture,
+				&standard_material.metallic_roughness_texture,
+				&standard_material.emissive_texture,
+			]
+				.into_iter()
+				.flatten()
+				.cloned()
+		);
+	}
+
+	match map_materials.handles.get(&material.name) {
+		Some(handle) => {
+			log::info!("updating material {}", material.name);
+
+			if let Some(existing) = materials.get_mut(handle) {
+				*existing = standard_material;
+			}
+		},
+		None => {
+			log::info!("adding material {}", material.name);
+
+			map_materials.handles.insert(material.name.clone(), materials.add(standard_material));
+		}
+	}
+
+	map_materials.definitions.insert(material.name.clone(), material.clone());
+}
+
+pub fn remove_material(map_materials: &mut MapMaterials, name: &str) {
+	log::info!("removing material {}", name);
+
+	map_materials.definitions.remove(name);
+	map_materials.handles.remove(name);
+}
+
+/// Multiplies the texture coordinates of a mesh, so that textures repeat
+/// `scale` times across it.
+pub fn scale_uvs(mesh: &mut Mesh, scale: [f32; 2]) {
+	if let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0) {
+		for uv in uvs.iter_mut() {
+			uv[0] *= scale[0];
+			uv[1] *= scale[1];
+		}
+	}
+}
+
+/// Replaces the materials of spawned glTF scenes with map materials
+fn apply_material_overrides(
+	mut commands: Commands,
+	query: Query<(Entity, &MaterialOverrides)>,
+	children: Query<&Children>,
+	mut mesh_materials: Query<&mut Handle<StandardMaterial>>,
+	gltfs: Res<Assets<Gltf>>,
+	map_materials: Res<MapMaterials>,
+) {
+	for (entity, material_overrides) in query.iter() {
+		let gltf = match gltfs.get(&material_overrides.gltf) {
+			Some(gltf) => gltf,
+			None => continue,
+		};
+
+		let gltf_names: HashMap<&Handle<StandardMaterial>, &str> = gltf.named_materials
+			.iter()
+			.map(|(name, handle)| (handle, name.as_str()))
+			.collect();
+
+		let mut scene_spawned = false;
+
+		for descendant in children.iter_descendants(entity) {
+			let mut handle = match mesh_materials.get_mut(descendant) {
+				Ok(handle) => handle,
+				Err(_) => continue,
+			};
+
+			scene_spawned = true;
+
+			let map_material = gltf_names
+				.get(&*handle)
+				.and_then(|name| material_overrides.overrides.get(*name))
+				.or_else(|| material_overrides.overrides.get("*"));
+
+			match map_material.map(|name| (name, map_materials.handles.get(name))) {
+				Some((_, Some(map_handle))) => *handle = map_handle.clone(),
+				Some((name, None)) => log::error!("unknown material `{}`", name),
+				None => {}
+			}
+		}
+
+		// The scene spawns all of its meshes at once
+		if scene_spawned {
+			commands.entity(entity).remove::<MaterialOverrides>();
+		}
+	}
+}
+
+/// Makes the textures of materials with a `uv_scale` repeat instead of
+/// stretching their edges.
+fn repeat_material_textures(
+	mut map_materials: ResMut<MapMaterials>,
+	mut images: ResMut<Assets<Image>>,
+	asset_server: Res<AssetServer>,
+) {
+	if map_materials.pending_repeat.is_empty() {
+		return;
+	}
+
+	map_materials.pending_repeat.retain(|handle| {
+		if let Some(image) = images.get_mut(handle) {
+			image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+				address_mode_u: AddressMode::Repeat,
+				address_mode_v: AddressMode::Repeat,
+				..ImageSampler::linear_descriptor()
+			});
+
+			return false;
+		}
+
+		asset_server.get_load_state(handle) != LoadState::Failed
+	});
+}
@@ -0,0 +1,324 @@
+use std::fmt;
+
+use serde_json::Value;
+
+/// Version of the map format written by this build. Maps without a
+/// `version` field are version 1.
+pub const MAP_VERSION: u32 = 5;
+
+/// Upgrades a map document from the version at the same index + 1 to the
+/// next one.
+const MIGRATIONS: [fn(&mut Value); (MAP_VERSION - 1) as usize] = [
+	rename_idle_animation,
+	tag_colliders,
+	combine_rotations,
+	group_transforms,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MigrationError {
+	InvalidVersion(Value),
+	UnsupportedVersion(u32),
+}
+
+impl fmt::Display for MigrationError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			MigrationError::InvalidVersion(version) => {
+				write!(f, "invalid map version {}, expected a positive integer", version)
+			},
+			MigrationError::UnsupportedVersion(version) => {
+				write!(f, "map version {} is newer than the supported version {}", version, MAP_VERSION)
+			},
+		}
+	}
+}
+
+impl std::error::Error for MigrationError {}
+
+pub fn map_version(map: &Value) -> Result<u32, MigrationError> {
+	match map.get("version") {
+		None | Some(Value::Null) => Ok(1),
+		Some(version) => match version.as_u64() {
+			Some(v) if v >= 1 && v <= MAP_VERSION as u64 => Ok(v as u32),
+			Some(v) if v > MAP_VERSION as u64 => Err(MigrationError::UnsupportedVersion(v.min(u32::MAX as u64) as u32)),
+			_ => Err(MigrationError::InvalidVersion(version.clone())),
+		}
+	}
+}
+
+/// Upgrades a map document to `MAP_VERSION` in place. Returns the version
+/// the document had.
+pub fn migrate(map: &mut Value) -> Result<u32, MigrationError> {
+	let version = map_version(map)?;
+
+	for migration in &MIGRATIONS[(version - 1) as usize..] {
+		migration(map);
+	}
+
+	if let Value::Object(fields) = map {
+		// Keep `version` at the top of documents that did not have one
+		let mut versioned = serde_json::Map::new();
+		versioned.insert("version".to_string(), Value::from(MAP_VERSION));
+		versioned.extend(std::mem::take(fields));
+		versioned.insert("version".to_string(), Value::from(MAP_VERSION));
+
+		*fields = versioned;
+	}
+
+	Ok(version)
+}
+
+/// Templates and entity overrides, which share the template fields
+fn for_each_template(map: &mut Value, f: impl Fn(&mut serde_json::Map<String, Value>)) {
+	if let Some(Value::Array(templates)) = map.get_mut("templates") {
+		for template in templates {
+			if let Value::Object(template) = template {
+				f(template);
+			}
+		}
+	}
+
+	if let Some(Value::Array(entities)) = map.get_mut("entities") {
+		for entity in entities {
+			if let Some(Value::Object(overrides)) = entity.get_mut("overrides") {
+				f(overrides);
+			}
+		}
+	}
+}
+
+/// Replaces `fields` with a single field computed from their values. The new
+/// field takes the place of the first replaced one so that rewritten files
+/// keep their layout.
+fn replace_fields(
+	object: &mut serde_json::Map<String, Value>,
+	fields: &[&str],
+	field: &str,
+	value: impl FnOnce(Vec<Option<&Value>>) -> Value,
+) {
+	if !fields.iter().any(|f| object.contains_key(*f)) {
+		return;
+	}
+
+	let new_value = value(fields.iter().map(|f| object.get(*f)).collect());
+	let mut new_value = Some(new_value);
+
+	let mut replaced = serde_json::Map::new();
+
+	for (key, value) in std::mem::take(object) {
+		if fields.contains(&key.as_str()) {
+			if let Some(new_value) = new_value.take() {
+				replaced.insert(field.to_string(), new_value);
+			}
+		} else if key != field {
+			replaced.insert(key, value);
+		}
+	}
+
+	*object = replaced;
+}
+
+/// 1 -> 2: `iddle_animation` is spelled `idle_animation`
+fn rename_idle_animation(map: &mut Value) {
+	for_each_template(map, |template| {
+		replace_fields(template, &["iddle_animation"], "idle_animation", |values| {
+			values[0].cloned().unwrap_or(Value::Null)
+		});
+	});
+}
+
+/// 2 -> 3: colliders are tagged with a `type` field like shapes and lights,
+/// `{ "Cuboid": { "x": 1 } }` becomes `{ "type": "Cuboid", "x": 1 }`
+fn tag_colliders(map: &mut Value) {
+	for_each_template(map, |template| {
+		let collider = match template.get_mut("collider") {
+			Some(collider) => collider,
+			None => return,
+		};
+
+		match collider {
+			Value::String(variant) => {
+				let mut tagged = serde_json::Map::new();
+				tagged.insert("type".to_string(), Value::String(variant.clone()));
+
+				*collider = Value::Object(tagged);
+			},
+			Value::Object(fields) if fields.len() == 1 && !fields.contains_key("type") => {
+				let (variant, body) = fields.iter().next().unwrap();
+
+				let mut tagged = serde_json::Map::new();
+				tagged.insert("type".to_string(), Value::String(variant.clone()));
+
+				if let Value::Object(body) = body {
+					tagged.extend(body.clone());
+				}
+
+				*collider = Value::Object(tagged);
+			},
+			_ => {}
+		}
+	});
+}
+
+/// 3 -> 4: `initial_rotation_x`, `_y` and `_z` in degrees are combined into
+/// `initial_rotation` in radians, like the rotation of entities
+fn combine_rotations(map: &mut Value) {
+	let fields = ["initial_rotation_x", "initial_rotation_y", "initial_rotation_z"];
+
+	for_each_template(map, |template| {
+		replace_fields(template, &fields, "initial_rotation", |degrees| {
+			degrees
+				.iter()
+				.map(|d| Value::from(d.and_then(|d| d.as_f64()).unwrap_or(0.0).to_radians()))
+				.collect()
+		});
+	});
+}
+
+/// 4 -> 5: entities and shapes have a `transform` block. The
+/// `initial_position`, `initial_rotation` and `scale` of entities and the
+/// `location` of planes become its `position`, `rotation` and `scale`.
+fn group_transforms(map: &mut Value) {
+	let fields = ["initial_position", "initial_rotation", "scale"];
+
+	if let Some(Value::Array(entities)) = map.get_mut("entities") {
+		for entity in entities {
+			if let Value::Object(entity) = entity {
+				replace_fields(entity, &fields, "transform", |values| {
+					["position", "rotation", "scale"]
+						.iter()
+						.zip(values)
+						.filter_map(|(field, value)| value.map(|value| (field.to_string(), value.clone())))
+						.collect::<serde_json::Map<_, _>>()
+						.into()
+				});
+			}
+		}
+	}
+
+	if let Some(Value::Array(shapes)) = map.get_mut("shapes") {
+		for shape in shapes {
+			let location = match shape.get_mut("shape") {
+				Some(Value::Object(shape_type)) => shape_type.remove("location"),
+				_ => None,
+			};
+
+			if let (Some(location), Value::Object(shape)) = (location, shape) {
+				let mut transform = serde_json::Map::new();
+				transform.insert("position".to_string(), location);
+
+				shape.insert("transform".to_string(), Value::Object(transform));
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use serde_json::json;
+
+	#[test]
+	fn renames_idle_animation() {
+		let mut map = json!({
+			"templates": [{ "name": "orc", "iddle_animation": "idle" }],
+			"entities": [{ "template": "orc", "overrides": { "iddle_animation": "rest" } }]
+		});
+
+		rename_idle_animation(&mut map);
+
+		assert_eq!(map, json!({
+			"templates": [{ "name": "orc", "idle_animation": "idle" }],
+			"entities": [{ "template": "orc", "overrides": { "idle_animation": "rest" } }]
+		}));
+	}
+
+	#[test]
+	fn tags_colliders() {
+		let mut map = json!({
+			"templates": [
+				{ "name": "a", "collider": { "Cuboid": { "x": 1, "y": 2, "z": 3 } } },
+				{ "name": "b", "collider": "AABB" },
+				{ "name": "c", "collider": { "type": "AABB" } }
+			]
+		});
+
+		tag_colliders(&mut map);
+
+		assert_eq!(map, json!({
+			"templates": [
+				{ "name": "a", "collider": { "type": "Cuboid", "x": 1, "y": 2, "z": 3 } },
+				{ "name": "b", "collider": { "type": "AABB" } },
+				{ "name": "c", "collider": { "type": "AABB" } }
+			]
+		}));
+	}
+
+	#[test]
+	fn combines_rotations() {
+		let mut map = json!({
+			"templates": [
+				{ "name": "a", "initial_rotation_y": 180 },
+				{ "name": "b" }
+			]
+		});
+
+		combine_rotations(&mut map);
+
+		assert_eq!(map, json!({
+			"templates": [
+				{ "name": "a", "initial_rotation": [0.0, 180f64.to_radians(), 0.0] },
+				{ "name": "b" }
+			]
+		}));
+	}
+
+	#[test]
+	fn groups_transforms() {
+		let mut map = json!({
+			"entities": [
+				{ "template": "orc", "initial_position": [1, 0, 2], "player": true, "scale": 2 },
+				{ "template": "orc" }
+			],
+			"shapes": [{ "shape": { "type": "Plane", "size": 10, "location": [0, -1, 0] } }]
+		});
+
+		group_transforms(&mut map);
+
+		assert_eq!(map, json!({
+			"entities": [
+				{ "template": "orc", "transform": { "position": [1, 0, 2], "scale": 2 }, "player": true },
+				{ "template": "orc" }
+			],
+			"shapes": [{
+				"shape": { "type": "Plane", "size": 10 },
+				"transform": { "position": [0, -1, 0] }
+			}]
+		}));
+	}
+
+	#[test]
+	fn migrates_to_latest_version() {
+		let mut map = json!({
+			"templates": [{ "name": "orc", "iddle_animation": "idle", "collider": "AABB" }]
+		});
+
+		assert_eq!(migrate(&mut map), Ok(1));
+		assert_eq!(map["version"], json!(MAP_VERSION));
+		assert_eq!(map["templates"][0]["collider"], json!({ "type": "AABB" }));
+
+		let migrated = map.clone();
+
+		assert_eq!(migrate(&mut map), Ok(MAP_VERSION));
+		assert_eq!(map, migrated);
+	}
+
+	#[test]
+	fn rejects_unknown_versions() {
+		assert_eq!(migrate(&mut json!({ "version": 99 })), Err(MigrationError::UnsupportedVersion(99)));
+		assert_eq!(migrate(&mut json!({ "version": "2" })), Err(MigrationError::InvalidVersion(json!("2"))));
+		assert_eq!(migrate(&mut json!({ "version": 0 })), Err(MigrationError::InvalidVersion(json!(0))));
+	}
+}
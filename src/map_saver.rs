@@ -0,0 +1,419 @@
+use std::ffi::OsStr;
+use std::path::Path;
+
+use bevy::ecs::event::ManualEventReader;
+use bevy::prelude::*;
+
+use crate::console_plugin::ConsoleCommand;
+use crate::map;
+use crate::map::LightType;
+use crate::map::Map;
+use crate::map::MapCamera;
+use crate::map::MapEntity;
+use crate::map::MapRotation;
+use crate::map::MapScale;
+use crate::map::MapTransform;
+use crate::map_ids::is_generated_id;
+use crate::map_migration::MAP_VERSION;
+use crate::types::GameEntity;
+use crate::types::MapCameraSource;
+use crate::types::MapEntitySource;
+use crate::types::MapLightSource;
+use crate::types::MapMaterials;
+use crate::types::MapPrefabInstanceSource;
+use crate::types::MapPrefabs;
+use crate::types::MapShapeSource;
+use crate::types::MapTemplates;
+use crate::types::Navigation;
+use crate::types::NeedsCamera;
+use crate::types::PrefabPart;
+
+const SAVED_MAP_PATH: &str = "./config/saved_map.json";
+
+pub struct MapSaverPlugin;
+
+impl Plugin for MapSaverPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_system(handle_save_command);
+	}
+}
+
+/// Where `save [name]` writes: `config/<name>.json`, or `config/saved_map.json`
+/// without a name. Names with an extension such as `level.ron` keep it. Names
+/// must be plain file names, so nothing is written outside `config/`.
+fn save_path(name: Option<&str>) -> Result<String, String> {
+	match name {
+		Some(name) if Path::new(name).file_name() != Some(OsStr::new(name)) => {
+			Err(format!("`{}` is not a file name", name))
+		},
+		Some(name) if Path::new(name).extension().is_some() => Ok(format!("./config/{}", name)),
+		Some(name) => Ok(format!("./config/{}.json", name)),
+		None => Ok(SAVED_MAP_PATH.to_string()),
+	}
+}
+
+/// Saves the world on the `save [name]` console command, see `save_path`.
+/// Maps split with `includes` are saved flattened into a single file.
+fn handle_save_command(
+	world: &mut World,
+	mut reader: Local<ManualEventReader<ConsoleCommand>>,
+) {
+	let paths: Vec<Result<String, String>> = {
+		let events = world.resource::<Events<ConsoleCommand>>();
+
+		reader.iter(events)
+			.filter(|command| command.name == "save")
+			.map(|command| save_path(command.args.first().map(|name| name.as_str())))
+			.collect()
+	};
+
+	for path in paths {
+		let path = match path {
+			Ok(path) => path,
+			Err(err) => {
+				log::error!("can't save the map: {}", err);
+				continue;
+			},
+		};
+
+		let map = world_to_map(world);
+
+		match map.save(&path) {
+			Ok(_) => log::info!("saved map to {}", path),
+			Err(err) => log::error!("failed to save map to {}: {}", path, err),
+		}
+	}
+}
+
+fn vec3_to_array(vec: Vec3) -> [f32; 3] {
+	[vec.x, vec.y, vec.z]
+}
+
+/// Keeps the value from the map while it still matches the live one, so
+/// that unset fields stay unset.
+fn live_value<T: PartialEq + Copy>(original: Option<T>, live: T, default: T) -> Option<T> {
+	match original {
+		Some(original) if original == live => Some(original),
+		None if live == default => None,
+		_ => Some(live)
+	}
+}
+
+/// Keeps the parts of a map transform that still match the live transform,
+/// so that e.g. Euler angles are not rewritten as a quaternion.
+fn live_transform(original: Option<MapTransform>, live: &Transform) -> Option<MapTransform> {
+	let original = original.unwrap_or_default();
+
+	let rotation = match original.rotation {
+		Some(rotation) if rotation.to_quat().abs_diff_eq(live.rotation, 1e-6) => Some(rotation),
+		None if live.rotation.abs_diff_eq(Quat::IDENTITY, 1e-6) => None,
+		_ => Some(MapRotation::Quaternion(live.rotation.to_array())),
+	};
+
+	let scale = match original.scale {
+		Some(scale) if scale.to_vec3() == live.scale => Some(scale),
+		None if live.scale == Vec3::ONE => None,
+		_ if live.scale == Vec3::splat(live.scale.x) => Some(MapScale::Uniform(live.scale.x)),
+		_ => Some(MapScale::PerAxis(live.scale.to_array())),
+	};
+
+	let transform = MapTransform {
+		position: live_value(original.position, vec3_to_array(live.translation), [0.0, 0.0, 0.0]),
+		rotation,
+		scale,
+	};
+
+	if transform == MapTransform::default() {
+		None
+	} else {
+		Some(transform)
+	}
+}
+
+fn color_to_hex(color: Color) -> String {
+	let [r, g, b, a] = color.as_rgba_f32().map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+
+	match a {
+		255 => format!("{:02x}{:02x}{:02x}", r, g, b),
+		_ => format!("{:02x}{:02x}{:02x}{:02x}", r, g, b, a),
+	}
+}
+
+/// Builds a map of the live world: map entities, shapes and prefab instances
+/// at their current transforms, the templates, materials, prefabs and lights
+/// from the map and the current ambient light and camera. Parts of prefab
+/// instances are saved as the instance. Generated ids are left out, like in
+/// the loaded map.
+pub fn world_to_map(world: &mut World) -> Map {
+	let mut entities = world
+		.query_filtered::<(&GameEntity, &Transform, Option<&MapEntitySource>), Without<PrefabPart>>()
+		.iter(world)
+		.map(|(game_entity, transform, source)| {
+			let mut map_entity = match source {
+				Some(source) => source.map_entity.clone(),
+				None => MapEntity {
+					entity_id: game_entity.entity_id.clone(),
+					template: game_entity.template.clone(),
+					..Default::default()
+				}
+			};
+
+			map_entity.transform = live_transform(map_entity.transform, transform);
+			map_entity.max_health = live_value(map_entity.max_health, game_entity.max_health, 100.0);
+			map_entity.npc = live_value(map_entity.npc, game_entity.npc, false);
+
+			if is_generated_id(&map_entity.entity_id) {
+				map_entity.entity_id = String::new();
+			}
+
+			map_entity
+		})
+		.collect::<Vec<_>>();
+
+	entities.sort_by(|a, b| a.entity_id.cmp(&b.entity_id));
+
+	let mut templates = match world.get_resource::<MapTemplates>() {
+		Some(map_templates) => map_templates.definitions.values().cloned().collect(),
+		None => Vec::new()
+	};
+
+	templates.sort_by(|a, b| a.name.cmp(&b.name));
+
+	let mut materials = match world.get_resource::<MapMaterials>() {
+		Some(map_materials) => map_materials.definitions.values().cloned().collect(),
+		None => Vec::new()
+	};
+
+	materials.sort_by(|a, b| a.name.cmp(&b.name));
+
+	let mut shapes = world
+		.query_filtered::<(&MapShapeSource, &Transform), Without<PrefabPart>>()
+		.iter(world)
+		.map(|(source, transform)| {
+			let mut map_shape = source.map_shape.clone();
+
+			map_shape.transform = live_transform(map_shape.transform, transform);
+
+			if is_generated_id(&map_shape.id) {
+				map_shape.id = String::new();
+			}
+
+			map_shape
+		})
+		.collect::<Vec<_>>();
+
+	shapes.sort_by(|a, b| a.id.cmp(&b.id));
+
+	let mut lights = world
+		.query_filtered::<(&MapLightSource, &Transform), Without<PrefabPart>>()
+		.iter(world)
+		.map(|(source, transform)| {
+			let mut light = source.light.clone();
+
+			match &mut light.light_type {
+				LightType::Point(point) => {
+					point.location = live_value(point.location, vec3_to_array(transform.translation), [0.0, 0.0, 0.0]);
+				},
+				LightType::Spot(spot) => {
+					spot.location = live_value(spot.location, vec3_to_array(transform.translation), [0.0, 0.0, 0.0]);
+				},
+				LightType::Directional(_) => {}
+			}
+
+			if is_generated_id(&light.id) {
+				light.id = String::new();
+			}
+
+			light
+		})
+		.collect::<Vec<_>>();
+
+	lights.sort_by(|a, b| a.id.cmp(&b.id));
+
+	let mut prefabs = match world.get_resource::<MapPrefabs>() {
+		Some(map_prefabs) => map_prefabs.definitions.values().cloned().collect(),
+		None => Vec::new()
+	};
+
+	prefabs.sort_by(|a, b| a.name.cmp(&b.name));
+
+	for prefab in prefabs.iter_mut() {
+		for entity in prefab.entities.iter_mut().flatten() {
+			if is_generated_id(&entity.entity_id) {
+				entity.entity_id = String::new();
+			}
+		}
+
+		for shape in prefab.shapes.iter_mut().flatten() {
+			if is_generated_id(&shape.id) {
+				shape.id = String::new();
+			}
+		}
+
+		for light in prefab.lights.iter_mut().flatten() {
+			if is_generated_id(&light.id) {
+				light.id = String::new();
+			}
+		}
+	}
+
+	let mut instances = world
+		.query::<(&MapPrefabInstanceSource, &Transform)>()
+		.iter(world)
+		.map(|(source, transform)| {
+			let mut instance = source.instance.clone();
+
+			instance.transform = live_transform(instance.transform, transform);
+
+			if is_generated_id(&instance.id) {
+				instance.id = String::new();
+			}
+
+			instance
+		})
+		.collect::<Vec<_>>();
+
+	instances.sort_by(|a, b| a.id.cmp(&b.id));
+
+	let ambient_light = match world.get_resource::<AmbientLight>() {
+		Some(ambient_light) => {
+			let default = AmbientLight::default();
+
+			if ambient_light.color == default.color && ambient_light.brightness == default.brightness {
+				None
+			} else {
+				Some(map::AmbientLight {
+					color: color_to_hex(ambient_light.color),
+					brightness: ambient_light.brightness
+				})
+			}
+		},
+		None => None
+	};
+
+	let pending_camera = world
+		.query::<&NeedsCamera>()
+		.iter(world)
+		.map(|needs_camera| MapCamera {
+			entity_id: needs_camera.entity_id.clone(),
+			camera_type: needs_camera.camera_type.clone()
+		})
+		.next();
+
+	let camera = match pending_camera {
+		Some(camera) => Some(camera),
+		None => world
+			.query::<&MapCameraSource>()
+			.iter(world)
+			.map(|source| source.map_camera.clone())
+			.next()
+	};
+
+	Map {
+		version: Some(MAP_VERSION),
+		includes: None,
+		entities: if entities.is_empty() { None } else { Some(entities) },
+		templates: if templates.is_empty() { None } else { Some(templates) },
+		materials: if materials.is_empty() { None } else { Some(materials) },
+		shapes: if shapes.is_empty() { None } else { Some(shapes) },
+		lights: if lights.is_empty() { None } else { Some(lights) },
+		prefabs: if prefabs.is_empty() { None } else { Some(prefabs) },
+		instances: if instances.is_empty() { None } else { Some(instances) },
+		ambient_light,
+		camera,
+		navigation: world.get_resource::<Navigation>().and_then(|navigation| navigation.settings.clone()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::map::MapTemplate;
+
+	#[test]
+	fn snapshots_moved_entities() {
+		let source = r#"{
+	"templates": [{ "name": "orc", "asset": "orkki.glb" }],
+	"entities": [{ "entity_id": "player", "template": "orc", "player": true }],
+	"camera": { "entity_id": "player" }
+}"#;
+		let map = Map::parse(source).unwrap();
+		let map_entity = map.entities.as_ref().unwrap()[0].clone();
+
+		let mut world = World::new();
+
+		let mut map_templates = MapTemplates::default();
+		map_templates.insert(map.templates.as_ref().unwrap()[0].clone());
+		world.insert_resource(map_templates);
+
+		world.spawn((
+			GameEntity {
+				entity_id: "player".to_string(),
+				template: "orc".to_string(),
+				max_health: 100.0,
+				..Default::default()
+			},
+			Transform::from_xyz(1.0, 2.0, 3.0),
+			MapEntitySource { map_entity },
+		));
+		world.spawn(NeedsCamera {
+			entity_id: "player".to_string(),
+			camera_type: None
+		});
+
+		let saved = world_to_map(&mut world);
+		let entity = &saved.entities.as_ref().unwrap()[0];
+
+		assert_eq!(entity.transform, Some(MapTransform {
+			position: Some([1.0, 2.0, 3.0]),
+			..Default::default()
+		}));
+		assert_eq!(entity.player, Some(true));
+		assert_eq!(entity.max_health, None);
+		assert_eq!(saved.templates, Some(vec![MapTemplate {
+			name: "orc".to_string(),
+			asset: Some("orkki.glb".to_string()),
+			..Default::default()
+		}]));
+		assert_eq!(saved.camera.as_ref().map(|c| c.entity_id.as_str()), Some("player"));
+
+		let reparsed = Map::parse(&saved.to_json().unwrap()).unwrap();
+
+		assert_eq!(reparsed.entities, saved.entities);
+		assert!(!saved.to_json().unwrap().contains("null"));
+	}
+
+	#[test]
+	fn keeps_transforms_that_still_match() {
+		let euler = MapTransform {
+			rotation: Some(MapRotation::Euler([0.0, 1.0, 0.0])),
+			scale: Some(MapScale::Uniform(2.0)),
+			..Default::default()
+		};
+		let mut live = euler.to_transform();
+
+		assert_eq!(live_transform(Some(euler), &live), Some(euler));
+
+		live.scale.y = 3.0;
+		live.rotation = Quat::IDENTITY;
+
+		assert_eq!(live_transform(Some(euler), &live), Some(MapTransform {
+			rotation: Some(MapRotation::Quaternion([0.0, 0.0, 0.0, 1.0])),
+			scale: Some(MapScale::PerAxis([2.0, 3.0, 2.0])),
+			..Default::default()
+		}));
+		assert_eq!(live_transform(None, &Transform::IDENTITY), None);
+	}
+
+	#[test]
+	fn saves_only_inside_the_config_directory() {
+		assert_eq!(save_path(None), Ok(SAVED_MAP_PATH.to_string()));
+		assert_eq!(save_path(Some("level")), Ok("./config/level.json".to_string()));
+		assert_eq!(save_path(Some("level.ron")), Ok("./config/level.ron".to_string()));
+
+		for name in ["../../something.json", "maps/level", "/tmp/level", "..", ""] {
+			assert!(save_path(Some(name)).is_err(), "{}", name);
+		}
+	}
+}
@@ -0,0 +1,111 @@
+use schemars::gen::SchemaSettings;
+use schemars::schema::RootSchema;
+
+use crate::map::to_json_string;
+use crate::map::Map;
+
+/// JSON Schema of the map format, generated from the types in `map` so
+/// that it always matches what the game accepts.
+pub fn map_schema() -> RootSchema {
+	SchemaSettings::draft07()
+		.into_generator()
+		.into_root_schema_for::<Map>()
+}
+
+/// The schema formatted like the map files.
+pub fn map_schema_json() -> anyhow::Result<String> {
+	let schema = serde_json::to_value(map_schema())?;
+
+	Ok(to_json_string(&schema)? + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use serde_json::Value;
+
+	use crate::map::MapTemplate;
+	use crate::map::MapEntity;
+	use crate::map_validation::serde_fields;
+
+	fn schema() -> Value {
+		serde_json::to_value(map_schema()).unwrap()
+	}
+
+	fn property_names(schema: &Value) -> Vec<String> {
+		let mut names: Vec<String> = schema["properties"]
+			.as_object()
+			.unwrap()
+			.keys()
+			.cloned()
+			.collect();
+
+		names.sort();
+		names
+	}
+
+	fn field_names(fields: &[&str]) -> Vec<String> {
+		let mut names: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+
+		names.sort();
+		names
+	}
+
+	/// Every `enum` value below `value`, which lists the variants of unit
+	/// enums and the tags of tagged ones
+	fn enum_values(value: &Value, values: &mut Vec<String>) {
+		match value {
+			Value::Object(fields) => {
+				if let Some(Value::Array(variants)) = fields.get("enum") {
+					values.extend(variants.iter().filter_map(|v| v.as_str()).map(|v| v.to_string()));
+				}
+
+				fields.values().for_each(|field| enum_values(field, values));
+			},
+			Value::Array(items) => items.iter().for_each(|item| enum_values(item, values)),
+			_ => {}
+		}
+	}
+
+	#[test]
+	fn describes_every_field() {
+		let schema = schema();
+		let definitions = &schema["definitions"];
+
+		assert_eq!(property_names(&schema), field_names(serde_fields::<Map>()));
+		assert_eq!(property_names(&definitions["MapTemplate"]), field_names(serde_fields::<MapTemplate>()));
+		assert_eq!(property_names(&definitions["MapEntity"]), field_names(serde_fields::<MapEntity>()));
+		assert!(definitions["MapTemplate"]["description"].is_string());
+	}
+
+	#[test]
+	fn lists_enum_variants() {
+		let schema = schema();
+		let definitions = &schema["definitions"];
+
+		let variants = |name: &str| {
+			let mut values = Vec::new();
+			enum_values(&definitions[name], &mut values);
+			values.sort();
+			values
+		};
+
+		assert_eq!(variants("MapShapeType"), vec!["Box", "Circle", "Cube", "Plane", "Quad", "Terrain"]);
+		assert_eq!(variants("LightType"), vec!["Point", "Directional", "Spot"]);
+		assert_eq!(variants("MapEntityCollider"), vec!["AABB", "Capsule", "Cuboid"]);
+		assert_eq!(variants("CameraType"), vec!["FPS", "ThirdPerson"]);
+	}
+
+	#[test]
+	fn covers_the_example_map() {
+		let schema = schema();
+		let map: Value = serde_json::from_str(include_str!("../config/map.json")).unwrap();
+
+		let properties = property_names(&schema);
+
+		for key in map.as_object().unwrap().keys() {
+			assert!(properties.contains(key), "`{}` is missing from the schema", key);
+		}
+	}
+}
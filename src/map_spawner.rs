@@ -1,6 +1,7 @@
 use std::sync::mpsc;
 
 use bevy::ecs::system::EntityCommands;
+use bevy::asset::HandleId;
 use bevy::gltf::Gltf;
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
@@ -10,33 +11,79 @@ use crate::map::Map;
 use crate::map::MapEntityPhysics;
 use crate::map::LightType;
 use crate::map::MapEntityCollider;
+use crate::map::MapAabbCollider;
+use crate::map::MapCapsuleCollider;
+use crate::map::MapCuboidCollider;
+use crate::map::MapCamera;
 use crate::map::MapChange;
 use crate::map::MapEntity;
+use crate::map::MapPrefabInstance;
 use crate::map::MapShape;
 use crate::map::MapShapeType;
 use crate::map::MapTemplate;
+use crate::animations::AnimationEntityLink;
 use crate::map_loader::MapChangesReceiver;
+use crate::map_materials::insert_material;
+use crate::map_materials::map_color;
+use crate::map_materials::remove_material;
+use crate::map_materials::scale_uvs;
+use crate::types::AddBoundingCollider;
 use crate::types::AddCollidingMesh;
+use crate::types::AssetPackReloaded;
 use crate::types::AssetPacks;
+use crate::types::Behavior;
+use crate::types::CollisionMeshCache;
+use crate::types::ComputedBounds;
+use crate::types::CurrentAnimation;
 use crate::types::MoveCycle;
 use crate::types::EntityScene;
 use crate::types::GameEntity;
 use crate::types::GltfRegister;
 use crate::types::Health;
+use crate::types::MapEntitySource;
+use crate::types::MapCameraSource;
+use crate::types::MapLightSource;
+use crate::types::MapMaterials;
+use crate::types::MapPrefabInstanceSource;
+use crate::types::MapPrefabs;
+use crate::types::MapShapeSource;
 use crate::types::MapTemplates;
+use crate::types::MaterialOverrides;
 use crate::types::NPC;
+use crate::types::NavigationPath;
 use crate::types::NeedsAsset;
 use crate::types::NeedsCamera;
+use crate::types::NeedsPlaceholder;
 use crate::types::NeedsTemplate;
+use crate::types::NeedsTerrain;
 use crate::types::PlayerCamera;
 use crate::types::PlayerIds;
 use crate::types::Point;
+use crate::types::PrefabPart;
 use crate::types::StartAnimation;
 use crate::types::Target;
+use crate::types::TargetPosition;
 use crate::types::Tower;
 use crate::types::UnloadedGltfAsset;
 use crate::types::You;
 
+/// The asset of a template with the settings for spawning its scene
+fn needs_asset(template: &MapTemplate) -> Option<NeedsAsset> {
+	let asset = template.asset.as_ref()?;
+
+	Some(NeedsAsset {
+		asset: asset.clone(),
+		collision_mesh: template.collision_mesh(),
+		bounding_collider: match &template.collider {
+			Some(MapEntityCollider::AABB(MapAabbCollider { padding })) => Some(padding.unwrap_or(0.0)),
+			_ => None,
+		},
+		initial_transform: template.initial_transform,
+		initial_rotation: template.initial_rotation,
+		materials: template.materials.clone(),
+	})
+}
+
 fn handle_map_template(
 	entity_commands: &mut EntityCommands,
 	template: &MapTemplate,
@@ -45,33 +92,42 @@ fn handle_map_template(
 ) {
 	game_entity.asset = template.asset.clone();
 
-	match &template.asset {
-		Some(asset) => {
-			entity_commands.insert(NeedsAsset {
-				asset: asset.clone(),
-				add_colliding_mesh: template.automatic_collision_mesh.unwrap_or_default(),
-				initial_transform: template.initial_transform.clone(),
-				initial_rotation_y: template.initial_rotation_y.clone(),
+	match needs_asset(template) {
+		Some(needs_asset) => {
+			entity_commands.insert(needs_asset);
+		},
+		// Logic-only entities such as spawn points and triggers
+		None if cfg!(debug_assertions) && template.placeholder.unwrap_or(true) => {
+			entity_commands.insert(NeedsPlaceholder {
+				collider: template.collider.clone(),
 			});
 		},
 		None => {}
 	}
 
-	game_entity.idle_animation = template.iddle_animation.clone();
+	game_entity.idle_animation = template.idle_animation.clone();
 	game_entity.walk_animation = template.walk_animation.clone();
 	game_entity.run_animation = template.run_animation.clone();
 	game_entity.reload_animation = template.reload_animation.clone();
 	game_entity.shoot_animation = template.shoot_animation.clone();
+	game_entity.walk_speed = template.walk_speed;
+	game_entity.run_speed = template.run_speed;
 
 	game_entity.weapons = template.weapons.clone();
 
+	// Takes over from the `Tower` shooting of NPCs
+	if let Some(behavior) = &template.behavior {
+		entity_commands.insert(Behavior::new(behavior.clone(), map_entity_transform(entity).translation));
+	}
+
 	match &template.collider {
 		Some(collider) => {
 			match collider {
-				MapEntityCollider::AABB => {
-
+				MapEntityCollider::AABB(_) => {
+					// Fitted to the asset's meshes once they are loaded, see
+					// `NeedsAsset::bounding_collider`
 				},
-				MapEntityCollider::Capsule { a, b, radius } => {
+				MapEntityCollider::Capsule(MapCapsuleCollider { a, b, radius }) => {
 					log::info!("spawning capsule collider: {:?} {:?} {:?}", a, b, radius);
 
 					entity_commands.insert((
@@ -79,7 +135,7 @@ fn handle_map_template(
 						Collider::capsule(Vec3::Y * *a, Vec3::Y * *b, *radius)
 					));
 				},
-				MapEntityCollider::Cuboid { x, y, z } => {
+				MapEntityCollider::Cuboid(MapCuboidCollider { x, y, z }) => {
 					log::info!("spawning cuboid collider: {:?} {:?} {:?}", x, y, z);
 
 					let half_x = *x / 2.0;
@@ -136,12 +192,100 @@ fn handle_map_template(
 	}
 }
 
+/// Roles a map entity gives its entity, each added and removed as a whole
+#[derive(Clone, Copy)]
+struct EntityRoles {
+	npc: bool,
+	move_cycle: bool,
+	player: bool,
+}
+
+impl EntityRoles {
+	const ALL: EntityRoles = EntityRoles { npc: true, move_cycle: true, player: true };
+
+	/// The roles whose fields differ, so that the others keep their state
+	fn changed(old: &MapEntity, new: &MapEntity) -> EntityRoles {
+		EntityRoles {
+			npc: old.npc != new.npc,
+			move_cycle: old.move_cycle != new.move_cycle,
+			player: old.player != new.player,
+		}
+	}
+}
+
+fn insert_entity_roles(
+	entity_commands: &mut EntityCommands,
+	entity: &MapEntity,
+	player_ids: &mut ResMut<PlayerIds>,
+	roles: EntityRoles,
+) {
+	if roles.npc && entity.npc == Some(true) {
+		entity_commands.insert(NPC);
+		entity_commands.insert(Health { value: 3 });	
+		entity_commands.insert(Tower {
+			shooting_timer: Timer::from_seconds(0.3, TimerMode::Repeating),
+			bullet_offset: Vec3::new(0.0, 0.2, 0.5),
+		});
+	
+	}
+
+	if let Some(move_cycle) = entity.move_cycle.as_ref().filter(|_| roles.move_cycle) {
+		let mut cycle = MoveCycle::default();
+
+		for target in move_cycle {
+			cycle.targets.push(
+				Point {
+					x: target[0] as i32,
+					y: target[1] as i32,
+					z: target[2] as i32,
+				}
+			);
+		}
+
+		log::info!("move cycle {:?}", cycle);
+
+		entity_commands.insert(cycle);
+	}
+
+	if roles.player && entity.player == Some(true) {
+		let player_id = player_ids.provide_player_id(&entity.entity_id);
+
+		log::info!("[{}] entity is player {}", entity.entity_id, player_id);
+		entity_commands.insert(Target { speed: 0.0 });
+	    // entity_commands.insert(Health { value: 3 });	
+		entity_commands.insert((
+			You,
+			Ccd::enabled()
+		));
+
+		
+	}
+}
+
+fn remove_entity_roles(entity_commands: &mut EntityCommands, roles: EntityRoles) {
+	if roles.npc {
+		entity_commands.remove::<(NPC, Health, Tower)>();
+	}
+
+	if roles.move_cycle {
+		entity_commands.remove::<(MoveCycle, TargetPosition, NavigationPath)>();
+	}
+
+	if roles.player {
+		entity_commands.remove::<(Target, You, Ccd)>();
+	}
+}
+
+fn map_entity_transform(entity: &MapEntity) -> Transform {
+	entity.transform.unwrap_or_default().to_transform()
+}
+
 fn spaw_map_entity(
 	commands: &mut Commands,
 	entity: &MapEntity,
 	player_ids: &mut ResMut<PlayerIds>,
 	
-) {
+) -> Entity {
 	log::info!("Spawning map entity: {}", entity.template);
 
 	let game_entity = GameEntity {
@@ -159,25 +303,12 @@ fn spaw_map_entity(
 		},
 		game_entity,
 		Name::new(format!("{}:{}", entity.entity_id, entity.template)),
+		MapEntitySource {
+			map_entity: entity.clone()
+		},
 	));
 
-	let scale = match entity.scale {
-		Some(scale) => Vec3::splat(scale),
-		None => Vec3::splat(1.0)
-	};
-
-	let translation = match entity.initial_position {
-		Some(translation) => Vec3::from_slice(&translation),
-		None => Vec3::default()
-	};
-
-	let entity_transform = Transform {
-		scale: scale,
-		translation: translation,
-		..Default::default()
-	};
-
-	new_component.insert(entity_transform.clone());
+	new_component.insert(map_entity_transform(entity));
 
 	new_component.insert(
 		NeedsTemplate {
@@ -186,47 +317,88 @@ fn spaw_map_entity(
 		}
 	);
 
-	if let Some(true) = entity.npc{
-		new_component.insert(NPC);
-		new_component.insert(Health { value: 3 });	
-		new_component.insert(Tower {
-			shooting_timer: Timer::from_seconds(0.3, TimerMode::Repeating),
-			bullet_offset: Vec3::new(0.0, 0.2, 0.5),
-		});
-	
-	}
+	insert_entity_roles(&mut new_component, entity, player_ids, EntityRoles::ALL);
 
-	if let Some(move_cycle) = &entity.move_cycle {
-		let mut cycle = MoveCycle::default();
+	new_component.id()
+}
 
-		for target in move_cycle {
-			cycle.targets.push(
-				Point {
-					x: target[0] as i32,
-					y: target[1] as i32,
-					z: target[2] as i32,
-				}
-			);
+/// Strips everything a template added to an entity so that the template
+/// can be applied again from scratch.
+fn reset_template(
+	commands: &mut Commands,
+	entity: Entity,
+	children: &Query<&Children>,
+	entity_scenes: &Query<(), With<EntityScene>>,
+) {
+	if let Ok(children) = children.get(entity) {
+		for child in children.iter() {
+			if entity_scenes.get(*child).is_ok() {
+				commands.entity(*child).despawn_recursive();
+			}
 		}
+	}
 
-		log::info!("move cycle {:?}", cycle);
+	let mut entity_commands = commands.entity(entity);
+
+	entity_commands.remove::<(RigidBody, Collider, KinematicCharacterController)>();
+	entity_commands.remove::<(AdditionalMassProperties, Friction)>();
+	entity_commands.remove::<(NeedsAsset, AddCollidingMesh, AddBoundingCollider, ComputedBounds)>();
+	entity_commands.remove::<(NeedsPlaceholder, Behavior)>();
+	entity_commands.remove::<(AnimationEntityLink, CurrentAnimation)>();
+}
+
+fn update_map_entity(
+	commands: &mut Commands,
+	entity: Entity,
+	game_entity: &mut GameEntity,
+	transform: &mut Transform,
+	source: &mut MapEntitySource,
+	map_entity: &MapEntity,
+	player_ids: &mut ResMut<PlayerIds>,
+	children: &Query<&Children>,
+	entity_scenes: &Query<(), With<EntityScene>>,
+) {
+	log::info!("[{}] updating map entity", map_entity.entity_id);
+
+	let new_transform = map_entity_transform(map_entity);
 
-		new_component.insert(cycle);
+	transform.translation = new_transform.translation;
+	transform.rotation = new_transform.rotation;
+	transform.scale = new_transform.scale;
+
+	let max_health = map_entity.max_health.unwrap_or(100.0);
+
+	if game_entity.max_health != max_health {
+		game_entity.max_health = max_health;
+		game_entity.curr_health = game_entity.curr_health.min(max_health);
 	}
 
-	if let Some(true) = entity.player {
-		let player_id = player_ids.provide_player_id(&entity.entity_id);
+	game_entity.npc = map_entity.npc.unwrap_or(false);
 
-		log::info!("[{}] entity is player {}", entity.entity_id, player_id);
-		new_component.insert(Target { speed: 0.0 });
-	    // new_component.insert(Health { value: 3 });	
-		new_component.insert((
-			You,
-			Ccd::enabled()
-		));
+	if game_entity.template != map_entity.template || source.map_entity.overrides != map_entity.overrides {
+		log::info!("[{}] applying template {} -> {}", map_entity.entity_id, game_entity.template, map_entity.template);
 
-		
+		reset_template(commands, entity, children, entity_scenes);
+
+		game_entity.template = map_entity.template.clone();
+
+		commands.entity(entity).insert(
+			NeedsTemplate {
+				template: map_entity.template.clone(),
+				map_enitity: map_entity.clone()
+			}
+		);
 	}
+
+	let mut entity_commands = commands.entity(entity);
+
+	entity_commands.insert(Name::new(format!("{}:{}", map_entity.entity_id, map_entity.template)));
+
+	let roles = EntityRoles::changed(&source.map_entity, map_entity);
+	remove_entity_roles(&mut entity_commands, roles);
+	insert_entity_roles(&mut entity_commands, map_entity, player_ids, roles);
+
+	source.map_entity = map_entity.clone();
 }
 
 pub fn handle_needs_template(
@@ -236,11 +408,11 @@ pub fn handle_needs_template(
 	mut query: Query<(Entity, &NeedsTemplate, &mut GameEntity)>,
 ) {
 	for (entity, needs_template, mut game_entity) in query.iter_mut() {
-		match template_map.templates.get(&needs_template.template) {
+		match template_map.for_entity(&needs_template.map_enitity) {
 			Some(template) => {
 				let mut entity_commands = commands.entity(entity);
 				
-				handle_map_template(&mut entity_commands, template, &needs_template.map_enitity, &mut game_entity);
+				handle_map_template(&mut entity_commands, &template, &needs_template.map_enitity, &mut game_entity);
 
 				entity_commands.remove::<NeedsTemplate>();
 			},
@@ -252,14 +424,18 @@ pub fn handle_needs_template(
 fn spawn_light(
 	commands: &mut Commands,
 	light: &Light
-) {
+) -> Entity {
+	let source = MapLightSource {
+		light: light.clone()
+	};
+
 	match &light.light_type {
 		LightType::Point(point) => {
 			log::info!("Spawning point light: {:?}", point);
 
 			let mut light_bundle = PointLightBundle {
 				point_light: PointLight {
-					color: Color::hex(&point.color).unwrap(),
+					color: map_color(&point.color),
 					..Default::default()
 				},
 				..Default::default()
@@ -285,232 +461,745 @@ fn spawn_light(
 				light_bundle.transform = Transform::from_xyz(location[0], location[1], location[2]);
 			}
 
-			commands.spawn(light_bundle);
+			commands.spawn((light_bundle, source)).id()
+		},
+		LightType::Directional(directional) => {
+			log::info!("Spawning directional light: {:?}", directional);
+
+			let mut light_bundle = DirectionalLightBundle {
+				directional_light: DirectionalLight {
+					color: map_color(&directional.color),
+					..Default::default()
+				},
+				..Default::default()
+			};
+
+			if let Some(illuminance) = directional.illuminance {
+				light_bundle.directional_light.illuminance = illuminance;
+			}
+
+			if let Some(shadows_enabled) = directional.shadows_enabled {
+				light_bundle.directional_light.shadows_enabled = shadows_enabled;
+			}
+
+			if let Some(rotation) = directional.rotation {
+				light_bundle.transform.rotation = Quat::from_euler(
+					EulerRot::YXZ,
+					rotation[1],
+					rotation[0],
+					rotation[2]
+				);
+			}
+
+			if let Some(cascades) = &directional.shadow_cascades {
+				match cascades.to_builder() {
+					Ok(builder) => light_bundle.cascade_shadow_config = builder.build(),
+					Err(err) => log::error!("invalid shadow cascades of light {}: {}", light.id, err),
+				}
+			}
+
+			commands.spawn((light_bundle, source)).id()
+		},
+		LightType::Spot(spot) => {
+			log::info!("Spawning spot light: {:?}", spot);
+
+			let mut light_bundle = SpotLightBundle {
+				spot_light: SpotLight {
+					color: map_color(&spot.color),
+					..Default::default()
+				},
+				..Default::default()
+			};
+
+			if let Some(intensity) = spot.intensity {
+				light_bundle.spot_light.intensity = intensity;
+			}
+
+			if let Some(range) = spot.range {
+				light_bundle.spot_light.range = range;
+			}
+
+			if let Some(radius) = spot.radius {
+				light_bundle.spot_light.radius = radius;
+			}
+
+			if let Some(shadows_enabled) = spot.shadows_enabled {
+				light_bundle.spot_light.shadows_enabled = shadows_enabled;
+			}
+
+			if let Some(inner_angle) = spot.inner_angle {
+				light_bundle.spot_light.inner_angle = inner_angle;
+			}
+
+			if let Some(outer_angle) = spot.outer_angle {
+				light_bundle.spot_light.outer_angle = outer_angle;
+			}
+
+			if let Some(location) = spot.location {
+				light_bundle.transform.translation = Vec3::new(location[0], location[1], location[2]);
+			}
+
+			let direction = match spot.direction {
+				Some(direction) => Vec3::new(direction[0], direction[1], direction[2]).normalize_or_zero(),
+				None => Vec3::NEG_Y,
+			};
+
+			if direction != Vec3::ZERO {
+				// Spot lights shine along their -z, which `looking_to` points
+				let up = if direction.cross(Vec3::Y) == Vec3::ZERO { Vec3::Z } else { Vec3::Y };
+
+				light_bundle.transform = light_bundle.transform.looking_to(direction, up);
+			}
+
+			commands.spawn((light_bundle, source)).id()
 		}
 	}
 }
 
+/// Name of a shape entity, e.g. `box:floor`
+fn shape_name(shape: &MapShape) -> Name {
+	let kind = match &shape.shape {
+		MapShapeType::Cube(_) => "cube",
+		MapShapeType::Plane(_) => "plane",
+		MapShapeType::Quad(_) => "quad",
+		MapShapeType::Circle(_) => "circle",
+		MapShapeType::Box(_) => "box",
+		MapShapeType::Terrain(_) => "terrain",
+	};
+
+	Name::new(format!("{}:{}", kind, shape.id))
+}
+
+/// Collider matching the mesh of a primitive shape, if the shape wants one.
+/// Flat shapes get a thin box.
+fn shape_collider(shape: &MapShape) -> Option<Collider> {
+	let box_collider = matches!(&shape.shape, MapShapeType::Box(box_shape) if box_shape.collider == Some(true));
+
+	if shape.collider != Some(true) && shape.physics.is_none() && !box_collider {
+		return None;
+	}
+
+	const THICKNESS: f32 = 0.01;
+
+	let collider = match &shape.shape {
+		MapShapeType::Cube(cube) => Collider::cuboid(cube.size / 2.0, cube.size / 2.0, cube.size / 2.0),
+		MapShapeType::Plane(plane) => Collider::cuboid(plane.size / 2.0, THICKNESS, plane.size / 2.0),
+		MapShapeType::Quad(quad) => Collider::cuboid(quad.size[0] / 2.0, quad.size[1] / 2.0, THICKNESS),
+		MapShapeType::Circle(circle) => Collider::compound(vec![(
+			Vec3::ZERO,
+			// Circles face +z, cylinders stand along y
+			Quat::from_rotation_x(std::f32::consts::FRAC_PI_2),
+			Collider::cylinder(THICKNESS, circle.radius),
+		)]),
+		MapShapeType::Box(box_shape) => {
+			let min = Vec3::new(box_shape.min_x, box_shape.min_y, box_shape.min_z);
+			let max = Vec3::new(box_shape.max_x, box_shape.max_y, box_shape.max_z);
+			let half_extents = (max - min) / 2.0;
+
+			log::info!("spawning box collider: {:?}", half_extents);
+
+			Collider::compound(vec![(
+				(min + max) / 2.0,
+				Quat::IDENTITY,
+				Collider::cuboid(half_extents.x, half_extents.y, half_extents.z),
+			)])
+		},
+		// Terrains build their heightfield once the heightmap has loaded
+		MapShapeType::Terrain(_) => return None,
+	};
+
+	Some(collider)
+}
+
+fn shape_rigid_body(shape: &MapShape) -> RigidBody {
+	match shape.physics {
+		Some(MapEntityPhysics::Dynamic) => RigidBody::Dynamic,
+		Some(MapEntityPhysics::Kinematic) => RigidBody::KinematicPositionBased,
+		Some(MapEntityPhysics::Static) | None => RigidBody::Fixed,
+	}
+}
+
 fn spawn_shape(
 	commands: &mut Commands,
 	meshes: &mut ResMut<Assets<Mesh>>,
 	materials: &mut ResMut<Assets<StandardMaterial>>,
+	map_materials: &MapMaterials,
+	asset_server: &AssetServer,
 	shape: &MapShape
-) {
+) -> Entity {
 	log::info!("spawning shape: {:?}", shape);
 
-	match &shape.shape {
-		MapShapeType::Cube(cube) => {
-			commands.spawn(
-				PbrBundle {
-					mesh: meshes.add(Mesh::from(shape::Cube { size: cube.size })),
-					..Default::default()
-				}
-			);
+	let transform = shape.transform.unwrap_or_default().to_transform();
 
-		},
-		MapShapeType::Plane(plane) => {
-			log::info!("spawning plane {:?}", plane);
-			
-			let mut plane_bundle = PbrBundle {
-				mesh: meshes.add(Mesh::from(shape::Plane { 
-					size: plane.size,
-					..Default::default() 
-				})),
-				material: materials.add(
-					StandardMaterial {
-						base_color: Color::rgb(0.3, 0.5, 0.3),
-						..Default::default()
-					}
-				),
+	let (mesh, default_color) = match &shape.shape {
+		MapShapeType::Cube(cube) => (Mesh::from(shape::Cube { size: cube.size }), None),
+		MapShapeType::Plane(plane) => (
+			Mesh::from(shape::Plane {
+				size: plane.size,
 				..Default::default()
-			};
+			}),
+			Some(Color::rgb(0.3, 0.5, 0.3))
+		),
+		MapShapeType::Quad(quad) => (
+			Mesh::from(shape::Quad {
+				size: Vec2::from_slice(&quad.size),
+				..Default::default()
+			}),
+			None
+		),
+		MapShapeType::Circle(circle) => (
+			Mesh::from(shape::Circle {
+				radius: circle.radius,
+				vertices: circle.vertices.unwrap_or(32),
+			}),
+			None
+		),
+		MapShapeType::Box(box_shape) => (
+			Mesh::from(shape::Box {
+				min_x: box_shape.min_x,
+				min_y: box_shape.min_y,
+				min_z: box_shape.min_z,
+				max_x: box_shape.max_x,
+				max_y: box_shape.max_y,
+				max_z: box_shape.max_z,
+			}),
+			Some(Color::hex("E6EED6").unwrap())
+		),
+		MapShapeType::Terrain(terrain) => {
+			// The mesh and collider are built once the images have loaded
+			let terrain_entity = commands.spawn((
+				SpatialBundle::from_transform(transform),
+				NeedsTerrain {
+					heightmap: asset_server.load(terrain.heightmap.as_str()),
+					splat_map: terrain.splat_map.as_ref().map(|path| asset_server.load(path.as_str())),
+				},
+				shape_name(shape),
+				MapShapeSource {
+					map_shape: shape.clone()
+				},
+			)).id();
 
-			// if let Some(material) = plane.material {
-			// 	plane_bundle.material = materials.add(Color::hex(material).unwrap().into());
-			// }
+			return terrain_entity;
+		},
+	};
 
-			if let Some(location) = plane.location {
-				plane_bundle.transform = Transform::from_xyz(location[0], location[1], location[2]);
-			}
+	let material_name = shape.shape.material();
 
-			commands.spawn(plane_bundle);
-		},
-		MapShapeType::Quad(quad) => {
-			commands.spawn(
-				PbrBundle {
-					mesh: meshes.add(Mesh::from(shape::Quad { 
-						size: Vec2::from_slice(&quad.size),
-						..Default::default() 
-					})),
-					..Default::default()
-				}
-			);
+	let map_material = material_name.and_then(|name| {
+		let material = map_materials.handles.get(name);
+
+		if material.is_none() {
+			log::error!("shape {} references unknown material `{}`", shape.id, name);
+		}
+
+		material.cloned()
+	});
+
+	let material = map_material.unwrap_or_else(|| match default_color {
+		Some(base_color) => materials.add(StandardMaterial {
+			base_color,
+			..Default::default()
+		}),
+		None => Handle::default(),
+	});
+
+	let uv_scale = material_name
+		.and_then(|name| map_materials.definitions.get(name))
+		.and_then(|material| material.uv_scale);
+
+	let mut mesh = mesh;
+
+	if let Some(uv_scale) = uv_scale {
+		scale_uvs(&mut mesh, uv_scale);
+	}
+
+	let mut entity_commands = commands.spawn((
+		PbrBundle {
+			mesh: meshes.add(mesh),
+			material,
+			transform,
+			..Default::default()
 		},
-		MapShapeType::Circle(circle) => {
-			commands.spawn(
-				PbrBundle {
-					mesh: meshes.add(Mesh::from(shape::Circle {
-						radius: circle.radius,
-						vertices: match circle.vertices {
-							Some(vertices) => vertices,
-							None => 32
-						},
-						..Default::default()
-					})),
-					..Default::default()
-				}
-			);
+		shape_name(shape),
+		MapShapeSource {
+			map_shape: shape.clone()
 		},
-		MapShapeType::Box(box_shape) => {
-			let mut entity_commands = commands.spawn((
-				PbrBundle {
-					mesh: meshes.add(Mesh::from(shape::Box {
-						min_x: box_shape.min_x,
-						min_y: box_shape.min_y,
-						min_z: box_shape.min_z,
-						max_x: box_shape.max_x,
-						max_y: box_shape.max_y,
-						max_z: box_shape.max_z,
-						..Default::default()
-					})),
-					material: materials.add(StandardMaterial {
-						base_color: Color::hex("E6EED6").unwrap(),
-						..default()
-					}),
-					..Default::default()
-				},
-				Name::new(format!("box:{}", shape.id),
-			)));
+	));
 
-			if let Some(true) = box_shape.collider {
-				let hx = (box_shape.max_x - box_shape.min_x) / 2.0;
-				let hy = (box_shape.max_y - box_shape.min_y) / 2.0;
-				let hz = (box_shape.max_z - box_shape.min_z) / 2.0;
+	if let Some(collider) = shape_collider(shape) {
+		entity_commands.insert((
+			collider,
+			shape_rigid_body(shape)
+		));
+	}
 
-				log::info!("spawning box collider: {:?}", (hx, hy, hz));
+	entity_commands.id()
+}
 
-				entity_commands.insert((
-					Collider::cuboid(hx, hy, hz),
-					RigidBody::Fixed
-				));
+/// Spawns the parent of a prefab instance's parts, placed at the instance
+fn spawn_prefab_instance(
+	commands: &mut Commands,
+	instance: &MapPrefabInstance
+) -> Entity {
+	log::info!("spawning prefab instance {} of {}", instance.id, instance.prefab);
+
+	commands.spawn((
+		SpatialBundle::from_transform(instance.transform.unwrap_or_default().to_transform()),
+		Name::new(format!("prefab:{}", instance.id)),
+		MapPrefabInstanceSource {
+			instance: instance.clone()
+		},
+	)).id()
+}
+
+/// Spawns the entities, shapes and lights of a prefab instance as children
+/// of `parent`, so that moving or removing the instance moves or removes
+/// all of them.
+fn spawn_prefab_parts(
+	commands: &mut Commands,
+	parent: Entity,
+	instance: &MapPrefabInstance,
+	map_prefabs: &MapPrefabs,
+	meshes: &mut ResMut<Assets<Mesh>>,
+	materials: &mut ResMut<Assets<StandardMaterial>>,
+	map_materials: &MapMaterials,
+	asset_server: &AssetServer,
+	player_ids: &mut ResMut<PlayerIds>,
+) {
+	let parts = match map_prefabs.definitions.get(&instance.prefab) {
+		Some(prefab) => match prefab.instantiate(instance) {
+			Ok(parts) => parts,
+			Err(err) => {
+				log::error!("invalid overrides in prefab instance {}: {}", instance.id, err);
+
+				return;
 			}
 		},
+		None => {
+			log::error!("prefab instance {} references unknown prefab `{}`", instance.id, instance.prefab);
+
+			return;
+		}
+	};
+
+	let mut children = Vec::new();
+
+	for entity in parts.entities.iter() {
+		children.push(spaw_map_entity(commands, entity, player_ids));
+	}
+
+	for shape in parts.shapes.iter() {
+		children.push(spawn_shape(commands, meshes, materials, map_materials, asset_server, shape));
 	}
+
+	for light in parts.lights.iter() {
+		children.push(spawn_light(commands, light));
+	}
+
+	for child in children.iter() {
+		commands.entity(*child).insert(PrefabPart);
+	}
+
+	commands.entity(parent).push_children(&children);
+}
+
+fn register_template(
+	template: &MapTemplate,
+	asset_server: &Res<AssetServer>,
+	gltf_register: &mut ResMut<GltfRegister>,
+	map_templates: &mut ResMut<MapTemplates>,
+) -> Vec<String> {
+	match &template.asset {
+		Some(asset_path) => {
+			let asset: Handle<Gltf> = asset_server.load(asset_path);
+
+			let unloaded_asset = UnloadedGltfAsset {
+				asset: asset_path.clone(),
+				gltf: asset
+			};
+
+			gltf_register.unloaded.push(unloaded_asset);
+		},
+		None => {
+			log::info!("template {} has no asset", template.name);
+		},
+	}
+
+	map_templates.insert(template.clone())
 }
 
-// pub fn handle_map_changes(
-// 	mut commands: Commands,
-// 	changes_receiver: Res<MapChangesReceiver>,
-// 	mut map_templates: ResMut<MapTemplates>, 
-// 	mut gltf_register: ResMut<GltfRegister>,
-// 	mut done: Local<bool>,
-// 	asset_server: Res<AssetServer>,
-// 	mut meshes: ResMut<Assets<Mesh>>,
-// 	mut materials: ResMut<Assets<StandardMaterial>>,
-// 	mut player_ids: ResMut<PlayerIds>,
-// ) {
-// 	// if *done {
-// 	// 	return;
-// 	// }
-
-// 	let changes_receiver = changes_receiver.rx.lock().unwrap();
-
-// 	loop {
-// 		match changes_receiver.try_recv() {
-// 			Ok(change) => {
-// 				log::info!("mapchange {:?}", change);
-
-// 				match change {
-// 					MapChange::NewMapEntity(entity) => {
-// 						spaw_map_entity(
-// 							&mut commands, 
-// 							&entity,
-// 							&mut player_ids
-// 						);
-// 					},
-//         			MapChange::NewMapTemplate(template) => {
-// 						match &template.asset {
-// 							Some(asset_path) => {
-// 								let asset: Handle<Gltf> = asset_server.load(asset_path);
-
-// 								let unloaded_asset = UnloadedGltfAsset {
-// 									asset: asset_path.clone(),
-// 									gltf: asset
-// 								};
-
-// 								gltf_register.unloaded.push(unloaded_asset);
-// 							},
-// 							None => todo!(),
-// 						}
-
-// 						map_templates.templates.insert(template.name.clone(), template);
-// 					},
-// 					MapChange::NewMapShape(shape) => {
-// 						spawn_shape(&mut commands, &mut meshes, &mut materials, shape);
-// 					},
-// 					MapChange::NewLight(ligth) => {
-// 						spawn_light(&mut commands, ligth);
-// 					},
-// 					MapChange::NewAmbientLight(args) => {
-// 						commands.insert_resource(AmbientLight {
-// 							brightness: args.brightness,
-// 							color: Color::hex(args.color).unwrap(),
-// 						});
-// 					},
-//         			MapChange::NewCamera(map_camera) => {
-// 						commands.spawn(
-// 							NeedsCamera {
-// 								entity_id: map_camera.entity_id,
-// 								camera_type: map_camera.camera_type
-// 							}
-// 						);
-// 					},
-// 					MapChange::UpdateMapEntity(_) => {},
-// 					MapChange::RemoveMapEntity(_) => {},
-// 					MapChange::UpdateMaptemplate(_) => {},
-// 					MapChange::RemoveMapTemplate(_) => {},
-// 					MapChange::UpdateLight(_) => {},
-// 					MapChange::UpdateAmbientLight(_) => {},
-// 					MapChange::UpdateMapShape(_) => {},
-// 					MapChange::RemoveMapShape(_) => {},
-// 					MapChange::RemoveLight(_) => {},
-// 					MapChange::RemoveAmbientLight => {},
-// 					MapChange::UpdateCamera(_) => {},
-// 					MapChange::RemoveCamera => {},			
-// 				}
-// 			},
-// 			Err(err) => {
-// 				match err {
-// 					mpsc::TryRecvError::Empty => {
-// 						break;
-// 					},
-// 					mpsc::TryRecvError::Disconnected => {
-// 						log::info!("changes disconnected");
+/// Applies the current version of the changed templates to every entity
+/// using them.
+fn reapply_templates(
+	commands: &mut Commands,
+	changed_templates: &[String],
+	game_entities: &Query<(Entity, &mut GameEntity, &mut Transform, &mut MapEntitySource)>,
+	children: &Query<&Children>,
+	entity_scenes: &Query<(), With<EntityScene>>,
+) {
+	for (entity, game_entity, _, source) in game_entities.iter() {
+		if !changed_templates.contains(&game_entity.template) {
+			continue;
+		}
+
+		log::info!("[{}] reapplying template {}", game_entity.entity_id, game_entity.template);
+
+		reset_template(commands, entity, children, entity_scenes);
+
+		commands.entity(entity).insert(
+			NeedsTemplate {
+				template: game_entity.template.clone(),
+				map_enitity: source.map_entity.clone()
+			}
+		);
+	}
+}
+
+pub fn handle_map_changes(
+	mut commands: Commands,
+	changes_receiver: Res<MapChangesReceiver>,
+	mut map_templates: ResMut<MapTemplates>, 
+	mut gltf_register: ResMut<GltfRegister>,
+	mut done: Local<bool>,
+	asset_server: Res<AssetServer>,
+	mut meshes: ResMut<Assets<Mesh>>,
+	mut materials: ResMut<Assets<StandardMaterial>>,
+	mut map_materials: ResMut<MapMaterials>,
+	mut player_ids: ResMut<PlayerIds>,
+	mut game_entities: Query<(Entity, &mut GameEntity, &mut Transform, &mut MapEntitySource)>,
+	mut shapes: Query<(Entity, &mut MapShapeSource, Option<&Handle<Mesh>>, &mut Transform), Without<GameEntity>>,
+	lights: Query<(Entity, &MapLightSource)>,
+	cameras: Query<Entity, Or<(With<PlayerCamera>, With<NeedsCamera>)>>,
+	(children, entity_scenes): (Query<&Children>, Query<(), With<EntityScene>>),
+	(mut map_prefabs, mut prefab_instances): (
+		ResMut<MapPrefabs>,
+		Query<(Entity, &mut MapPrefabInstanceSource, &mut Transform), (Without<GameEntity>, Without<MapShapeSource>)>,
+	),
+) {
+	if *done {
+		return;
+	}
+
+	let changes_receiver = changes_receiver.rx.lock().unwrap();
+
+	loop {
+		match changes_receiver.try_recv() {
+			Ok(change) => {
+				log::info!("mapchange {:?}", change);
+
+				match change {
+					MapChange::NewMapEntity(entity) => {
+						spaw_map_entity(
+							&mut commands, 
+							&entity,
+							&mut player_ids
+						);
+					},
+					MapChange::UpdateMapEntity(map_entity) => {
+						let found = game_entities
+							.iter_mut()
+							.find(|(_, game_entity, _, _)| game_entity.entity_id == map_entity.entity_id);
+
+						match found {
+							Some((entity, mut game_entity, mut transform, mut source)) => {
+								update_map_entity(
+									&mut commands,
+									entity,
+									&mut game_entity,
+									&mut transform,
+									&mut source,
+									&map_entity,
+									&mut player_ids,
+									&children,
+									&entity_scenes,
+								);
+							},
+							None => {
+								log::warn!("[{}] updated entity not found, spawning it", map_entity.entity_id);
+
+								spaw_map_entity(&mut commands, &map_entity, &mut player_ids);
+							}
+						}
+					},
+					MapChange::RemoveMapEntity(entity_id) => {
+						for (entity, game_entity, _, _) in game_entities.iter() {
+							if game_entity.entity_id == entity_id {
+								log::info!("[{}] removing map entity", entity_id);
+
+								commands.entity(entity).despawn_recursive();
+							}
+						}
+					},
+					MapChange::NewMapTemplate(template) | MapChange::UpdateMapTemplate(template) => {
+						let changed = register_template(&template, &asset_server, &mut gltf_register, &mut map_templates);
+
+						reapply_templates(&mut commands, &changed, &game_entities, &children, &entity_scenes);
+					},
+					MapChange::RemoveMapTemplate(name) => {
+						log::info!("removing template {}", name);
+
+						let mut changed = map_templates.remove(&name);
+						changed.retain(|changed_name| *changed_name != name);
+
+						reapply_templates(&mut commands, &changed, &game_entities, &children, &entity_scenes);
+					},
+					MapChange::NewMaterial(material) => {
+						insert_material(&mut map_materials, &mut materials, &asset_server, &material);
+					},
+					MapChange::UpdateMaterial(material) => {
+						let old_scale = map_materials.definitions
+							.get(&material.name)
+							.and_then(|material| material.uv_scale)
+							.unwrap_or([1.0, 1.0]);
+						let new_scale = material.uv_scale.unwrap_or([1.0, 1.0]);
+
+						// The material itself is shared, but texture coordinates
+						// are scaled per mesh
+						if old_scale != new_scale {
+							for (_, source, mesh, _) in shapes.iter() {
+								if source.map_shape.shape.material() != Some(material.name.as_str()) {
+									continue;
+								}
+
+								if let Some(mesh) = mesh.and_then(|mesh| meshes.get_mut(mesh)) {
+									scale_uvs(mesh, [new_scale[0] / old_scale[0], new_scale[1] / old_scale[1]]);
+								}
+							}
+						}
+
+						insert_material(&mut map_materials, &mut materials, &asset_server, &material);
+					},
+					MapChange::RemoveMaterial(name) => {
+						remove_material(&mut map_materials, &name);
+					},
+					MapChange::NewMapShape(shape) => {
+						spawn_shape(&mut commands, &mut meshes, &mut materials, &map_materials, &asset_server, &shape);
+					},
+					MapChange::UpdateMapShape(shape) => {
+						let mut moved = false;
+
+						for (entity, mut source, _, mut transform) in shapes.iter_mut() {
+							if source.map_shape.id != shape.id {
+								continue;
+							}
+
+							// Only moved, no need to rebuild the mesh and collider
+							let mut old_shape = source.map_shape.clone();
+							old_shape.transform = shape.transform;
+
+							if old_shape == shape {
+								log::info!("moving shape {}", shape.id);
+
+								*transform = shape.transform.unwrap_or_default().to_transform();
+								source.map_shape = shape.clone();
+								moved = true;
+							} else {
+								commands.entity(entity).despawn_recursive();
+							}
+						}
+
+						if !moved {
+							spawn_shape(&mut commands, &mut meshes, &mut materials, &map_materials, &asset_server, &shape);
+						}
+					},
+					MapChange::RemoveMapShape(id) => {
+						for (entity, source, _, _) in shapes.iter() {
+							if source.map_shape.id == id {
+								commands.entity(entity).despawn_recursive();
+							}
+						}
+					},
+					MapChange::NewLight(light) => {
+						spawn_light(&mut commands, &light);
+					},
+					MapChange::UpdateLight(light) => {
+						for (entity, source) in lights.iter() {
+							if source.light.id == light.id {
+								commands.entity(entity).despawn_recursive();
+							}
+						}
+
+						spawn_light(&mut commands, &light);
+					},
+					MapChange::RemoveLight(id) => {
+						for (entity, source) in lights.iter() {
+							if source.light.id == id {
+								commands.entity(entity).despawn_recursive();
+							}
+						}
+					},
+					MapChange::NewPrefab(prefab) | MapChange::UpdatePrefab(prefab) => {
+						log::info!("loading prefab {}", prefab.name);
+
+						map_prefabs.definitions.insert(prefab.name.clone(), prefab.clone());
+
+						for (entity, source, _) in prefab_instances.iter() {
+							if source.instance.prefab != prefab.name {
+								continue;
+							}
+
+							commands.entity(entity).despawn_descendants();
+
+							spawn_prefab_parts(
+								&mut commands,
+								entity,
+								&source.instance,
+								&map_prefabs,
+								&mut meshes,
+								&mut materials,
+								&map_materials,
+								&asset_server,
+								&mut player_ids,
+							);
+						}
+					},
+					MapChange::RemovePrefab(name) => {
+						log::info!("removing prefab {}", name);
+
+						map_prefabs.definitions.remove(&name);
+					},
+					MapChange::NewPrefabInstance(instance) => {
+						let parent = spawn_prefab_instance(&mut commands, &instance);
+
+						spawn_prefab_parts(
+							&mut commands,
+							parent,
+							&instance,
+							&map_prefabs,
+							&mut meshes,
+							&mut materials,
+							&map_materials,
+							&asset_server,
+							&mut player_ids,
+						);
+					},
+					MapChange::UpdatePrefabInstance(instance) => {
+						let mut found = false;
+
+						for (entity, mut source, mut transform) in prefab_instances.iter_mut() {
+							if source.instance.id != instance.id {
+								continue;
+							}
+
+							found = true;
+
+							// Moving the instance moves its parts, anything else rebuilds them
+							*transform = instance.transform.unwrap_or_default().to_transform();
+
+							if source.instance.prefab != instance.prefab || source.instance.overrides != instance.overrides {
+								log::info!("rebuilding prefab instance {}", instance.id);
+
+								commands.entity(entity).despawn_descendants();
+
+								spawn_prefab_parts(
+									&mut commands,
+									entity,
+									&instance,
+									&map_prefabs,
+									&mut meshes,
+									&mut materials,
+									&map_materials,
+									&asset_server,
+									&mut player_ids,
+								);
+							}
+
+							source.instance = instance.clone();
+						}
+
+						if !found {
+							let parent = spawn_prefab_instance(&mut commands, &instance);
+
+							spawn_prefab_parts(
+								&mut commands,
+								parent,
+								&instance,
+								&map_prefabs,
+								&mut meshes,
+								&mut materials,
+								&map_materials,
+								&asset_server,
+								&mut player_ids,
+							);
+						}
+					},
+					MapChange::RemovePrefabInstance(id) => {
+						for (entity, source, _) in prefab_instances.iter() {
+							if source.instance.id == id {
+								log::info!("removing prefab instance {}", id);
+
+								commands.entity(entity).despawn_recursive();
+							}
+						}
+					},
+					MapChange::NewAmbientLight(args) | MapChange::UpdateAmbientLight(args) => {
+						commands.insert_resource(AmbientLight {
+							brightness: args.brightness,
+							color: map_color(&args.color),
+						});
+					},
+					MapChange::RemoveAmbientLight => {
+						commands.insert_resource(AmbientLight::default());
+					},
+					MapChange::NewCamera(map_camera) => {
+						commands.spawn(
+							NeedsCamera {
+								entity_id: map_camera.entity_id,
+								camera_type: map_camera.camera_type
+							}
+						);
+					},
+					MapChange::UpdateCamera(map_camera) => {
+						for entity in cameras.iter() {
+							commands.entity(entity).despawn_recursive();
+						}
+
+						commands.spawn(
+							NeedsCamera {
+								entity_id: map_camera.entity_id,
+								camera_type: map_camera.camera_type
+							}
+						);
+					},
+					MapChange::RemoveCamera => {
+						for entity in cameras.iter() {
+							commands.entity(entity).despawn_recursive();
+						}
+					},
+				}
+			},
+			Err(err) => {
+				match err {
+					mpsc::TryRecvError::Empty => {
+						break;
+					},
+					mpsc::TryRecvError::Disconnected => {
+						log::info!("changes disconnected");
 	
-// 						*done = true;
+						*done = true;
 	
-// 						return;
-// 					},
-// 				}
-// 			}
-// 		};
-// 	}
-// }
+						return;
+					},
+				}
+			}
+		};
+	}
+}
 
 pub fn give_camera(
 	mut commands: Commands,
 	needs_camera: Query<(Entity, &NeedsCamera)>,
-	game_entities: Query<(Entity, &GameEntity)>,
+	game_entities: Query<(Entity, &GameEntity, &MapEntitySource)>,
 	map_templates: ResMut<MapTemplates>, 
 ) {
 	for (needs_cam_entity, needs_camera) in needs_camera.iter() {
-		let (entity, game_entity) = match game_entities
+		let (entity, game_entity, source) = match game_entities
 			.iter()
-			.find(|(_, game_entity)| {
+			.find(|(_, game_entity, _)| {
 				game_entity.entity_id == needs_camera.entity_id
 			}) {
-			Some((entity, game_entity)) => {
-				(entity, game_entity)
+			Some((entity, game_entity, source)) => {
+				(entity, game_entity, source)
 			},
 			None => {
 				log::error!("could not find game entity for camera");
@@ -519,7 +1208,7 @@ pub fn give_camera(
 			}
 		};
 
-		let template = match map_templates.templates.get(&game_entity.template) {
+		let template = match map_templates.for_entity(&source.map_entity) {
 			Some(template) => {
 				template
 			},
@@ -566,7 +1255,13 @@ pub fn give_camera(
 						..Default::default()
 					}
 				),
-				PlayerCamera::default()
+				PlayerCamera::default(),
+				MapCameraSource {
+					map_camera: MapCamera {
+						entity_id: needs_camera.entity_id.clone(),
+						camera_type: needs_camera.camera_type.clone()
+					}
+				}
 			));
 
 			entity_commands.with_children(|parent| {
@@ -588,6 +1283,8 @@ pub fn give_assets(
 	mut commands: Commands,
 	query: Query<(Entity, &GameEntity, &NeedsAsset)>,
 	asset_packs: Res<AssetPacks>,
+	asset_server: Res<AssetServer>,
+	mut gltf_register: ResMut<GltfRegister>,
 ) {
 	for (entity, game_entity, needs_asset) in query.iter() {
 		match asset_packs.asset_packs.get(&needs_asset.asset) {
@@ -605,16 +1302,33 @@ pub fn give_assets(
 					continue;
 				};	
 
-				if needs_asset.add_colliding_mesh {
-					log::info!("[{}] adding collision mesh", game_entity.entity_id);
+				let mut scene_transform = Transform::default();
 
-					entity_commands.insert(
-						AddCollidingMesh {
-							glft: asset_pack.gltf.clone(),
-						}
+				if let Some(transform) = needs_asset.initial_transform {
+					log::info!("[{}] initial transform {:?}", game_entity.entity_id, transform);
+
+					scene_transform.translation = Vec3::new(transform[0], transform[1], transform[2]);
+				}
+
+				if let Some(rotation) = needs_asset.initial_rotation {
+					log::info!("[{}] initial rotation {:?}", game_entity.entity_id, rotation);
+
+					scene_transform.rotation = Quat::from_euler(
+						EulerRot::YXZ,
+						rotation[1],
+						rotation[0],
+						rotation[2]
 					);
 				}
 
+				if let Some(padding) = needs_asset.bounding_collider {
+					entity_commands.insert(AddBoundingCollider {
+						scene: scene.clone(),
+						scene_transform,
+						padding,
+					});
+				}
+
 				entity_commands.with_children(|parent| {
 					log::info!("[{}] assign scene", game_entity.entity_id);
 
@@ -623,25 +1337,31 @@ pub fn give_assets(
 						SpatialBundle::default()
 					));
 
+					if let Some(materials) = &needs_asset.materials {
+						entity_commands.insert(MaterialOverrides {
+							gltf: asset_pack.gltf.clone(),
+							overrides: materials.clone(),
+						});
+					}
+
+					// The colliders are children of the scene, so that they go
+					// away with it when the template is applied again
+					if let Some(collision_mesh) = &needs_asset.collision_mesh {
+						log::info!("[{}] adding collision mesh", game_entity.entity_id);
+
+						entity_commands.insert(AddCollidingMesh {
+							scene: scene.clone(),
+							scene_transform,
+							collision_mesh: collision_mesh.clone(),
+						});
+					}
+
 					entity_commands.with_children(|parent| {
-						let mut bundle = SceneBundle {
-							scene: scene,
+						let bundle = SceneBundle {
+							scene,
+							transform: scene_transform,
 							..Default::default()
 						};
-	
-						if let Some(transform) = needs_asset.initial_transform {
-							log::info!("[{}] initial transform {:?}", game_entity.entity_id, transform);
-	
-							bundle.transform.translation = Vec3::new(transform[0], transform[1], transform[2]);
-						}
-	
-						if let Some(rotation) = needs_asset.initial_rotation_y {
-							log::info!("[{}] initial rotation {:?}", game_entity.entity_id, rotation);
-	
-							bundle.transform.rotation = Quat::from_rotation_y(
-								rotation.to_radians()
-							);
-						}
 
 						parent.spawn(bundle);
 					});
@@ -649,11 +1369,134 @@ pub fn give_assets(
 			},
 			None => {
 				log::info!("[{}] no asset {:?}", game_entity.entity_id, needs_asset.asset);
+
+				// Assets that only an entity's overrides refer to aren't
+				// registered with the templates
+				if !gltf_register.unloaded.iter().any(|unloaded| unloaded.asset == needs_asset.asset) {
+					gltf_register.unloaded.push(UnloadedGltfAsset {
+						asset: needs_asset.asset.clone(),
+						gltf: asset_server.load(needs_asset.asset.as_str()),
+					});
+				}
+			}
+		}
+	}
+}
+
+/// Respawns the scenes of entities whose glTF file changed on disk. The
+/// entities themselves stay, with their transform, health and physics, and
+/// the animation they were playing is started again on the new scene.
+pub fn reload_entity_scenes(
+	mut commands: Commands,
+	mut reloaded: EventReader<AssetPackReloaded>,
+	asset_packs: Res<AssetPacks>,
+	map_templates: Res<MapTemplates>,
+	mut cache: ResMut<CollisionMeshCache>,
+	game_entities: Query<(Entity, &GameEntity, &MapEntitySource, Option<&CurrentAnimation>), (Without<NeedsAsset>, Without<NeedsTemplate>)>,
+	children: Query<&Children>,
+	entity_scenes: Query<(), With<EntityScene>>,
+) {
+	for reloaded in reloaded.iter() {
+		// The scenes keep their handles, so colliders built from the old
+		// meshes would otherwise be reused
+		if let Some(asset_pack) = asset_packs.asset_packs.get(&reloaded.asset) {
+			let scenes: Vec<HandleId> = asset_pack.scenes.iter().map(|scene| scene.id()).collect();
+
+			cache.colliders.retain(|(scene, _), _| !scenes.contains(scene));
+			cache.bounds.retain(|scene, _| !scenes.contains(scene));
+		}
+
+		for (entity, game_entity, source, current_animation) in game_entities.iter() {
+			if game_entity.asset.as_ref() != Some(&reloaded.asset) {
+				continue;
+			}
+
+			let needs_asset = match map_templates.for_entity(&source.map_entity).as_ref().and_then(needs_asset) {
+				Some(needs_asset) => needs_asset,
+				None => continue,
+			};
+
+			log::info!("[{}] reloading scene of {}", game_entity.entity_id, reloaded.asset);
+
+			if let Ok(children) = children.get(entity) {
+				for child in children.iter() {
+					if entity_scenes.get(*child).is_ok() {
+						commands.entity(*child).despawn_recursive();
+					}
+				}
+			}
+
+			let mut entity_commands = commands.entity(entity);
+
+			entity_commands.remove::<(AnimationEntityLink, AddBoundingCollider)>();
+			entity_commands.insert(needs_asset);
+
+			if let Some(current_animation) = current_animation {
+				entity_commands.insert(StartAnimation {
+					asset: current_animation.asset.clone(),
+					animation: current_animation.animation.clone(),
+					repeat: current_animation.repeat,
+				});
 			}
 		}
 	}
 }
 
+/// Spawns a translucent mesh shaped like the collider for entities without
+/// an asset. It is an `EntityScene`, so applying the template again
+/// replaces it like a scene.
+pub fn give_placeholders(
+	mut commands: Commands,
+	query: Query<(Entity, &GameEntity, &NeedsPlaceholder)>,
+	mut meshes: ResMut<Assets<Mesh>>,
+	mut materials: ResMut<Assets<StandardMaterial>>,
+	mut placeholder_material: Local<Option<Handle<StandardMaterial>>>,
+) {
+	for (entity, game_entity, needs_placeholder) in query.iter() {
+		log::info!("[{}] giving placeholder", game_entity.entity_id);
+
+		let mesh = match &needs_placeholder.collider {
+			Some(MapEntityCollider::Capsule(MapCapsuleCollider { a, b, radius })) => PbrBundle {
+				mesh: meshes.add(Mesh::from(shape::Capsule {
+					radius: *radius,
+					depth: (b - a).abs(),
+					..default()
+				})),
+				transform: Transform::from_translation(Vec3::Y * (a + b) / 2.0),
+				..default()
+			},
+			Some(MapEntityCollider::Cuboid(MapCuboidCollider { x, y, z })) => PbrBundle {
+				mesh: meshes.add(Mesh::from(shape::Box::new(x * 2.0, y * 2.0, z * 2.0))),
+				..default()
+			},
+			_ => PbrBundle {
+				mesh: meshes.add(Mesh::from(shape::UVSphere { radius: 0.25, ..default() })),
+				..default()
+			},
+		};
+
+		let material = placeholder_material.get_or_insert_with(|| materials.add(StandardMaterial {
+			base_color: Color::rgba(0.2, 0.8, 1.0, 0.35),
+			alpha_mode: AlphaMode::Blend,
+			unlit: true,
+			..default()
+		})).clone();
+
+		let mut entity_commands = commands.entity(entity);
+		entity_commands.remove::<NeedsPlaceholder>();
+
+		entity_commands.with_children(|parent| {
+			parent.spawn((
+				EntityScene,
+				PbrBundle {
+					material,
+					..mesh
+				},
+			));
+		});
+	}
+}
+
 pub fn spawn_map_entites(
 	mut commands: Commands,
 	mut player_ids: ResMut<PlayerIds>,
@@ -663,6 +1506,8 @@ pub fn spawn_map_entites(
 	asset_server: Res<AssetServer>,
 	mut meshes: ResMut<Assets<Mesh>>,
 	mut materials: ResMut<Assets<StandardMaterial>>,
+	mut map_materials: ResMut<MapMaterials>,
+	mut map_prefabs: ResMut<MapPrefabs>,
 ) {
 	if let Some(entities) = map.entities.as_ref() {
 		for entity in entities.iter() {
@@ -676,21 +1521,7 @@ pub fn spawn_map_entites(
 		for template in templates.iter() {
 			log::info!("spawn map template");
 
-			match &template.asset {
-				Some(asset_path) => {
-					let asset: Handle<Gltf> = asset_server.load(asset_path);
-
-					let unloaded_asset = UnloadedGltfAsset {
-						asset: asset_path.clone(),
-						gltf: asset
-					};
-
-					gltf_register.unloaded.push(unloaded_asset);
-				},
-				None => todo!(),
-			}
-
-			map_templates.templates.insert(template.name.clone(), template.clone());
+			register_template(template, &asset_server, &mut gltf_register, &mut map_templates);
 		}
 	}
 	
@@ -707,7 +1538,7 @@ pub fn spawn_map_entites(
 
 		commands.insert_resource(AmbientLight {
 			brightness: ambied_light.brightness,
-			color: Color::hex(&ambied_light.color).unwrap(),
+			color: map_color(&ambied_light.color),
 		});
 	}
 
@@ -720,9 +1551,33 @@ pub fn spawn_map_entites(
 		);
 	}
 
+	for material in map.materials.iter().flatten() {
+		insert_material(&mut map_materials, &mut materials, &asset_server, material);
+	}
+
 	if let Some(shape) = &map.shapes {
 		for shape in shape.iter() {
-			spawn_shape(&mut commands, &mut meshes, &mut materials, shape);
+			spawn_shape(&mut commands, &mut meshes, &mut materials, &map_materials, &asset_server, shape);
 		}
 	}
+
+	for prefab in map.prefabs.iter().flatten() {
+		map_prefabs.definitions.insert(prefab.name.clone(), prefab.clone());
+	}
+
+	for instance in map.instances.iter().flatten() {
+		let parent = spawn_prefab_instance(&mut commands, instance);
+
+		spawn_prefab_parts(
+			&mut commands,
+			parent,
+			instance,
+			&map_prefabs,
+			&mut meshes,
+			&mut materials,
+			&map_materials,
+			&asset_server,
+			&mut player_ids,
+		);
+	}
 }
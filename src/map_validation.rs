@@ -0,0 +1,1963 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Component;
+use std::path::Path;
+
+use bevy::prelude::Resource;
+use serde::de;
+use serde::de::DeserializeOwned;
+use serde::de::Visitor;
+use serde::forward_to_deserialize_any;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::map::AmbientLight;
+use crate::map::DirectionalMapLight;
+use crate::map::Light;
+use crate::map::LightType;
+use crate::map::Map;
+use crate::map::MapAabbCollider;
+use crate::map::MapBehavior;
+use crate::map::MapBehaviorChild;
+use crate::map::MapBehaviorChildren;
+use crate::map::MapBox;
+use crate::map::MapCamera;
+use crate::map::MapCapsuleCollider;
+use crate::map::MapCircle;
+use crate::map::MapCollisionMesh;
+use crate::map::MapCooldown;
+use crate::map::MapCube;
+use crate::map::MapCuboidCollider;
+use crate::map::MapEntity;
+use crate::map::MapEntityCollider;
+use crate::map::MapFlee;
+use crate::map::MapHealthBelow;
+use crate::map::MapMaterial;
+use crate::map::MapNavigation;
+use crate::map::MapPlane;
+use crate::map::MapPlayerInRange;
+use crate::map::MapPrefab;
+use crate::map::MapPrefabInstance;
+use crate::map::MapQuad;
+use crate::map::MapRotation;
+use crate::map::MapScale;
+use crate::map::MapShadowCascades;
+use crate::map::MapShape;
+use crate::map::MapShapeType;
+use crate::map::MapTemplate;
+use crate::map::MapTerrain;
+use crate::map::MapWait;
+use crate::map::MapTransform;
+use crate::map::PointMapLight;
+use crate::map::resolve_template;
+use crate::map::SpotMapLight;
+use crate::map::Weapon;
+use crate::map_format::escape_pointer;
+use crate::map_ids::assign_ids;
+use crate::map_format::FormatError;
+use crate::map_format::MapFormat;
+use crate::map_migration::migrate;
+use crate::map_migration::MAP_VERSION;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Severity {
+	Error,
+	Warning
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MapDiagnostic {
+	pub severity: Severity,
+	pub file: String,
+	pub line: usize,
+	pub column: usize,
+	pub pointer: String,
+	pub message: String,
+}
+
+impl fmt::Display for MapDiagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let severity = match self.severity {
+			Severity::Error => "error",
+			Severity::Warning => "warning",
+		};
+
+		write!(f, "{}:{}:{}: {}: {}", self.file, self.line, self.column, severity, self.message)
+	}
+}
+
+#[derive(Debug, Clone, Default, Serialize, Resource)]
+pub struct MapDiagnostics {
+	pub diagnostics: Vec<MapDiagnostic>
+}
+
+impl MapDiagnostics {
+	pub fn has_errors(&self) -> bool {
+		self.diagnostics.iter().any(|d| d.severity == Severity::Error)
+	}
+
+	pub fn errors(&self) -> impl Iterator<Item = &MapDiagnostic> {
+		self.diagnostics.iter().filter(|d| d.severity == Severity::Error)
+	}
+
+	pub fn log(&self) {
+		for diagnostic in &self.diagnostics {
+			match diagnostic.severity {
+				Severity::Error => log::error!("{}", diagnostic),
+				Severity::Warning => log::warn!("{}", diagnostic),
+			}
+		}
+	}
+}
+
+/// Collects diagnostics for a map and the files it includes, resolving
+/// JSON pointers to the line and column they were written at.
+#[derive(Default)]
+struct Reporter {
+	/// Format and source of every file, to look up the positions of values
+	sources: HashMap<String, (MapFormat, String)>,
+	/// Where the elements of the merged map were defined, from the pointer
+	/// in the merged map to the file and pointer they came from
+	origins: HashMap<String, (String, String)>,
+	/// File that pointers without a known origin belong to
+	file: String,
+	diagnostics: Vec<MapDiagnostic>,
+}
+
+impl Reporter {
+	fn report_at(&mut self, severity: Severity, file: &str, pointer: &str, message: String) {
+		// Fall back to the closest parent that has a known position, so a
+		// missing field is reported at the object that should contain it.
+		let mut lookup = pointer;
+
+		let (line, column) = loop {
+			if let Some(position) = self.find_position(file, lookup) {
+				break position;
+			}
+
+			match lookup.rfind('/') {
+				Some(index) => lookup = &lookup[..index],
+				None => break (1, 1),
+			}
+		};
+
+		self.diagnostics.push(MapDiagnostic {
+			severity,
+			file: file.to_string(),
+			line,
+			column,
+			pointer: pointer.to_string(),
+			message,
+		});
+	}
+
+	fn report(&mut self, severity: Severity, pointer: &str, message: String) {
+		// `/templates/3/asset` is looked up as `/templates/3`, then `/templates`
+		let prefixes = pointer
+			.match_indices('/')
+			.map(|(index, _)| &pointer[..index])
+			.chain(std::iter::once(pointer))
+			.skip(1)
+			.take(2)
+			.collect::<Vec<_>>();
+
+		let origin = prefixes
+			.iter()
+			.rev()
+			.find_map(|prefix| self.origins.get(*prefix).map(|origin| (*prefix, origin.clone())));
+
+		match origin {
+			Some((prefix, (file, origin_prefix))) => {
+				let pointer = format!("{}{}", origin_prefix, &pointer[prefix.len()..]);
+
+				self.report_at(severity, &file, &pointer, message);
+			},
+			None => {
+				let file = self.file.clone();
+
+				self.report_at(severity, &file, pointer, message);
+			}
+		}
+	}
+
+	fn error(&mut self, pointer: &str, message: String) {
+		self.report(Severity::Error, pointer, message);
+	}
+
+	fn warning(&mut self, pointer: &str, message: String) {
+		self.report(Severity::Warning, pointer, message);
+	}
+
+	fn position(&self, file: &str, pointer: &str) -> (usize, usize) {
+		self.find_position(file, pointer).unwrap_or((1, 1))
+	}
+
+	fn find_position(&self, file: &str, pointer: &str) -> Option<(usize, usize)> {
+		let (format, source) = self.sources.get(file)?;
+
+		format.position(source, pointer)
+	}
+}
+
+pub struct ValidatedMap {
+	/// The merged map, whenever every file could be deserialized, even if
+	/// validation found errors in it
+	pub map: Option<Map>,
+	pub diagnostics: MapDiagnostics,
+	/// Every file of the include graph, including the ones that failed to load
+	pub files: Vec<String>,
+}
+
+/// Reads, merges and validates a map and all the files it includes.
+pub fn validate_map(
+	path: &str,
+	read: &dyn Fn(&str) -> std::io::Result<String>,
+	assets_dir: Option<&Path>,
+) -> ValidatedMap {
+	let root = normalize_path(Path::new(path));
+
+	let mut reporter = Reporter::default();
+	let mut loader = IncludeLoader {
+		read,
+		stack: Vec::new(),
+		files: Vec::new(),
+		documents: Vec::new(),
+		failed: false,
+	};
+
+	loader.load(&mut reporter, &root, None);
+
+	let files = loader.files;
+
+	if loader.failed {
+		sort_diagnostics(&mut reporter.diagnostics, &root);
+
+		return ValidatedMap {
+			map: None,
+			diagnostics: MapDiagnostics { diagnostics: reporter.diagnostics },
+			files,
+		};
+	}
+
+	let mut map = merge_documents(&mut reporter, loader.documents);
+
+	reporter.file = root.clone();
+
+	check_references(&mut reporter, &map, assets_dir);
+	check_values(&mut reporter, &map);
+
+	assign_ids(&mut map);
+
+	sort_diagnostics(&mut reporter.diagnostics, &root);
+
+	ValidatedMap {
+		map: Some(map),
+		diagnostics: MapDiagnostics { diagnostics: reporter.diagnostics },
+		files,
+	}
+}
+
+/// Validates a map given as a string. Included files are read from disk
+/// relative to `file`.
+pub fn parse_and_validate(
+	file: &str,
+	source: &str,
+	assets_dir: Option<&Path>,
+) -> ValidatedMap {
+	let root = normalize_path(Path::new(file));
+
+	let read = |path: &str| {
+		if path == root {
+			Ok(source.to_string())
+		} else {
+			std::fs::read_to_string(path)
+		}
+	};
+
+	validate_map(file, &read, assets_dir)
+}
+
+pub fn load_and_validate(
+	path: &str,
+	assets_dir: Option<&Path>,
+) -> ValidatedMap {
+	validate_map(path, &|path| std::fs::read_to_string(path), assets_dir)
+}
+
+/// Sorts diagnostics by position, the root file first.
+fn sort_diagnostics(diagnostics: &mut Vec<MapDiagnostic>, root: &str) {
+	diagnostics.sort_by(|a, b| {
+		(a.file != root, &a.file, a.line, a.column).cmp(&(b.file != root, &b.file, b.line, b.column))
+	});
+}
+
+fn format_diagnostic(file: &str, err: &FormatError) -> MapDiagnostic {
+	MapDiagnostic {
+		severity: Severity::Error,
+		file: file.to_string(),
+		line: err.line,
+		column: err.column,
+		pointer: String::new(),
+		message: err.message.clone(),
+	}
+}
+
+/// Lexically normalizes a path so that the same file is always known by the
+/// same name, also where there is no file system to ask (wasm).
+pub fn normalize_path(path: &Path) -> String {
+	let mut parts: Vec<String> = Vec::new();
+
+	for component in path.components() {
+		match component {
+			Component::CurDir => {},
+			Component::ParentDir => {
+				match parts.last() {
+					Some(last) if last != ".." && last != "" => {
+						parts.pop();
+					},
+					_ => parts.push("..".to_string()),
+				}
+			},
+			Component::RootDir => parts.push(String::new()),
+			Component::Prefix(prefix) => parts.push(prefix.as_os_str().to_string_lossy().to_string()),
+			Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+		}
+	}
+
+	if parts.len() == 1 && parts[0] == "" {
+		return "/".to_string();
+	}
+
+	parts.join("/")
+}
+
+fn include_path(including_file: &str, include: &str) -> String {
+	let base = Path::new(including_file).parent().unwrap_or(Path::new(""));
+
+	normalize_path(&base.join(include))
+}
+
+struct IncludeLoader<'a> {
+	read: &'a dyn Fn(&str) -> std::io::Result<String>,
+	/// Files currently being loaded, to detect include cycles
+	stack: Vec<String>,
+	files: Vec<String>,
+	/// Parsed files in merge order, included files before their includer
+	documents: Vec<(String, Map)>,
+	failed: bool,
+}
+
+impl<'a> IncludeLoader<'a> {
+	fn load(&mut self, reporter: &mut Reporter, file: &str, included_from: Option<(&str, &str)>) {
+		self.files.push(file.to_string());
+
+		let source = match (self.read)(file) {
+			Ok(source) => source,
+			Err(err) => {
+				self.failed = true;
+
+				let message = format!("could not read map: {}", err);
+
+				match included_from {
+					Some((including_file, pointer)) => {
+						reporter.report_at(Severity::Error, including_file, pointer, message);
+					},
+					None => {
+						reporter.diagnostics.push(MapDiagnostic {
+							severity: Severity::Error,
+							file: file.to_string(),
+							line: 0,
+							column: 0,
+							pointer: String::new(),
+							message,
+						});
+					}
+				}
+
+				return;
+			}
+		};
+
+		let format = MapFormat::from_path(file);
+
+		let mut value = match format.parse(&source) {
+			Ok(value) => {
+				reporter.sources.insert(file.to_string(), (format, source.clone()));
+
+				value
+			},
+			Err(err) => {
+				self.failed = true;
+				reporter.diagnostics.push(format_diagnostic(file, &err));
+
+				return;
+			}
+		};
+
+		reporter.file = file.to_string();
+
+		let version = match migrate(&mut value) {
+			Ok(version) => version,
+			Err(err) => {
+				self.failed = true;
+				reporter.report_at(Severity::Error, file, "/version", err.to_string());
+
+				return;
+			}
+		};
+
+		check_unknown_fields(reporter, &value);
+
+		// Older documents are read from the migrated value, which has no
+		// line information for deserialization errors
+		let map = match version {
+			MAP_VERSION => format.deserialize::<Map>(&source),
+			_ => serde_json::from_value::<Map>(value).map_err(FormatError::from),
+		};
+
+		let map = match map {
+			Ok(map) => map,
+			Err(err) if err.line == 0 => {
+				self.failed = true;
+
+				let message = match version {
+					MAP_VERSION => err.message,
+					_ => format!("{} (after migrating from version {})", err, version),
+				};
+				reporter.report_at(Severity::Error, file, "", message);
+
+				return;
+			},
+			Err(err) => {
+				self.failed = true;
+				reporter.diagnostics.push(format_diagnostic(file, &err));
+
+				return;
+			}
+		};
+
+		self.stack.push(file.to_string());
+
+		for (index, include) in map.includes.iter().flatten().enumerate() {
+			let pointer = format!("/includes/{}", index);
+			let included = include_path(file, include);
+
+			if self.stack.contains(&included) {
+				self.failed = true;
+
+				reporter.report_at(
+					Severity::Error,
+					file,
+					&pointer,
+					format!("`{}` includes itself through {}", included, self.stack.join(" -> "))
+				);
+
+				continue;
+			}
+
+			// Shared files included from several places are merged once
+			if self.files.contains(&included) {
+				continue;
+			}
+
+			self.load(reporter, &included, Some((file, &pointer)));
+		}
+
+		self.stack.pop();
+
+		self.documents.push((file.to_string(), map));
+	}
+}
+
+/// Concatenates the documents of an include graph into one map, reporting
+/// names and ids that are defined more than once.
+fn merge_documents(reporter: &mut Reporter, documents: Vec<(String, Map)>) -> Map {
+	let mut merged = Map {
+		version: Some(MAP_VERSION),
+		..Default::default()
+	};
+
+	let mut template_names: HashMap<String, (String, String)> = HashMap::new();
+	let mut material_names: HashMap<String, (String, String)> = HashMap::new();
+	let mut entity_ids: HashMap<String, (String, String)> = HashMap::new();
+	let mut shape_ids: HashMap<String, (String, String)> = HashMap::new();
+	let mut light_ids: HashMap<String, (String, String)> = HashMap::new();
+	let mut prefab_names: HashMap<String, (String, String)> = HashMap::new();
+	let mut instance_ids: HashMap<String, (String, String)> = HashMap::new();
+
+	fn check_unique(
+		reporter: &mut Reporter,
+		seen: &mut HashMap<String, (String, String)>,
+		what: &str,
+		id: &str,
+		file: &str,
+		pointer: String,
+	) {
+		if id == "" {
+			return;
+		}
+
+		match seen.get(id) {
+			Some((first_file, first_pointer)) => {
+				let (line, column) = reporter.position(first_file, first_pointer);
+
+				reporter.report_at(
+					Severity::Error,
+					file,
+					&pointer,
+					format!("duplicate {} `{}`, first defined at {}:{}:{}", what, id, first_file, line, column)
+				);
+			},
+			None => {
+				seen.insert(id.to_string(), (file.to_string(), pointer));
+			}
+		}
+	}
+
+	for (file, map) in documents {
+		for (index, template) in map.templates.into_iter().flatten().enumerate() {
+			let merged_templates = merged.templates.get_or_insert_with(Vec::new);
+			let pointer = format!("/templates/{}", index);
+
+			check_unique(reporter, &mut template_names, "template name", &template.name, &file, format!("{}/name", pointer));
+
+			reporter.origins.insert(format!("/templates/{}", merged_templates.len()), (file.clone(), pointer));
+			merged_templates.push(template);
+		}
+
+		for (index, material) in map.materials.into_iter().flatten().enumerate() {
+			let merged_materials = merged.materials.get_or_insert_with(Vec::new);
+			let pointer = format!("/materials/{}", index);
+
+			check_unique(reporter, &mut material_names, "material name", &material.name, &file, format!("{}/name", pointer));
+
+			reporter.origins.insert(format!("/materials/{}", merged_materials.len()), (file.clone(), pointer));
+			merged_materials.push(material);
+		}
+
+		for (index, entity) in map.entities.into_iter().flatten().enumerate() {
+			let merged_entities = merged.entities.get_or_insert_with(Vec::new);
+			let pointer = format!("/entities/{}", index);
+
+			check_unique(reporter, &mut entity_ids, "entity_id", &entity.entity_id, &file, format!("{}/entity_id", pointer));
+
+			reporter.origins.insert(format!("/entities/{}", merged_entities.len()), (file.clone(), pointer));
+			merged_entities.push(entity);
+		}
+
+		for (index, shape) in map.shapes.into_iter().flatten().enumerate() {
+			let merged_shapes = merged.shapes.get_or_insert_with(Vec::new);
+			let pointer = format!("/shapes/{}", index);
+
+			check_unique(reporter, &mut shape_ids, "shape id", &shape.id, &file, format!("{}/id", pointer));
+
+			reporter.origins.insert(format!("/shapes/{}", merged_shapes.len()), (file.clone(), pointer));
+			merged_shapes.push(shape);
+		}
+
+		for (index, light) in map.lights.into_iter().flatten().enumerate() {
+			let merged_lights = merged.lights.get_or_insert_with(Vec::new);
+			let pointer = format!("/lights/{}", index);
+
+			check_unique(reporter, &mut light_ids, "light id", &light.id, &file, format!("{}/id", pointer));
+
+			reporter.origins.insert(format!("/lights/{}", merged_lights.len()), (file.clone(), pointer));
+			merged_lights.push(light);
+		}
+
+		for (index, prefab) in map.prefabs.into_iter().flatten().enumerate() {
+			let merged_prefabs = merged.prefabs.get_or_insert_with(Vec::new);
+			let pointer = format!("/prefabs/{}", index);
+
+			check_unique(reporter, &mut prefab_names, "prefab name", &prefab.name, &file, format!("{}/name", pointer));
+
+			reporter.origins.insert(format!("/prefabs/{}", merged_prefabs.len()), (file.clone(), pointer));
+			merged_prefabs.push(prefab);
+		}
+
+		for (index, instance) in map.instances.into_iter().flatten().enumerate() {
+			let merged_instances = merged.instances.get_or_insert_with(Vec::new);
+			let pointer = format!("/instances/{}", index);
+
+			check_unique(reporter, &mut instance_ids, "instance id", &instance.id, &file, format!("{}/id", pointer));
+
+			reporter.origins.insert(format!("/instances/{}", merged_instances.len()), (file.clone(), pointer));
+			merged_instances.push(instance);
+		}
+
+		if let Some(ambient_light) = map.ambient_light {
+			if let Some((first_file, _)) = reporter.origins.get("/ambient_light") {
+				let message = format!("ambient_light is already defined in {}", first_file);
+				reporter.report_at(Severity::Error, &file, "/ambient_light", message);
+			} else {
+				reporter.origins.insert("/ambient_light".to_string(), (file.clone(), "/ambient_light".to_string()));
+				merged.ambient_light = Some(ambient_light);
+			}
+		}
+
+		if let Some(camera) = map.camera {
+			if let Some((first_file, _)) = reporter.origins.get("/camera") {
+				let message = format!("camera is already defined in {}", first_file);
+				reporter.report_at(Severity::Error, &file, "/camera", message);
+			} else {
+				reporter.origins.insert("/camera".to_string(), (file.clone(), "/camera".to_string()));
+				merged.camera = Some(camera);
+			}
+		}
+
+		if let Some(navigation) = map.navigation {
+			if let Some((first_file, _)) = reporter.origins.get("/navigation") {
+				let message = format!("navigation is already defined in {}", first_file);
+				reporter.report_at(Severity::Error, &file, "/navigation", message);
+			} else {
+				reporter.origins.insert("/navigation".to_string(), (file.clone(), "/navigation".to_string()));
+				merged.navigation = Some(navigation);
+			}
+		}
+	}
+
+	merged
+}
+
+/// Hex colors as `Color::hex` reads them: 3, 4, 6 or 8 digits, optionally
+/// after a `#`
+fn is_valid_color(color: &str) -> bool {
+	let digits = color.strip_prefix('#').unwrap_or(color);
+
+	matches!(digits.len(), 3 | 4 | 6 | 8) && digits.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Items of a list of the map followed by the same list of every prefab,
+/// with their pointers, e.g. `/shapes/0` and `/prefabs/1/shapes/0`
+fn map_parts<'m, T>(
+	map: &'m Map,
+	key: &str,
+	top_level: &'m Option<Vec<T>>,
+	in_prefab: impl Fn(&'m MapPrefab) -> &'m Option<Vec<T>>,
+) -> Vec<(String, &'m T)> {
+	let mut parts: Vec<(String, &T)> = top_level
+		.iter()
+		.flatten()
+		.enumerate()
+		.map(|(index, part)| (format!("/{}/{}", key, index), part))
+		.collect();
+
+	for (prefab_index, prefab) in map.prefabs.iter().flatten().enumerate() {
+		parts.extend(
+			in_prefab(prefab)
+				.iter()
+				.flatten()
+				.enumerate()
+				.map(|(index, part)| (format!("/prefabs/{}/{}/{}", prefab_index, key, index), part))
+		);
+	}
+
+	parts
+}
+
+fn check_references(reporter: &mut Reporter, map: &Map, assets_dir: Option<&Path>) {
+	let mut material_names = HashSet::new();
+
+	for (index, material) in map.materials.iter().flatten().enumerate() {
+		let pointer = format!("/materials/{}", index);
+
+		if material.name == "" {
+			reporter.error(&pointer, "material has no name".to_string());
+		}
+
+		material_names.insert(material.name.as_str());
+
+		for (field, color) in [("base_color", &material.base_color), ("emissive", &material.emissive)] {
+			if let Some(color) = color {
+				if !is_valid_color(color) {
+					reporter.error(&format!("{}/{}", pointer, field), format!("invalid hex color `{}`", color));
+				}
+			}
+		}
+
+		if let Some(assets_dir) = assets_dir {
+			for (field, texture) in material.textures() {
+				if !assets_dir.join(texture).exists() {
+					reporter.warning(
+						&format!("{}/{}", pointer, field),
+						format!("material `{}` references missing texture `{}`", material.name, texture)
+					);
+				}
+			}
+		}
+	}
+
+	let check_material_overrides = |reporter: &mut Reporter, pointer: &str, template: &MapTemplate| {
+		for (gltf_material, material) in template.materials.iter().flatten() {
+			if !material_names.contains(material.as_str()) {
+				reporter.error(
+					&format!("{}/materials/{}", pointer, escape_pointer(gltf_material)),
+					format!("template references unknown material `{}`", material)
+				);
+			}
+		}
+	};
+
+	let mut template_names = HashSet::new();
+
+	for (index, template) in map.templates.iter().flatten().enumerate() {
+		let pointer = format!("/templates/{}", index);
+
+		if template.name == "" {
+			reporter.error(&pointer, "template has no name".to_string());
+		}
+
+		template_names.insert(template.name.as_str());
+
+		check_material_overrides(reporter, &pointer, template);
+
+		if let (Some(asset), Some(assets_dir)) = (&template.asset, assets_dir) {
+			// Bevy asset paths may carry a label such as `#Scene0`
+			let file = asset.split('#').next().unwrap_or_default();
+
+			if !assets_dir.join(file).exists() {
+				reporter.warning(
+					&format!("{}/asset", pointer),
+					format!("template `{}` references missing asset `{}`", template.name, asset)
+				);
+			}
+		}
+	}
+
+	let templates = map.templates.as_deref().unwrap_or_default();
+
+	for (index, template) in templates.iter().enumerate() {
+		if template.extends.is_none() {
+			continue;
+		}
+
+		let lookup = |name: &str| templates.iter().find(|t| t.name == name);
+
+		if let Err(err) = resolve_template(&template.name, lookup) {
+			reporter.error(&format!("/templates/{}/extends", index), err.to_string());
+		}
+	}
+
+	for (index, template) in templates.iter().enumerate() {
+		let lookup = |name: &str| templates.iter().find(|t| t.name == name);
+
+		// Templates that don't resolve are reported above
+		let resolved = match resolve_template(&template.name, lookup) {
+			Ok(resolved) if resolved.asset.is_none() => resolved,
+			_ => continue,
+		};
+
+		if let Some(MapEntityCollider::AABB(_)) = resolved.collider {
+			reporter.warning(
+				&format!("/templates/{}/collider", index),
+				format!("template `{}` has no asset to fit its AABB collider to", template.name)
+			);
+		}
+
+		if resolved.collision_mesh().is_some() {
+			reporter.warning(
+				&format!("/templates/{}/collision_mesh", index),
+				format!("template `{}` has no asset to build a collision mesh from", template.name)
+			);
+		}
+	}
+
+	let mut entity_ids: HashSet<String> = map.entities
+		.iter()
+		.flatten()
+		.map(|entity| entity.entity_id.clone())
+		.collect();
+
+	for (pointer, entity) in map_parts(map, "entities", &map.entities, |prefab| &prefab.entities) {
+		if let Some(overrides) = &entity.overrides {
+			check_material_overrides(reporter, &format!("{}/overrides", pointer), overrides);
+		}
+
+		if !template_names.contains(entity.template.as_str()) {
+			reporter.error(
+				&format!("{}/template", pointer),
+				format!("entity references unknown template `{}`", entity.template)
+			);
+		}
+	}
+
+	let mut prefabs: HashMap<&str, &MapPrefab> = HashMap::new();
+
+	for (index, prefab) in map.prefabs.iter().flatten().enumerate() {
+		let pointer = format!("/prefabs/{}", index);
+
+		if prefab.name.is_empty() {
+			reporter.error(&pointer, "prefab has no name".to_string());
+		}
+
+		prefabs.insert(prefab.name.as_str(), prefab);
+
+		let part_ids = prefab.entities
+			.iter()
+			.flatten()
+			.enumerate()
+			.map(|(part_index, e)| (format!("{}/entities/{}/entity_id", pointer, part_index), &e.entity_id))
+			.chain(prefab.shapes.iter().flatten().enumerate().map(|(part_index, s)| (format!("{}/shapes/{}/id", pointer, part_index), &s.id)))
+			.chain(prefab.lights.iter().flatten().enumerate().map(|(part_index, l)| (format!("{}/lights/{}/id", pointer, part_index), &l.id)));
+
+		let mut seen = HashSet::new();
+
+		for (part_pointer, id) in part_ids {
+			if !id.is_empty() && !seen.insert(id) {
+				reporter.error(&part_pointer, format!("duplicate part id `{}` in prefab `{}`", id, prefab.name));
+			}
+		}
+	}
+
+	for (index, instance) in map.instances.iter().flatten().enumerate() {
+		let pointer = format!("/instances/{}", index);
+
+		let prefab = match prefabs.get(instance.prefab.as_str()) {
+			Some(prefab) => prefab,
+			None => {
+				reporter.error(
+					&format!("{}/prefab", pointer),
+					format!("instance references unknown prefab `{}`", instance.prefab)
+				);
+
+				continue;
+			}
+		};
+
+		for (part_id, part_override) in instance.overrides.iter().flatten() {
+			let override_pointer = format!("{}/overrides/{}", pointer, escape_pointer(part_id));
+			let what = format!("override of `{}`", part_id);
+
+			if prefab.entities.iter().flatten().any(|e| &e.entity_id == part_id) {
+				check_object_fields(reporter, part_override, &override_pointer, &what, serde_fields::<MapEntity>());
+			} else if prefab.shapes.iter().flatten().any(|s| &s.id == part_id) {
+				check_object_fields(reporter, part_override, &override_pointer, &what, serde_fields::<MapShape>());
+			} else if prefab.lights.iter().flatten().any(|l| &l.id == part_id) {
+				check_object_fields(reporter, part_override, &override_pointer, &what, serde_fields::<Light>());
+			} else {
+				reporter.error(&override_pointer, format!("prefab `{}` has no part `{}`", prefab.name, part_id));
+			}
+		}
+
+		match prefab.instantiate(instance) {
+			Ok(parts) => {
+				entity_ids.extend(parts.entities.into_iter().map(|entity| entity.entity_id));
+			},
+			Err(err) => {
+				reporter.error(&format!("{}/overrides", pointer), format!("invalid overrides: {}", err));
+			}
+		}
+	}
+
+	if let Some(camera) = &map.camera {
+		if !entity_ids.contains(&camera.entity_id) {
+			reporter.error(
+				"/camera/entity_id",
+				format!("camera references unknown entity `{}`", camera.entity_id)
+			);
+		}
+	}
+
+	for (pointer, shape) in map_parts(map, "shapes", &map.shapes, |prefab| &prefab.shapes) {
+		let pointer = format!("{}/shape", pointer);
+
+		if let Some(material) = shape.shape.material() {
+			if !material_names.contains(material) {
+				reporter.error(
+					&format!("{}/material", pointer),
+					format!("shape references unknown material `{}`", material)
+				);
+			}
+		}
+
+		if let MapShapeType::Terrain(terrain) = &shape.shape {
+			for (layer_index, layer) in terrain.layers.iter().flatten().enumerate() {
+				if !material_names.contains(layer.as_str()) {
+					reporter.error(
+						&format!("{}/layers/{}", pointer, layer_index),
+						format!("terrain layer references unknown material `{}`", layer)
+					);
+				}
+			}
+
+			let images = [("heightmap", Some(&terrain.heightmap)), ("splat_map", terrain.splat_map.as_ref())];
+
+			for (field, image) in images {
+				if let (Some(image), Some(assets_dir)) = (image, assets_dir) {
+					if !assets_dir.join(image).exists() {
+						reporter.warning(
+							&format!("{}/{}", pointer, field),
+							format!("terrain references missing image `{}`", image)
+						);
+					}
+				}
+			}
+		}
+	}
+
+	for (pointer, light) in map_parts(map, "lights", &map.lights, |prefab| &prefab.lights) {
+		let color = match &light.light_type {
+			LightType::Point(point) => &point.color,
+			LightType::Directional(directional) => &directional.color,
+			LightType::Spot(spot) => &spot.color,
+		};
+
+		if !is_valid_color(color) {
+			reporter.error(
+				&format!("{}/type/color", pointer),
+				format!("invalid hex color `{}`", color)
+			);
+		}
+	}
+
+	if let Some(ambient_light) = &map.ambient_light {
+		if !is_valid_color(&ambient_light.color) {
+			reporter.error(
+				"/ambient_light/color",
+				format!("invalid hex color `{}`", ambient_light.color)
+			);
+		}
+	}
+}
+
+fn check_positive(reporter: &mut Reporter, pointer: &str, what: &str, value: f32) {
+	if !value.is_finite() || value <= 0.0 {
+		reporter.error(pointer, format!("{} must be a positive number, got {}", what, value));
+	}
+}
+
+fn check_non_negative(reporter: &mut Reporter, pointer: &str, what: &str, value: f32) {
+	if !value.is_finite() || value < 0.0 {
+		reporter.error(pointer, format!("{} must not be negative, got {}", what, value));
+	}
+}
+
+fn check_finite(reporter: &mut Reporter, pointer: &str, what: &str, values: &[f32]) {
+	if values.iter().any(|v| !v.is_finite()) {
+		reporter.error(pointer, format!("{} must only contain finite numbers", what));
+	}
+}
+
+fn check_transform(reporter: &mut Reporter, pointer: &str, transform: &MapTransform) {
+	if let Some(position) = transform.position {
+		check_finite(reporter, &format!("{}/position", pointer), "position", &position);
+	}
+
+	match transform.rotation {
+		Some(MapRotation::Euler(rotation)) => {
+			check_finite(reporter, &format!("{}/rotation", pointer), "rotation", &rotation);
+		},
+		Some(MapRotation::Quaternion(quaternion)) if quaternion.iter().any(|v| !v.is_finite()) || quaternion == [0.0; 4] => {
+			reporter.error(&format!("{}/rotation", pointer), format!("invalid rotation quaternion {:?}", quaternion));
+		},
+		_ => {}
+	}
+
+	match transform.scale {
+		Some(MapScale::Uniform(scale)) => {
+			check_positive(reporter, &format!("{}/scale", pointer), "scale", scale);
+		},
+		Some(MapScale::PerAxis(scale)) => {
+			for (axis, value) in scale.iter().enumerate() {
+				check_positive(reporter, &format!("{}/scale/{}", pointer, axis), "scale", *value);
+			}
+		},
+		None => {}
+	}
+}
+
+/// Checks the numbers of a behavior node and every node below it
+fn check_behavior(reporter: &mut Reporter, behavior: &MapBehavior, pointer: &str) {
+	match behavior {
+		MapBehavior::Sequence(MapBehaviorChildren { children }) | MapBehavior::Selector(MapBehaviorChildren { children }) => {
+			if children.is_empty() {
+				reporter.warning(&format!("{}/children", pointer), "behavior node has no children".to_string());
+			}
+
+			for (index, child) in children.iter().enumerate() {
+				check_behavior(reporter, child, &format!("{}/children/{}", pointer, index));
+			}
+		},
+		MapBehavior::Invert(MapBehaviorChild { child }) | MapBehavior::AlwaysSucceed(MapBehaviorChild { child }) => {
+			check_behavior(reporter, child, &format!("{}/child", pointer));
+		},
+		MapBehavior::Cooldown(MapCooldown { seconds, child }) => {
+			check_positive(reporter, &format!("{}/seconds", pointer), "cooldown seconds", *seconds);
+			check_behavior(reporter, child, &format!("{}/child", pointer));
+		},
+		MapBehavior::PlayerInRange(MapPlayerInRange { range }) => {
+			check_positive(reporter, &format!("{}/range", pointer), "player range", *range);
+		},
+		MapBehavior::HealthBelow(MapHealthBelow { fraction }) => {
+			if !(0.0..=1.0).contains(fraction) {
+				reporter.error(&format!("{}/fraction", pointer), format!("fraction must be between 0 and 1, got {}", fraction));
+			}
+		},
+		MapBehavior::Flee(MapFlee { distance }) => {
+			check_positive(reporter, &format!("{}/distance", pointer), "flee distance", *distance);
+		},
+		MapBehavior::Wait(MapWait { seconds }) => {
+			check_positive(reporter, &format!("{}/seconds", pointer), "wait seconds", *seconds);
+		},
+		MapBehavior::Patrol | MapBehavior::Chase | MapBehavior::Attack | MapBehavior::ReturnHome => {},
+	}
+}
+
+/// Sanity checks for numeric values that would otherwise only blow up
+/// once they reach the physics engine or the renderer.
+fn check_values(reporter: &mut Reporter, map: &Map) {
+	for (index, template) in map.templates.iter().flatten().enumerate() {
+		let pointer = format!("/templates/{}", index);
+
+		match &template.collider {
+			Some(MapEntityCollider::Capsule(MapCapsuleCollider { a, b, radius })) => {
+				let collider = format!("{}/collider", pointer);
+
+				check_finite(reporter, &collider, "capsule end points", &[*a, *b]);
+				check_positive(reporter, &format!("{}/radius", collider), "capsule radius", *radius);
+			},
+			Some(MapEntityCollider::Cuboid(MapCuboidCollider { x, y, z })) => {
+				let collider = format!("{}/collider", pointer);
+
+				check_positive(reporter, &format!("{}/x", collider), "cuboid x", *x);
+				check_positive(reporter, &format!("{}/y", collider), "cuboid y", *y);
+				check_positive(reporter, &format!("{}/z", collider), "cuboid z", *z);
+			},
+			Some(MapEntityCollider::AABB(MapAabbCollider { padding: Some(padding) })) => {
+				check_non_negative(reporter, &format!("{}/collider/padding", pointer), "bounding box padding", *padding);
+			},
+			Some(MapEntityCollider::AABB(MapAabbCollider { padding: None })) | None => {},
+		}
+
+		if let Some(mass) = template.mass {
+			check_positive(reporter, &format!("{}/mass", pointer), "mass", mass);
+		}
+
+		if let Some(friction) = template.friction {
+			check_non_negative(reporter, &format!("{}/friction", pointer), "friction", friction);
+		}
+
+		if let Some(behavior) = &template.behavior {
+			check_behavior(reporter, behavior, &format!("{}/behavior", pointer));
+		}
+
+		if let Some(meshes) = template.collision_mesh.as_ref().and_then(|c| c.meshes.as_ref()) {
+			let meshes_pointer = format!("{}/collision_mesh/meshes", pointer);
+
+			if meshes.is_empty() {
+				reporter.warning(&meshes_pointer, "collision_mesh lists no meshes, no colliders are built".to_string());
+			}
+
+			for (mesh_index, mesh) in meshes.iter().enumerate() {
+				if mesh.is_empty() {
+					reporter.error(&format!("{}/{}", meshes_pointer, mesh_index), "collision mesh name must not be empty".to_string());
+				}
+			}
+		}
+	}
+
+	for (index, material) in map.materials.iter().flatten().enumerate() {
+		let pointer = format!("/materials/{}", index);
+
+		for (field, value) in [
+			("metallic", material.metallic),
+			("roughness", material.roughness),
+			("alpha_cutoff", material.alpha_cutoff),
+		] {
+			if let Some(value) = value {
+				if !(0.0..=1.0).contains(&value) {
+					reporter.error(&format!("{}/{}", pointer, field), format!("{} must be between 0 and 1, got {}", field, value));
+				}
+			}
+		}
+
+		if let Some([u, v]) = material.uv_scale {
+			check_positive(reporter, &format!("{}/uv_scale/0", pointer), "uv_scale", u);
+			check_positive(reporter, &format!("{}/uv_scale/1", pointer), "uv_scale", v);
+		}
+	}
+
+	for (pointer, shape) in map_parts(map, "shapes", &map.shapes, |prefab| &prefab.shapes) {
+		if let Some(transform) = &shape.transform {
+			check_transform(reporter, &format!("{}/transform", pointer), transform);
+		}
+
+		let pointer = format!("{}/shape", pointer);
+
+		if let MapShapeType::Terrain(terrain) = &shape.shape {
+			check_positive(reporter, &format!("{}/size/0", pointer), "terrain width", terrain.size[0]);
+			check_positive(reporter, &format!("{}/size/1", pointer), "terrain depth", terrain.size[1]);
+			check_finite(reporter, &format!("{}/height_scale", pointer), "height_scale", &[terrain.height_scale]);
+
+			if let Some(resolution) = terrain.resolution {
+				if resolution < 2 {
+					reporter.error(&format!("{}/resolution", pointer), format!("terrain resolution must be at least 2, got {}", resolution));
+				}
+			}
+
+			if let Some(max_slope) = terrain.max_slope {
+				if !(0.0..=std::f32::consts::FRAC_PI_2).contains(&max_slope) {
+					reporter.error(
+						&format!("{}/max_slope", pointer),
+						format!("max_slope must be between 0 and {}, got {}", std::f32::consts::FRAC_PI_2, max_slope)
+					);
+				}
+			}
+
+			match (&terrain.layers, &terrain.splat_map) {
+				(Some(layers), _) if layers.len() > 4 => {
+					reporter.error(&format!("{}/layers", pointer), format!("terrain can have at most 4 layers, got {}", layers.len()));
+				},
+				(Some(_), None) => {
+					reporter.warning(&format!("{}/layers", pointer), "terrain layers are not used without a splat_map".to_string());
+				},
+				_ => {}
+			}
+		}
+	}
+
+	for (pointer, entity) in map_parts(map, "entities", &map.entities, |prefab| &prefab.entities) {
+		if let Some(transform) = &entity.transform {
+			check_transform(reporter, &format!("{}/transform", pointer), transform);
+		}
+
+		if let Some(max_health) = entity.max_health {
+			check_positive(reporter, &format!("{}/max_health", pointer), "max_health", max_health);
+		}
+
+		if let Some(move_cycle) = &entity.move_cycle {
+			let move_cycle_pointer = format!("{}/move_cycle", pointer);
+
+			if move_cycle.is_empty() {
+				reporter.error(&move_cycle_pointer, "move_cycle needs at least one point".to_string());
+			}
+
+			for (point_index, point) in move_cycle.iter().enumerate() {
+				let point_pointer = format!("{}/{}", move_cycle_pointer, point_index);
+
+				check_finite(reporter, &point_pointer, "move_cycle point", point);
+
+				// Move cycle points are snapped to the integer navigation grid
+				if point.iter().any(|v| v.fract() != 0.0) {
+					reporter.warning(
+						&point_pointer,
+						format!("move_cycle point {:?} is truncated to whole numbers", point)
+					);
+				}
+			}
+		}
+	}
+
+	for (pointer, light) in map_parts(map, "lights", &map.lights, |prefab| &prefab.lights) {
+		let pointer = format!("{}/type", pointer);
+
+		match &light.light_type {
+			LightType::Point(point) => {
+				if let Some(intensity) = point.intensity {
+					check_non_negative(reporter, &format!("{}/intensity", pointer), "light intensity", intensity);
+				}
+
+				if let Some(range) = point.range {
+					check_positive(reporter, &format!("{}/range", pointer), "light range", range);
+				}
+
+				if let Some(radius) = point.radius {
+					check_non_negative(reporter, &format!("{}/radius", pointer), "light radius", radius);
+				}
+			},
+			LightType::Directional(directional) => {
+				if let Some(illuminance) = directional.illuminance {
+					check_non_negative(reporter, &format!("{}/illuminance", pointer), "light illuminance", illuminance);
+				}
+
+				if let Some(rotation) = directional.rotation {
+					check_finite(reporter, &format!("{}/rotation", pointer), "light rotation", &rotation);
+				}
+
+				if let Some(Err(err)) = directional.shadow_cascades.as_ref().map(|c| c.resolve()) {
+					reporter.error(&format!("{}/shadow_cascades", pointer), err);
+				}
+			},
+			LightType::Spot(spot) => {
+				if let Some(intensity) = spot.intensity {
+					check_non_negative(reporter, &format!("{}/intensity", pointer), "light intensity", intensity);
+				}
+
+				if let Some(range) = spot.range {
+					check_positive(reporter, &format!("{}/range", pointer), "light range", range);
+				}
+
+				if let Some(radius) = spot.radius {
+					check_non_negative(reporter, &format!("{}/radius", pointer), "light radius", radius);
+				}
+
+				if let Some(direction) = spot.direction {
+					if direction.iter().all(|v| *v == 0.0) {
+						reporter.error(&format!("{}/direction", pointer), "spot light direction must not be zero".to_string());
+					}
+				}
+
+				let inner_angle = spot.inner_angle.unwrap_or(0.0);
+				let outer_angle = spot.outer_angle.unwrap_or(std::f32::consts::FRAC_PI_4);
+
+				if !(0.0..=std::f32::consts::FRAC_PI_2).contains(&outer_angle) {
+					reporter.error(
+						&format!("{}/outer_angle", pointer),
+						format!("spot light outer_angle must be between 0 and {}, got {}", std::f32::consts::FRAC_PI_2, outer_angle)
+					);
+				} else if !(0.0..=outer_angle).contains(&inner_angle) {
+					reporter.error(
+						&format!("{}/inner_angle", pointer),
+						format!("spot light inner_angle must be between 0 and outer_angle {}, got {}", outer_angle, inner_angle)
+					);
+				}
+			}
+		}
+	}
+
+	for (index, instance) in map.instances.iter().flatten().enumerate() {
+		if let Some(transform) = &instance.transform {
+			check_transform(reporter, &format!("/instances/{}/transform", index), transform);
+		}
+	}
+
+	if let Some(navigation) = &map.navigation {
+		let lengths = [
+			("agent_radius", "agent radius", navigation.agent_radius),
+			("agent_height", "agent height", navigation.agent_height),
+			("step_height", "step height", navigation.step_height),
+		];
+
+		for (field, what, value) in lengths {
+			if let Some(value) = value {
+				check_non_negative(reporter, &format!("/navigation/{}", field), what, value);
+			}
+		}
+
+		if let Some(max_slope) = navigation.max_slope {
+			if !(0.0..=std::f32::consts::FRAC_PI_2).contains(&max_slope) {
+				reporter.error("/navigation/max_slope", format!("max slope must be between 0 and pi / 2 radians, got {}", max_slope));
+			}
+		}
+	}
+
+	if let Some(ambient_light) = &map.ambient_light {
+		check_non_negative(reporter, "/ambient_light/brightness", "ambient brightness", ambient_light.brightness);
+	}
+}
+
+fn check_object_fields(
+	reporter: &mut Reporter,
+	value: &Value,
+	pointer: &str,
+	what: &str,
+	fields: &[&str],
+) {
+	let object = match value.as_object() {
+		Some(object) => object,
+		None => return,
+	};
+
+	for key in object.keys() {
+		if !fields.contains(&key.as_str()) {
+			reporter.warning(
+				&format!("{}/{}", pointer, escape_pointer(key)),
+				format!("unknown field `{}` in {}", key, what)
+			);
+		}
+	}
+}
+
+fn array_items<'v>(value: &'v Value, key: &str) -> impl Iterator<Item = (usize, &'v Value)> {
+	value
+		.get(key)
+		.and_then(|v| v.as_array())
+		.into_iter()
+		.flatten()
+		.enumerate()
+}
+
+/// Fields of the concrete struct behind a `"type"` tagged shape.
+fn shape_fields(shape_type: &str) -> Option<&'static [&'static str]> {
+	match shape_type {
+		"Cube" => Some(serde_fields::<MapCube>()),
+		"Plane" => Some(serde_fields::<MapPlane>()),
+		"Quad" => Some(serde_fields::<MapQuad>()),
+		"Circle" => Some(serde_fields::<MapCircle>()),
+		"Box" => Some(serde_fields::<MapBox>()),
+		"Terrain" => Some(serde_fields::<MapTerrain>()),
+		_ => None
+	}
+}
+
+/// Fields of the concrete struct behind a `"type"` tagged collider.
+fn collider_fields(collider_type: &str) -> Option<&'static [&'static str]> {
+	match collider_type {
+		"AABB" => Some(serde_fields::<MapAabbCollider>()),
+		"Capsule" => Some(serde_fields::<MapCapsuleCollider>()),
+		"Cuboid" => Some(serde_fields::<MapCuboidCollider>()),
+		_ => None
+	}
+}
+
+/// Fields of the concrete struct behind a `"type"` tagged behavior node.
+/// Actions without settings have no fields besides the tag.
+fn behavior_fields(behavior_type: &str) -> Option<&'static [&'static str]> {
+	match behavior_type {
+		"Sequence" | "Selector" => Some(serde_fields::<MapBehaviorChildren>()),
+		"Invert" | "AlwaysSucceed" => Some(serde_fields::<MapBehaviorChild>()),
+		"Cooldown" => Some(serde_fields::<MapCooldown>()),
+		"PlayerInRange" => Some(serde_fields::<MapPlayerInRange>()),
+		"HealthBelow" => Some(serde_fields::<MapHealthBelow>()),
+		"Patrol" | "Chase" | "Attack" | "ReturnHome" => Some(&[]),
+		"Flee" => Some(serde_fields::<MapFlee>()),
+		"Wait" => Some(serde_fields::<MapWait>()),
+		_ => None
+	}
+}
+
+/// Checks a behavior node and every node below it
+fn check_behavior_fields(reporter: &mut Reporter, behavior: &Value, pointer: &str) {
+	check_tagged_fields(reporter, behavior, pointer, "behavior", behavior_fields);
+
+	for (index, child) in array_items(behavior, "children") {
+		check_behavior_fields(reporter, child, &format!("{}/children/{}", pointer, index));
+	}
+
+	if let Some(child) = behavior.get("child") {
+		check_behavior_fields(reporter, child, &format!("{}/child", pointer));
+	}
+}
+
+/// Fields of the concrete struct behind a `"type"` tagged light.
+fn light_fields(light_type: &str) -> Option<&'static [&'static str]> {
+	match light_type {
+		"Point" => Some(serde_fields::<PointMapLight>()),
+		"Directional" => Some(serde_fields::<DirectionalMapLight>()),
+		"Spot" => Some(serde_fields::<SpotMapLight>()),
+		_ => None
+	}
+}
+
+fn check_tagged_fields(
+	reporter: &mut Reporter,
+	value: &Value,
+	pointer: &str,
+	what: &str,
+	fields_for: fn(&str) -> Option<&'static [&'static str]>,
+) {
+	let tag = match value.get("type").and_then(|t| t.as_str()) {
+		Some(tag) => tag,
+		None => return,
+	};
+
+	if let Some(fields) = fields_for(tag) {
+		let mut fields = fields.to_vec();
+		fields.push("type");
+
+		check_object_fields(reporter, value, pointer, &format!("{} `{}`", what, tag), &fields);
+	}
+}
+
+/// Checks the entities, shapes and lights of the map or of a prefab
+fn check_part_fields(reporter: &mut Reporter, parent: &Value, parent_pointer: &str) {
+	for (index, entity) in array_items(parent, "entities") {
+		let pointer = format!("{}/entities/{}", parent_pointer, index);
+
+		check_object_fields(reporter, entity, &pointer, "entity", serde_fields::<MapEntity>());
+
+		if let Some(transform) = entity.get("transform") {
+			check_object_fields(reporter, transform, &format!("{}/transform", pointer), "transform", serde_fields::<MapTransform>());
+		}
+
+		if let Some(overrides) = entity.get("overrides") {
+			check_object_fields(reporter, overrides, &format!("{}/overrides", pointer), "entity overrides", serde_fields::<MapTemplate>());
+
+			if let Some(behavior) = overrides.get("behavior") {
+				check_behavior_fields(reporter, behavior, &format!("{}/overrides/behavior", pointer));
+			}
+		}
+	}
+
+	for (index, shape) in array_items(parent, "shapes") {
+		let pointer = format!("{}/shapes/{}", parent_pointer, index);
+
+		check_object_fields(reporter, shape, &pointer, "shape", serde_fields::<MapShape>());
+
+		if let Some(transform) = shape.get("transform") {
+			check_object_fields(reporter, transform, &format!("{}/transform", pointer), "transform", serde_fields::<MapTransform>());
+		}
+
+		if let Some(shape_type) = shape.get("shape") {
+			check_tagged_fields(reporter, shape_type, &format!("{}/shape", pointer), "shape", shape_fields);
+		}
+	}
+
+	for (index, light) in array_items(parent, "lights") {
+		let pointer = format!("{}/lights/{}", parent_pointer, index);
+
+		check_object_fields(reporter, light, &pointer, "light", serde_fields::<Light>());
+
+		if let Some(light_type) = light.get("type") {
+			check_tagged_fields(reporter, light_type, &format!("{}/type", pointer), "light", light_fields);
+
+			if let Some(cascades) = light_type.get("shadow_cascades") {
+				let cascades_pointer = format!("{}/type/shadow_cascades", pointer);
+
+				check_object_fields(reporter, cascades, &cascades_pointer, "shadow cascades", serde_fields::<MapShadowCascades>());
+			}
+		}
+	}
+}
+
+fn check_unknown_fields(reporter: &mut Reporter, root: &Value) {
+	check_object_fields(reporter, root, "", "map", serde_fields::<Map>());
+
+	check_part_fields(reporter, root, "");
+
+	for (index, template) in array_items(root, "templates") {
+		let pointer = format!("/templates/{}", index);
+
+		check_object_fields(reporter, template, &pointer, "template", serde_fields::<MapTemplate>());
+
+		for (weapon_index, weapon) in array_items(template, "weapons") {
+			check_object_fields(reporter, weapon, &format!("{}/weapons/{}", pointer, weapon_index), "weapon", serde_fields::<Weapon>());
+		}
+
+		if let Some(collider) = template.get("collider") {
+			check_tagged_fields(reporter, collider, &format!("{}/collider", pointer), "collider", collider_fields);
+		}
+
+		if let Some(collision_mesh) = template.get("collision_mesh") {
+			check_object_fields(reporter, collision_mesh, &format!("{}/collision_mesh", pointer), "collision mesh", serde_fields::<MapCollisionMesh>());
+		}
+
+		if let Some(behavior) = template.get("behavior") {
+			check_behavior_fields(reporter, behavior, &format!("{}/behavior", pointer));
+		}
+	}
+
+	for (index, material) in array_items(root, "materials") {
+		check_object_fields(reporter, material, &format!("/materials/{}", index), "material", serde_fields::<MapMaterial>());
+	}
+
+	for (index, prefab) in array_items(root, "prefabs") {
+		let pointer = format!("/prefabs/{}", index);
+
+		check_object_fields(reporter, prefab, &pointer, "prefab", serde_fields::<MapPrefab>());
+		check_part_fields(reporter, prefab, &pointer);
+	}
+
+	for (index, instance) in array_items(root, "instances") {
+		let pointer = format!("/instances/{}", index);
+
+		check_object_fields(reporter, instance, &pointer, "prefab instance", serde_fields::<MapPrefabInstance>());
+
+		if let Some(transform) = instance.get("transform") {
+			check_object_fields(reporter, transform, &format!("{}/transform", pointer), "transform", serde_fields::<MapTransform>());
+		}
+	}
+
+	if let Some(ambient_light) = root.get("ambient_light") {
+		check_object_fields(reporter, ambient_light, "/ambient_light", "ambient light", serde_fields::<AmbientLight>());
+	}
+
+	if let Some(camera) = root.get("camera") {
+		check_object_fields(reporter, camera, "/camera", "camera", serde_fields::<MapCamera>());
+	}
+
+	if let Some(navigation) = root.get("navigation") {
+		check_object_fields(reporter, navigation, "/navigation", "navigation", serde_fields::<MapNavigation>());
+	}
+}
+
+/// Error used to smuggle field and variant names out of a `Deserialize`
+/// implementation, so the accepted keys always match the serde derives.
+#[derive(Debug)]
+struct Introspected(&'static [&'static str]);
+
+impl fmt::Display for Introspected {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:?}", self.0)
+	}
+}
+
+impl std::error::Error for Introspected {}
+
+impl de::Error for Introspected {
+	fn custom<T: fmt::Display>(_msg: T) -> Self {
+		Introspected(&[])
+	}
+}
+
+struct FieldCollector;
+
+impl<'de> de::Deserializer<'de> for FieldCollector {
+	type Error = Introspected;
+
+	fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+		Err(Introspected(&[]))
+	}
+
+	fn deserialize_struct<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		fields: &'static [&'static str],
+		_visitor: V,
+	) -> Result<V::Value, Self::Error> {
+		Err(Introspected(fields))
+	}
+
+	fn deserialize_enum<V: Visitor<'de>>(
+		self,
+		_name: &'static str,
+		variants: &'static [&'static str],
+		_visitor: V,
+	) -> Result<V::Value, Self::Error> {
+		Err(Introspected(variants))
+	}
+
+	forward_to_deserialize_any! {
+		bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+		bytes byte_buf option unit unit_struct newtype_struct seq tuple
+		tuple_struct map identifier ignored_any
+	}
+}
+
+/// Field names of a struct, or variant names of an externally tagged enum.
+pub(crate) fn serde_fields<T: DeserializeOwned>() -> &'static [&'static str] {
+	match T::deserialize(FieldCollector) {
+		Err(Introspected(fields)) => fields,
+		Ok(_) => &[],
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn messages(diagnostics: &MapDiagnostics) -> Vec<String> {
+		diagnostics.diagnostics.iter().map(|d| d.to_string()).collect()
+	}
+
+	#[test]
+	fn introspects_serde_fields() {
+		assert!(serde_fields::<MapEntity>().contains(&"entity_id"));
+		assert!(serde_fields::<MapTemplate>().contains(&"idle_animation"));
+	}
+
+	#[test]
+	fn lists_every_collider_field() {
+		for collider_type in ["AABB", "Capsule", "Cuboid"] {
+			let fields = collider_fields(collider_type).unwrap();
+
+			let mut collider = serde_json::Map::new();
+			collider.insert("type".to_string(), Value::from(collider_type));
+
+			for field in fields {
+				collider.insert(field.to_string(), Value::from(1.0));
+			}
+
+			assert!(serde_json::from_value::<MapEntityCollider>(Value::Object(collider.clone())).is_ok());
+
+			// Padding is the only optional collider field
+			for field in fields.iter().filter(|field| **field != "padding") {
+				let mut missing = collider.clone();
+				missing.remove(*field);
+
+				assert!(serde_json::from_value::<MapEntityCollider>(Value::Object(missing)).is_err());
+			}
+		}
+	}
+
+	#[test]
+	fn lists_every_behavior_field() {
+		let types = [
+			"Sequence", "Selector", "Invert", "AlwaysSucceed", "Cooldown", "PlayerInRange",
+			"HealthBelow", "Patrol", "Chase", "Attack", "Flee", "ReturnHome", "Wait",
+		];
+
+		for behavior_type in types {
+			let mut behavior = serde_json::Map::new();
+			behavior.insert("type".to_string(), Value::from(behavior_type));
+
+			for field in behavior_fields(behavior_type).unwrap() {
+				let value = match *field {
+					"children" => serde_json::json!([{ "type": "Patrol" }]),
+					"child" => serde_json::json!({ "type": "Patrol" }),
+					_ => Value::from(0.5),
+				};
+
+				behavior.insert(field.to_string(), value);
+			}
+
+			assert!(serde_json::from_value::<MapBehavior>(Value::Object(behavior)).is_ok(), "{}", behavior_type);
+		}
+	}
+
+	#[test]
+	fn reports_unknown_fields_with_position() {
+		let source = "{\n\t\"templates\": [\n\t\t{\n\t\t\t\"name\": \"orc\",\n\t\t\t\"speed\": 1\n\t\t}\n\t]\n}";
+
+		let ValidatedMap { map, diagnostics, .. } = parse_and_validate("map.json", source, None);
+
+		assert!(map.is_some());
+		assert_eq!(messages(&diagnostics), vec![
+			"map.json:5:10: warning: unknown field `speed` in template"
+		]);
+	}
+
+	#[test]
+	fn reports_unknown_fields_in_tagged_shapes() {
+		let source = r#"{"shapes": [{"shape": {"type": "Cube", "size": 1, "colour": "red"}}]}"#;
+
+		let ValidatedMap { diagnostics, .. } = parse_and_validate("map.json", source, None);
+
+		assert_eq!(diagnostics.diagnostics.len(), 1);
+		assert_eq!(diagnostics.diagnostics[0].pointer, "/shapes/0/shape/colour");
+		assert_eq!(diagnostics.diagnostics[0].severity, Severity::Warning);
+	}
+
+	#[test]
+	fn reports_broken_references() {
+		let source = r#"{
+			"templates": [{ "name": "orc" }],
+			"entities": [
+				{ "entity_id": "a", "template": "orc" },
+				{ "entity_id": "a", "template": "troll" }
+			],
+			"camera": { "entity_id": "b" }
+		}"#;
+
+		let ValidatedMap { diagnostics, .. } = parse_and_validate("map.json", source, None);
+
+		let pointers: Vec<&str> = diagnostics.errors().map(|d| d.pointer.as_str()).collect();
+
+		assert_eq!(pointers, vec![
+			"/entities/1/entity_id",
+			"/entities/1/template",
+			"/camera/entity_id",
+		]);
+		assert!(diagnostics.has_errors());
+	}
+
+	#[test]
+	fn reports_invalid_materials() {
+		let source = r#"{
+			"materials": [
+				{ "name": "stone", "base_color": "808080", "roughness": 0.9, "uv_scale": [4, 4] },
+				{ "name": "glass", "emissive": "nope", "metallic": 1.5, "shine": 1 }
+			],
+			"templates": [{ "name": "orc", "materials": { "Skin": "stone", "Armor": "steel" } }],
+			"shapes": [
+				{ "id": "floor", "shape": { "type": "Plane", "size": 10, "material": "stone" } },
+				{ "id": "wall", "shape": { "type": "Cube", "size": 1, "material": "brick" } }
+			]
+		}"#;
+
+		let ValidatedMap { diagnostics, .. } = parse_and_validate("map.json", source, None);
+
+		let pointers: Vec<&str> = diagnostics.diagnostics.iter().map(|d| d.pointer.as_str()).collect();
+
+		assert_eq!(pointers, vec![
+			"/materials/1/emissive",
+			"/materials/1/metallic",
+			"/materials/1/shine",
+			"/templates/0/materials/Armor",
+			"/shapes/1/shape/material",
+		]);
+	}
+
+	#[test]
+	fn reports_invalid_terrains() {
+		let source = r#"{
+			"materials": [{ "name": "grass" }],
+			"shapes": [{ "shape": {
+				"type": "Terrain",
+				"heightmap": "missing.png",
+				"size": [100, 0],
+				"height_scale": 10,
+				"resolution": 1,
+				"layers": ["grass", "rock"]
+			} }]
+		}"#;
+
+		let ValidatedMap { diagnostics, .. } = parse_and_validate("map.json", source, Some(Path::new("assets")));
+
+		let pointers: Vec<&str> = diagnostics.diagnostics.iter().map(|d| d.pointer.as_str()).collect();
+
+		assert_eq!(pointers, vec![
+			"/shapes/0/shape/heightmap",
+			"/shapes/0/shape/size/1",
+			"/shapes/0/shape/resolution",
+			"/shapes/0/shape/layers",
+			"/shapes/0/shape/layers/1",
+		]);
+	}
+
+	#[test]
+	fn reports_invalid_prefabs() {
+		let source = r#"{
+			"prefabs": [{
+				"name": "torch",
+				"shapes": [{ "id": "post", "shape": { "type": "Cube", "size": 0, "material": "wood" } }],
+				"lights": [{ "id": "post", "type": { "type": "Point", "color": "ffaa00" } }]
+			}],
+			"instances": [
+				{ "prefab": "lamp" },
+				{ "prefab": "torch", "overrides": { "flame": { "type": { "color": "ff0000" } }, "post": { "colour": "red" } } }
+			]
+		}"#;
+
+		let ValidatedMap { diagnostics, .. } = parse_and_validate("map.json", source, None);
+
+		let pointers: Vec<&str> = diagnostics.diagnostics.iter().map(|d| d.pointer.as_str()).collect();
+
+		assert_eq!(pointers, vec![
+			"/prefabs/0/shapes/0/shape/material",
+			"/prefabs/0/lights/0/id",
+			"/instances/0/prefab",
+			"/instances/1/overrides/flame",
+			"/instances/1/overrides/post/colour",
+		]);
+	}
+
+	#[test]
+	fn reports_broken_template_inheritance() {
+		let source = r#"{
+			"templates": [
+				{ "name": "a", "extends": "b" },
+				{ "name": "b", "extends": "a" },
+				{ "name": "c", "extends": "nobody" }
+			],
+			"entities": [{ "template": "c", "overrides": { "walk_sped": 2 } }]
+		}"#;
+
+		let ValidatedMap { diagnostics, .. } = parse_and_validate("map.json", source, None);
+
+		let pointers: Vec<&str> = diagnostics.diagnostics.iter().map(|d| d.pointer.as_str()).collect();
+
+		assert_eq!(pointers, vec![
+			"/templates/0/extends",
+			"/templates/1/extends",
+			"/templates/2/extends",
+			"/entities/0/overrides/walk_sped",
+		]);
+	}
+
+	#[test]
+	fn reports_invalid_colors() {
+		let source = r#"{
+			"lights": [{ "type": { "type": "Point", "color": "fffffz" } }],
+			"ambient_light": { "color": "white", "brightness": 1.0 }
+		}"#;
+
+		let ValidatedMap { diagnostics, .. } = parse_and_validate("map.json", source, None);
+
+		let pointers: Vec<&str> = diagnostics.errors().map(|d| d.pointer.as_str()).collect();
+
+		assert_eq!(pointers, vec!["/lights/0/type/color", "/ambient_light/color"]);
+	}
+
+	#[test]
+	fn reports_invalid_values() {
+		let source = r#"{
+			"version": 4,
+			"templates": [{ "name": "orc", "collider": { "type": "Cuboid", "x": 1, "y": 0, "z": 1 } }],
+			"entities": [{ "template": "orc", "move_cycle": [] }],
+			"lights": [{ "type": { "type": "Point", "color": "ffffff", "range": -1 } }]
+		}"#;
+
+		let ValidatedMap { diagnostics, .. } = parse_and_validate("map.json", source, None);
+
+		let pointers: Vec<&str> = diagnostics.errors().map(|d| d.pointer.as_str()).collect();
+
+		assert_eq!(pointers, vec![
+			"/templates/0/collider/y",
+			"/entities/0/move_cycle",
+			"/lights/0/type/range",
+		]);
+	}
+
+	#[test]
+	fn reports_invalid_lights() {
+		let source = r#"{
+			"lights": [
+				{ "type": { "type": "Directional", "color": "fff8e0", "rotation": [-0.8, 0.5, 0], "shadows_enabled": true } },
+				{ "type": { "type": "Directional", "color": "zzz", "shadow_cascades": { "minimum_distance": 10, "maximum_distance": 5 } } },
+				{ "type": { "type": "Spot", "color": "ffffff", "direction": [0, 0, 0], "inner_angle": 1, "outer_angle": 0.5 } }
+			]
+		}"#;
+
+		let ValidatedMap { map, diagnostics, .. } = parse_and_validate("map.json", source, None);
+
+		let pointers: Vec<&str> = diagnostics.errors().map(|d| d.pointer.as_str()).collect();
+
+		assert_eq!(pointers, vec![
+			"/lights/1/type/color",
+			"/lights/1/type/shadow_cascades",
+			"/lights/2/type/direction",
+			"/lights/2/type/inner_angle",
+		]);
+		assert!(matches!(map.unwrap().lights.unwrap()[0].light_type, LightType::Directional(_)));
+	}
+
+	#[test]
+	fn reports_syntax_errors() {
+		let ValidatedMap { map, diagnostics, .. } = parse_and_validate("map.json", "{\n\t\"entities\": [,]\n}", None);
+
+		assert!(map.is_none());
+		assert!(diagnostics.has_errors());
+		assert_eq!(diagnostics.diagnostics[0].line, 2);
+	}
+
+	#[test]
+	fn reports_missing_assets() {
+		let source = r#"{"templates": [{ "name": "orc", "asset": "does_not_exist.glb#Scene0" }]}"#;
+
+		let ValidatedMap { diagnostics, .. } = parse_and_validate("map.json", source, Some(Path::new("assets")));
+
+		assert_eq!(diagnostics.diagnostics.len(), 1);
+		assert_eq!(diagnostics.diagnostics[0].pointer, "/templates/0/asset");
+		assert!(!diagnostics.has_errors());
+	}
+
+	#[test]
+	fn accepts_templates_without_assets() {
+		let source = r#"{
+			"templates": [
+				{ "name": "spawn_point" },
+				{ "name": "trigger", "collider": { "type": "Cuboid", "x": 2, "y": 1, "z": 2 }, "physics": "Static", "placeholder": false },
+				{ "name": "wall", "collider": { "type": "AABB" } }
+			],
+			"entities": [{ "template": "spawn_point" }, { "template": "trigger" }]
+		}"#;
+
+		let ValidatedMap { diagnostics, .. } = parse_and_validate("map.json", source, Some(Path::new("assets")));
+
+		assert!(!diagnostics.has_errors());
+		assert_eq!(diagnostics.diagnostics.len(), 1);
+		assert_eq!(diagnostics.diagnostics[0].pointer, "/templates/2/collider");
+	}
+
+	fn validate_files(files: &[(&str, &str)]) -> ValidatedMap {
+		let read = |path: &str| {
+			files
+				.iter()
+				.find(|(file, _)| *file == path)
+				.map(|(_, source)| source.to_string())
+				.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "not found"))
+		};
+
+		validate_map(files[0].0, &read, None)
+	}
+
+	#[test]
+	fn merges_included_files() {
+		let validated = validate_files(&[
+			("maps/level.json", r#"{
+	"includes": ["../shared/templates.json", "enemies.json"],
+	"entities": [{ "entity_id": "player", "template": "orc", "initial_position": [0, 0, 0] }],
+	"camera": { "entity_id": "player" }
+}"#),
+			("maps/enemies.json", r#"{
+	"includes": ["../shared/templates.json"],
+	"entities": [{ "entity_id": "enemy", "template": "orc", "initial_position": [1, 0, 0] }]
+}"#),
+			("shared/templates.json", r#"{"templates": [{ "name": "orc" }]}"#),
+		]);
+
+		assert_eq!(validated.diagnostics.diagnostics, vec![]);
+		assert_eq!(validated.files, vec!["maps/level.json", "shared/templates.json", "maps/enemies.json"]);
+
+		let map = validated.map.unwrap();
+		let entity_ids = map.entities.unwrap().into_iter().map(|e| e.entity_id).collect::<Vec<_>>();
+
+		assert_eq!(map.templates.unwrap().len(), 1);
+		assert_eq!(entity_ids, vec!["enemy", "player"]);
+	}
+
+	#[test]
+	fn reports_conflicts_in_their_own_files() {
+		let validated = validate_files(&[
+			("map.json", r#"{
+	"includes": ["a.json"],
+	"templates": [{ "name": "orc" }],
+	"entities": [{ "entity_id": "a", "template": "goblin", "initial_position": [0, 0, 0] }]
+}"#),
+			("a.json", r#"{
+	"templates": [{ "name": "orc" }],
+	"camera": { "entity_id": "a" }
+}"#),
+		]);
+
+		let errors = validated.diagnostics.errors()
+			.map(|d| (d.file.as_str(), d.line, d.pointer.as_str()))
+			.collect::<Vec<_>>();
+
+		assert_eq!(errors, vec![
+			("map.json", 3, "/templates/0/name"),
+			("map.json", 4, "/entities/0/template"),
+		]);
+		assert!(validated.diagnostics.diagnostics[0].message.contains("a.json:2:"));
+	}
+
+	#[test]
+	fn reports_include_cycles_and_missing_files() {
+		let validated = validate_files(&[
+			("map.json", r#"{"includes": ["a.json", "missing.json"]}"#),
+			("a.json", r#"{"includes": ["./map.json"]}"#),
+		]);
+
+		let errors = validated.diagnostics.errors()
+			.map(|d| (d.file.as_str(), d.pointer.as_str()))
+			.collect::<Vec<_>>();
+
+		assert!(validated.map.is_none());
+		assert_eq!(errors, vec![
+			("map.json", "/includes/1"),
+			("a.json", "/includes/0"),
+		]);
+	}
+
+	#[test]
+	fn reads_every_format_alike() {
+		let json = validate_files(&[
+			("map.json", r#"{
+	"includes": ["templates.json"],
+	"entities": [{ "entity_id": "player", "template": "orc", "initial_position": [0, 1.5, 0], "player": true }],
+	"camera": { "entity_id": "player" }
+}"#),
+			("templates.json", r#"{"templates": [{ "name": "orc", "physics": "Dynamic", "collider": { "type": "Cuboid", "x": 1, "y": 2, "z": 1 } }]}"#),
+		]);
+		let mixed = validate_files(&[
+			("map.ron", r#"(
+	includes: ["templates.yaml"],
+	entities: [(entity_id: "player", template: "orc", initial_position: (0, 1.5, 0), player: Some(true))],
+	camera: MapCamera(entity_id: "player"),
+)"#),
+			("templates.yaml", "templates:\n- name: orc\n  physics: Dynamic\n  collider:\n    type: Cuboid\n    x: 1\n    y: 2\n    z: 1\n"),
+		]);
+
+		assert_eq!(mixed.diagnostics.diagnostics, vec![]);
+		assert_eq!(mixed.map, json.map);
+	}
+
+	#[test]
+	fn reports_positions_in_every_format() {
+		let validated = validate_files(&[
+			("map.ron", "(\n\tincludes: [\"templates.yml\"],\n\tentities: [(entity_id: \"a\", template: \"goblin\")],\n)"),
+			("templates.yml", "templates:\n  - name: orc\n    speed: 1\n"),
+		]);
+
+		let diagnostics = validated.diagnostics.diagnostics.iter()
+			.map(|d| (d.file.as_str(), d.line, d.column, d.pointer.as_str()))
+			.collect::<Vec<_>>();
+
+		assert_eq!(diagnostics, vec![
+			("map.ron", 3, 38, "/entities/0/template"),
+			("templates.yml", 3, 5, "/templates/0/speed"),
+		]);
+	}
+}
@@ -502,7 +502,7 @@ fn menu_action(
 			match menu_button_action {
 				MenuButtonAction::Quit => app_exit_events.send(AppExit),
 				MenuButtonAction::Play => {
-					game_state.set(GameState::Game);
+					game_state.set(GameState::Loading);
 					menu_state.set(MenuState::Disabled);
 				}
 				MenuButtonAction::Settings => menu_state.set(MenuState::Settings),
@@ -0,0 +1,659 @@
+use std::f32::consts::FRAC_PI_4;
+use std::fs;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::mesh::VertexAttributeValues;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::utils::HashMap;
+use bevy::utils::HashSet;
+use bevy_rapier3d::prelude::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::console_plugin::ConsoleCommand;
+use crate::map::Map;
+use crate::map::MapNavigation;
+use crate::path_finding::PolygonMesh;
+use crate::types::AddCollidingMesh;
+use crate::types::BakedNavigationMesh;
+use crate::types::GameState;
+use crate::types::LoadingAssets;
+use crate::types::MapShapeSource;
+use crate::types::Navigation;
+use crate::types::NavigationMesh;
+use crate::types::NavigationMeshComponent;
+use crate::types::NavigationOverlay;
+use crate::types::NavigationSource;
+use crate::types::NeedsAsset;
+use crate::types::NeedsTerrain;
+use crate::types::Point;
+
+/// Changes whenever baking changes, so that stale cache files aren't used
+const BAKE_VERSION: u64 = 1;
+
+/// Outside `config/`, which is bundled into builds
+const CACHE_DIR: &str = "./.cache/navmesh";
+
+/// Surfaces closer than this are treated as touching
+const EPSILON: f32 = 0.01;
+
+// This plugin bakes a navigation mesh from the static geometry of maps that
+// have `navigation` settings
+pub struct NavmeshPlugin;
+
+impl Plugin for NavmeshPlugin {
+	fn build(&self, app: &mut App) {
+		app
+			.insert_resource(Navigation::default())
+			.add_systems((
+				bake_navigation,
+				show_navigation,
+			).chain().in_set(OnUpdate(GameState::Game)));
+	}
+}
+
+/// A walkable polygon of the baked navigation mesh, counter-clockwise seen
+/// from above
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NavPolygon {
+	pub vertices: Vec<[f32; 3]>,
+}
+
+impl NavPolygon {
+	/// The polygon as a component of the navigation grid used for path
+	/// finding, covering the grid points inside it
+	pub fn grid_component(&self) -> NavigationMeshComponent {
+		let (min, max) = self.vertices.iter().fold(
+			(Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+			|(min, max), vertex| (min.min(Vec3::from(*vertex)), max.max(Vec3::from(*vertex))),
+		);
+
+		let y = ((min.y + max.y) / 2.0).round() as i32;
+		let (left, right) = ((min.x + 0.5).round() as i32, (max.x - 0.5).round() as i32);
+		let (up, down) = ((min.z + 0.5).round() as i32, (max.z - 0.5).round() as i32);
+
+		NavigationMeshComponent {
+			left_up: Point { x: left, z: up, y },
+			right_down: Point { x: right, z: down, y },
+			right_up: Point { x: right, z: up, y },
+			left_down: Point { x: left, z: down, y },
+		}
+	}
+}
+
+/// Navigation settings with the defaults filled in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BakeSettings {
+	pub agent_radius: f32,
+	pub agent_height: f32,
+	pub max_slope: f32,
+	pub step_height: f32,
+}
+
+impl From<&MapNavigation> for BakeSettings {
+	fn from(navigation: &MapNavigation) -> Self {
+		BakeSettings {
+			agent_radius: navigation.agent_radius.unwrap_or(0.4),
+			agent_height: navigation.agent_height.unwrap_or(1.8),
+			max_slope: navigation.max_slope.unwrap_or(FRAC_PI_4),
+			step_height: navigation.step_height.unwrap_or(0.5),
+		}
+	}
+}
+
+/// Triangles of a triangle list mesh, empty for other meshes
+pub fn mesh_triangles(mesh: &Mesh) -> Vec<[Vec3; 3]> {
+	if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+		return Vec::new();
+	}
+
+	let positions: Vec<Vec3> = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+		Some(VertexAttributeValues::Float32x3(positions)) => positions.iter().map(|position| Vec3::from(*position)).collect(),
+		_ => return Vec::new(),
+	};
+
+	let indices: Vec<usize> = match mesh.indices() {
+		Some(Indices::U16(indices)) => indices.iter().map(|index| *index as usize).collect(),
+		Some(Indices::U32(indices)) => indices.iter().map(|index| *index as usize).collect(),
+		None => (0..positions.len()).collect(),
+	};
+
+	indices
+		.chunks_exact(3)
+		.filter_map(|triangle| Some([
+			*positions.get(triangle[0])?,
+			*positions.get(triangle[1])?,
+			*positions.get(triangle[2])?,
+		]))
+		.collect()
+}
+
+/// FNV-1a hash of the geometry and settings, independent of the order of
+/// the triangles, naming the cache file
+pub fn geometry_hash(triangles: &[[Vec3; 3]], settings: &BakeSettings) -> u64 {
+	// Millimetres, so that float noise from transforms doesn't change the hash
+	let mut quantized: Vec<[i32; 9]> = triangles.iter().map(|triangle| {
+		let mut values = [0; 9];
+
+		for (value, coordinate) in values.iter_mut().zip(triangle.iter().flat_map(|vertex| vertex.to_array())) {
+			*value = (coordinate * 1000.0).round() as i32;
+		}
+
+		values
+	}).collect();
+
+	quantized.sort_unstable();
+
+	let mut hash: u64 = 0xcbf29ce484222325;
+	let mut write = |bytes: &[u8]| {
+		for byte in bytes {
+			hash ^= *byte as u64;
+			hash = hash.wrapping_mul(0x100000001b3);
+		}
+	};
+
+	write(&BAKE_VERSION.to_le_bytes());
+
+	for value in [settings.agent_radius, settings.agent_height, settings.max_slope, settings.step_height] {
+		write(&value.to_bits().to_le_bytes());
+	}
+
+	for triangle in quantized.iter() {
+		for value in triangle {
+			write(&value.to_le_bytes());
+		}
+	}
+
+	hash
+}
+
+/// A surface crossing the vertical line through a grid point
+struct Crossing {
+	height: f32,
+	facing_up: bool,
+	walkable: bool,
+}
+
+/// Heights of the surfaces crossing the vertical line through every grid
+/// point, sorted from the bottom
+fn column_crossings(triangles: &[[Vec3; 3]], settings: &BakeSettings) -> HashMap<(i32, i32), Vec<Crossing>> {
+	let mut columns: HashMap<(i32, i32), Vec<Crossing>> = HashMap::new();
+	let min_normal_y = settings.max_slope.cos();
+
+	for [a, b, c] in triangles.iter().copied() {
+		let normal = (b - a).cross(c - a).normalize_or_zero();
+
+		// Walls don't cross vertical lines
+		if normal.y.abs() < 1e-4 {
+			continue;
+		}
+
+		let (min, max) = (a.min(b).min(c), a.max(b).max(c));
+		let area = (b.x - a.x) * (c.z - a.z) - (c.x - a.x) * (b.z - a.z);
+
+		for x in min.x.ceil() as i32..=max.x.floor() as i32 {
+			for z in min.z.ceil() as i32..=max.z.floor() as i32 {
+				let (px, pz) = (x as f32, z as f32);
+
+				// Barycentric coordinates in the xz plane
+				let u = ((b.x - px) * (c.z - pz) - (c.x - px) * (b.z - pz)) / area;
+				let v = ((c.x - px) * (a.z - pz) - (a.x - px) * (c.z - pz)) / area;
+				let w = 1.0 - u - v;
+
+				if u < -1e-5 || v < -1e-5 || w < -1e-5 {
+					continue;
+				}
+
+				columns.entry((x, z)).or_default().push(Crossing {
+					height: u * a.y + v * b.y + w * c.y,
+					facing_up: normal.y > 0.0,
+					walkable: normal.y >= min_normal_y,
+				});
+			}
+		}
+	}
+
+	for crossings in columns.values_mut() {
+		crossings.sort_by(|a, b| a.height.total_cmp(&b.height));
+	}
+
+	columns
+}
+
+/// Heights agents can stand at on every grid point. A surface is standable
+/// when the next surface above faces down, so it isn't inside a solid, and
+/// is at least the agent's height away.
+fn standable_heights(triangles: &[[Vec3; 3]], settings: &BakeSettings) -> HashMap<(i32, i32), Vec<f32>> {
+	column_crossings(triangles, settings)
+		.into_iter()
+		.map(|(column, crossings)| {
+			let mut heights: Vec<f32> = Vec::new();
+
+			for crossing in crossings.iter().filter(|crossing| crossing.walkable) {
+				let above = crossings.iter().find(|other| other.height > crossing.height + EPSILON);
+
+				let clear = match above {
+					Some(above) => !above.facing_up && above.height - crossing.height >= settings.agent_height,
+					None => true,
+				};
+
+				if clear && heights.last().iter().all(|last| crossing.height - *last > EPSILON) {
+					heights.push(crossing.height);
+				}
+			}
+
+			(column, heights)
+		})
+		.filter(|(_, heights)| !heights.is_empty())
+		.collect()
+}
+
+/// Bakes walkable polygons from the triangles of the static geometry in
+/// world space. Grid points one metre apart are sampled, points within the
+/// agent's radius of an edge or wall are removed and the rest is merged into
+/// rectangles of the same rounded height.
+pub fn bake(triangles: &[[Vec3; 3]], settings: &BakeSettings) -> Vec<NavPolygon> {
+	let heights = standable_heights(triangles, settings);
+
+	let radius = settings.agent_radius.ceil() as i32;
+	let neighbours: Vec<(i32, i32)> = (-radius..=radius)
+		.flat_map(|dx| (-radius..=radius).map(move |dz| (dx, dz)))
+		.filter(|(dx, dz)| (*dx, *dz) != (0, 0) && ((dx * dx + dz * dz) as f32).sqrt() <= settings.agent_radius.ceil())
+		.collect();
+
+	let connected = |from: f32, (x, z): (i32, i32), distance: f32| {
+		let max_difference = settings.step_height + distance * settings.max_slope.tan();
+
+		heights.get(&(x, z)).into_iter().flatten().any(|height| (height - from).abs() <= max_difference)
+	};
+
+	// Cells by rounded height, row and column, so rows come out in order
+	let mut cells: Vec<(i32, i32, i32, f32)> = Vec::new();
+
+	for ((x, z), column) in heights.iter() {
+		for height in column {
+			let kept = neighbours.iter().all(|(dx, dz)| {
+				connected(*height, (x + dx, z + dz), ((dx * dx + dz * dz) as f32).sqrt())
+			});
+
+			if kept {
+				cells.push((height.round() as i32, *z, *x, *height));
+			}
+		}
+	}
+
+	cells.sort_by_key(|(level, z, x, _)| (*level, *z, *x));
+
+	let cell_heights: HashMap<(i32, i32, i32), f32> = cells.iter().map(|(level, z, x, height)| ((*level, *x, *z), *height)).collect();
+
+	// Rows are split into strips, then strips with the same columns in
+	// consecutive rows are merged into rectangles
+	let mut open: HashMap<(i32, i32, i32), (i32, i32)> = HashMap::new();
+	let mut rectangles: Vec<(i32, i32, i32, i32, i32)> = Vec::new();
+	let mut index = 0;
+
+	while index < cells.len() {
+		let (level, z, x0, _) = cells[index];
+		let mut x1 = x0;
+
+		while index + 1 < cells.len() && cells[index + 1].0 == level && cells[index + 1].1 == z && cells[index + 1].2 == x1 + 1 {
+			x1 += 1;
+			index += 1;
+		}
+
+		index += 1;
+
+		match open.get_mut(&(level, x0, x1)) {
+			Some((_, last_z)) if *last_z == z - 1 => *last_z = z,
+			Some(rows) => {
+				rectangles.push((level, x0, x1, rows.0, rows.1));
+				*rows = (z, z);
+			},
+			None => {
+				open.insert((level, x0, x1), (z, z));
+			},
+		}
+	}
+
+	rectangles.extend(open.into_iter().map(|((level, x0, x1), (z0, z1))| (level, x0, x1, z0, z1)));
+	rectangles.sort();
+
+	rectangles
+		.into_iter()
+		.map(|(level, x0, x1, z0, z1)| {
+			let corner = |x: i32, z: i32, dx: f32, dz: f32| [x as f32 + dx, cell_heights[&(level, x, z)], z as f32 + dz];
+
+			NavPolygon {
+				vertices: vec![
+					corner(x0, z0, -0.5, -0.5),
+					corner(x0, z1, -0.5, 0.5),
+					corner(x1, z1, 0.5, 0.5),
+					corner(x1, z0, 0.5, -0.5),
+				],
+			}
+		})
+		.collect()
+}
+
+#[derive(Serialize, Deserialize)]
+struct NavmeshCacheFile {
+	polygons: Vec<NavPolygon>,
+}
+
+fn cache_path(hash: u64) -> PathBuf {
+	PathBuf::from(CACHE_DIR).join(format!("{:016x}.json", hash))
+}
+
+fn read_cache(hash: u64) -> Option<Vec<NavPolygon>> {
+	let contents = fs::read_to_string(cache_path(hash)).ok()?;
+
+	match serde_json::from_str::<NavmeshCacheFile>(&contents) {
+		Ok(cache) => Some(cache.polygons),
+		Err(error) => {
+			log::warn!("ignoring broken navmesh cache {:016x}: {}", hash, error);
+
+			None
+		},
+	}
+}
+
+fn write_cache(hash: u64, polygons: &[NavPolygon]) {
+	let cache = NavmeshCacheFile {
+		polygons: polygons.to_vec(),
+	};
+
+	let result = fs::create_dir_all(CACHE_DIR)
+		.map_err(|error| error.to_string())
+		.and_then(|_| serde_json::to_string(&cache).map_err(|error| error.to_string()))
+		.and_then(|contents| fs::write(cache_path(hash), contents).map_err(|error| error.to_string()));
+
+	if let Err(error) = result {
+		log::warn!("failed to write navmesh cache {:016x}: {}", hash, error);
+	}
+}
+
+fn is_static(rigid_body: Option<&RigidBody>) -> bool {
+	matches!(rigid_body, None | Some(RigidBody::Fixed))
+}
+
+/// Whether the entity or one of its ancestors is a moving rigid body, so
+/// its colliders aren't part of the static geometry
+fn is_moving(entity: Entity, ancestors: &Query<(Option<&Parent>, Option<&RigidBody>)>) -> bool {
+	let mut current = Some(entity);
+
+	while let Some((parent, rigid_body)) = current.and_then(|entity| ancestors.get(entity).ok()) {
+		if !is_static(rigid_body) {
+			return true;
+		}
+
+		current = parent.map(|parent| parent.get());
+	}
+
+	false
+}
+
+/// Bakes the navigation mesh again when static geometry changes, once
+/// terrains and collision meshes are built. The result is cached on disk
+/// by the hash of the geometry, so unchanged maps load their mesh.
+fn bake_navigation(
+	mut commands: Commands,
+	map: Res<Map>,
+	mut navigation: ResMut<Navigation>,
+	loading_assets: Res<LoadingAssets>,
+	meshes: Res<Assets<Mesh>>,
+	pending: Query<(), Or<(With<NeedsTerrain>, With<AddCollidingMesh>)>>,
+	needs_assets: Query<&NeedsAsset>,
+	shapes: Query<(Entity, Ref<GlobalTransform>, Ref<Collider>, &Handle<Mesh>, Option<&RigidBody>), With<MapShapeSource>>,
+	sources: Query<(Entity, Ref<GlobalTransform>, Ref<NavigationSource>)>,
+	ancestors: Query<(Option<&Parent>, Option<&RigidBody>)>,
+	mut removed: RemovedComponents<Collider>,
+	baked: Query<Entity, With<BakedNavigationMesh>>,
+	mut dirty: Local<bool>,
+	mut contributors: Local<HashSet<Entity>>,
+) {
+	if map.is_changed() && navigation.settings != map.navigation {
+		navigation.settings = map.navigation.clone();
+		*dirty = true;
+	}
+
+	// Moving geometry isn't baked, so it only matters when it was part of
+	// the last bake
+	let changed = |entity: Entity, moving: bool, changed: bool| match moving {
+		true => contributors.contains(&entity),
+		false => changed,
+	};
+
+	*dirty |= removed.iter().filter(|entity| contributors.contains(entity)).count() > 0
+		|| shapes.iter().any(|(entity, transform, collider, _, rigid_body)| {
+			changed(entity, !is_static(rigid_body), transform.is_changed() || collider.is_changed())
+		})
+		|| sources.iter().any(|(entity, transform, source)| {
+			changed(entity, is_moving(entity, &ancestors), transform.is_changed() || source.is_changed())
+		});
+
+	if !*dirty {
+		return;
+	}
+
+	let settings = match &navigation.settings {
+		Some(settings) => BakeSettings::from(settings),
+		None => {
+			for entity in baked.iter() {
+				commands.entity(entity).despawn_recursive();
+			}
+
+			navigation.hash = None;
+			navigation.mesh = PolygonMesh::default();
+			contributors.clear();
+			*dirty = false;
+
+			return;
+		},
+	};
+
+	// Collision meshes of assets that failed to load never arrive
+	let waiting = !pending.is_empty() || needs_assets.iter().any(|needs_asset| {
+		needs_asset.collision_mesh.is_some()
+			&& !loading_assets.failed.iter().any(|(asset, _)| asset.path == needs_asset.asset)
+	});
+
+	if waiting {
+		return;
+	}
+
+	*dirty = false;
+
+	let mut triangles: Vec<[Vec3; 3]> = Vec::new();
+
+	contributors.clear();
+
+	for (entity, transform, _, mesh, rigid_body) in shapes.iter() {
+		if !is_static(rigid_body) {
+			continue;
+		}
+
+		if let Some(mesh) = meshes.get(mesh) {
+			triangles.extend(mesh_triangles(mesh).into_iter().map(|triangle| triangle.map(|vertex| transform.transform_point(vertex))));
+			contributors.insert(entity);
+		}
+	}
+
+	for (entity, transform, source) in sources.iter() {
+		if !is_moving(entity, &ancestors) {
+			triangles.extend(source.triangles.iter().map(|triangle| triangle.map(|vertex| transform.transform_point(vertex))));
+			contributors.insert(entity);
+		}
+	}
+
+	let hash = geometry_hash(&triangles, &settings);
+
+	if navigation.hash == Some(hash) {
+		return;
+	}
+
+	let polygons = match read_cache(hash) {
+		Some(polygons) => {
+			log::info!("loaded navmesh {:016x} with {} polygons", hash, polygons.len());
+
+			polygons
+		},
+		None => {
+			let polygons = bake(&triangles, &settings);
+
+			log::info!("baked navmesh {:016x} with {} polygons from {} triangles", hash, polygons.len(), triangles.len());
+
+			write_cache(hash, &polygons);
+
+			polygons
+		},
+	};
+
+	for entity in baked.iter() {
+		commands.entity(entity).despawn_recursive();
+	}
+
+	commands.spawn((
+		BakedNavigationMesh,
+		NavigationMesh {
+			components: polygons.iter().map(NavPolygon::grid_component).collect(),
+		},
+		Name::new("navmesh"),
+	));
+
+	navigation.hash = Some(hash);
+	navigation.mesh = PolygonMesh::new(polygons);
+}
+
+fn overlay_mesh(polygons: &[NavPolygon]) -> Mesh {
+	let mut positions: Vec<[f32; 3]> = Vec::new();
+	let mut indices: Vec<u32> = Vec::new();
+
+	for polygon in polygons {
+		let first = positions.len() as u32;
+
+		// Lifted a little, so it isn't hidden by the ground
+		positions.extend(polygon.vertices.iter().map(|[x, y, z]| [*x, y + 0.05, *z]));
+
+		for i in 1..polygon.vertices.len().saturating_sub(1) as u32 {
+			indices.extend([first, first + i, first + i + 1]);
+		}
+	}
+
+	let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+	mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 1.0, 0.0]; positions.len()]);
+	mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; positions.len()]);
+	mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+	mesh.set_indices(Some(Indices::U32(indices)));
+
+	mesh
+}
+
+/// Toggles a translucent overlay of the baked navigation mesh with the
+/// `navmesh` console command
+fn show_navigation(
+	mut commands: Commands,
+	mut console_commands: EventReader<ConsoleCommand>,
+	mut visible: Local<bool>,
+	navigation: Res<Navigation>,
+	mut meshes: ResMut<Assets<Mesh>>,
+	mut materials: ResMut<Assets<StandardMaterial>>,
+	overlays: Query<Entity, With<NavigationOverlay>>,
+) {
+	let toggled = console_commands.iter().filter(|command| command.name == "navmesh").count() % 2 == 1;
+
+	if toggled {
+		*visible = !*visible;
+		log::info!("navmesh overlay {}", if *visible { "on" } else { "off" });
+	}
+
+	if !toggled && !navigation.is_changed() {
+		return;
+	}
+
+	for overlay in overlays.iter() {
+		commands.entity(overlay).despawn_recursive();
+	}
+
+	if !*visible {
+		return;
+	}
+
+	commands.spawn((
+		NavigationOverlay,
+		PbrBundle {
+			mesh: meshes.add(overlay_mesh(navigation.mesh.polygons())),
+			material: materials.add(StandardMaterial {
+				base_color: Color::rgba(0.2, 0.6, 1.0, 0.35),
+				alpha_mode: AlphaMode::Blend,
+				unlit: true,
+				double_sided: true,
+				cull_mode: None,
+				..default()
+			}),
+			..default()
+		},
+	));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn plane(size: f32) -> Vec<[Vec3; 3]> {
+		let mesh = Mesh::from(shape::Plane { size, ..default() });
+
+		mesh_triangles(&mesh)
+	}
+
+	fn cube(center: Vec3, size: f32) -> Vec<[Vec3; 3]> {
+		let mesh = Mesh::from(shape::Cube { size });
+
+		mesh_triangles(&mesh).into_iter().map(|triangle| triangle.map(|vertex| vertex + center)).collect()
+	}
+
+	fn walkable(polygons: &[NavPolygon], x: f32, y: f32, z: f32) -> bool {
+		polygons.iter().any(|polygon| {
+			let [min_x, min_y, min_z] = polygon.vertices[0];
+			let [max_x, _, max_z] = polygon.vertices[2];
+
+			x > min_x && x < max_x && z > min_z && z < max_z && (min_y - y).abs() < EPSILON
+		})
+	}
+
+	#[test]
+	fn bakes_around_a_box_on_a_plane() {
+		let settings = BakeSettings::from(&MapNavigation::default());
+		let triangles: Vec<[Vec3; 3]> = plane(20.0).into_iter().chain(cube(Vec3::new(0.0, 1.0, 0.0), 2.0)).collect();
+
+		let polygons = bake(&triangles, &settings);
+
+		assert!(walkable(&polygons, 6.0, 0.0, 6.0));
+		// Under the box and a radius around it
+		assert!(!walkable(&polygons, 0.0, 0.0, 0.0));
+		assert!(!walkable(&polygons, 2.0, 0.0, 0.0));
+		// The plane's edge
+		assert!(!walkable(&polygons, 10.0, 0.0, 0.0));
+		// The middle of the top of the box
+		assert!(walkable(&polygons, 0.0, 2.0, 0.0));
+
+		for polygon in polygons.iter() {
+			let component = polygon.grid_component();
+
+			assert!(component.left_up.x <= component.right_down.x && component.left_up.z <= component.right_down.z);
+		}
+	}
+
+	#[test]
+	fn hash_ignores_triangle_order() {
+		let settings = BakeSettings::from(&MapNavigation::default());
+		let mut triangles: Vec<[Vec3; 3]> = plane(4.0).into_iter().chain(cube(Vec3::ZERO, 1.0)).collect();
+		let hash = geometry_hash(&triangles, &settings);
+
+		triangles.reverse();
+
+		assert_eq!(geometry_hash(&triangles, &settings), hash);
+		assert_ne!(geometry_hash(&triangles, &BakeSettings { agent_radius: 1.0, ..settings }), hash);
+	}
+}
@@ -1,5 +1,10 @@
+use std::f32::consts::PI;
+
 use bevy::prelude::App;
 use bevy::prelude::Plugin;
+use bevy_rapier3d::prelude::*;
+use crate::path_finding::find_path;
+use crate::path_finding::PathError;
 use crate::types::*;
 
 
@@ -10,38 +15,249 @@ use bevy::prelude::*;
 pub struct NpcPlugin;
 
 impl Plugin for NpcPlugin {
-    fn build(&self, app: &mut App) {
-        app
-            .add_system(move_targets);
-    }
+	fn build(&self, app: &mut App) {
+		app
+			.add_systems((
+				plan_paths,
+				follow_paths,
+			).chain().in_set(OnUpdate(GameState::Game)));
+	}
+}
+
+/// Speeds of templates that don't set `walk_speed` or `run_speed`
+const DEFAULT_WALK_SPEED: f32 = 2.0;
+const DEFAULT_RUN_SPEED: f32 = 5.0;
+
+/// How close to a waypoint counts as reaching it
+const ARRIVAL_DISTANCE: f32 = 0.2;
+
+/// Time without getting closer to the next waypoint before planning again
+const STUCK_TIME: f32 = 1.0;
+
+/// Plans after getting stuck before the target is given up
+const MAX_REPLANS: u32 = 3;
+
+/// Waypoints from `from` to `to`, over the baked navigation mesh when the
+/// map has one and over the navigation grid of terrains otherwise. Without
+/// either, entities walk straight.
+fn plan_path(
+	navigation: &Navigation,
+	components: &[NavigationMeshComponent],
+	from: Vec3,
+	to: Vec3,
+) -> Result<Vec<Vec3>, PathError> {
+	if navigation.hash.is_some() {
+		return navigation.mesh.find_path(from, to);
+	}
+
+	if components.is_empty() {
+		return Ok(vec![from, to]);
+	}
+
+	let point = |p: Vec3| Point { x: p.x.round() as i32, z: p.z.round() as i32, y: p.y.round() as i32 };
+
+	let path = find_path(components, &point(from), &point(to))?;
+
+	Ok(path.iter().map(|p| Vec3::new(p.x as f32, p.y as f32, p.z as f32)).collect())
+}
+
+/// Plans paths to new targets, after getting stuck and after the navigation
+/// mesh was baked again
+fn plan_paths(
+	mut commands: Commands,
+	navigation: Res<Navigation>,
+	navigation_meshes: Query<&NavigationMesh, Without<BakedNavigationMesh>>,
+	mut query: Query<(
+		Entity,
+		&mut GameEntity,
+		&Transform,
+		Ref<TargetPosition>,
+		Option<&NavigationPath>,
+		Option<&mut KinematicCharacterController>,
+		Option<&mut Velocity>,
+	)>,
+) {
+	// Paths wait for the mesh of maps that bake one
+	if navigation.settings.is_some() && navigation.hash.is_none() {
+		return;
+	}
+
+	let mut components: Option<Vec<NavigationMeshComponent>> = None;
+
+	for (entity, mut game_entity, transform, target, path, controller, velocity) in query.iter_mut() {
+		let replans = match path {
+			None => 0,
+			Some(path) if path.replan => path.replans + 1,
+			Some(_) if target.is_changed() || navigation.is_changed() => 0,
+			Some(_) => continue,
+		};
+
+		let components = components.get_or_insert_with(|| {
+			navigation_meshes.iter().flat_map(|mesh| mesh.components.iter().cloned()).collect()
+		});
+
+		let to = Vec3::new(target.x, target.y, target.z);
+
+		match plan_path(&navigation, components, transform.translation, to) {
+			Ok(waypoints) if replans <= MAX_REPLANS => {
+				commands.entity(entity).insert(NavigationPath {
+					// The first waypoint is where the entity already is
+					next: 1.min(waypoints.len() - 1),
+					waypoints,
+					closest: f32::INFINITY,
+					replans,
+					..default()
+				});
+			},
+			Ok(_) => {
+				log::warn!("[{}] stuck on the way to {}, giving up", game_entity.entity_id, to);
+
+				commands.entity(entity).remove::<(TargetPosition, NavigationPath)>();
+				stop(&mut game_entity, controller, velocity);
+			},
+			Err(error) => {
+				log::warn!("[{}] can't move to {}: {}", game_entity.entity_id, to, error);
+
+				commands.entity(entity).remove::<(TargetPosition, NavigationPath)>();
+				stop(&mut game_entity, controller, velocity);
+			},
+		}
+	}
 }
 
-fn move_targets(mut commands: Commands, mut npc: Query<(Entity, &TargetPosition, &mut Transform)>, time: Res<Time>) {
-    for (entity, target, mut transform) in &mut npc {    
-        let y = 0.0;
-        let x = target.x - transform.translation.x;
-        let z = target.z - transform.translation.z;
-         
-        let speed = 10.0;
-        let distance = Vec3::new(x, y, z);
-        let distance_abs = distance.abs();
-        let deltadistace = speed * time.delta_seconds();
+/// Stops an entity that was walking along a path, so that it doesn't keep
+/// walking straight ahead without one
+fn stop(
+	game_entity: &mut GameEntity,
+	controller: Option<Mut<KinematicCharacterController>>,
+	velocity: Option<Mut<Velocity>>,
+) {
+	game_entity.move_intent.move_forward = false;
 
+	if let Some(mut controller) = controller {
+		controller.translation = Some(Vec3::ZERO);
+	}
 
-        transform.translation += distance.normalize() * deltadistace;
+	if let Some(mut velocity) = velocity {
+		velocity.linvel.x = 0.0;
+		velocity.linvel.z = 0.0;
+	}
+}
 
-        if distance_abs.x < 0.1 && distance_abs.y < 0.1 {
-            
-            let mut entity_commands = commands.entity(entity);
-            entity_commands.remove::<TargetPosition>();
-		}      
-    }
+/// Planar direction and distance from `position` to `waypoint`
+fn planar_offset(position: Vec3, waypoint: Vec3) -> (Vec3, f32) {
+	let offset = Vec3::new(waypoint.x - position.x, 0.0, waypoint.z - position.z);
+
+	(offset.normalize_or_zero(), offset.length())
+}
+
+/// Moves entities along their paths through their physics bodies: the
+/// character controller of kinematic bodies or the velocity of dynamic
+/// ones. Only entities without a body are moved by their transform.
+fn follow_paths(
+	mut commands: Commands,
+	time: Res<Time>,
+	mut query: Query<(
+		Entity,
+		&mut GameEntity,
+		&mut Transform,
+		&mut NavigationPath,
+		Option<&RigidBody>,
+		Option<&mut KinematicCharacterController>,
+		Option<&mut Velocity>,
+	)>,
+) {
+	let delta = time.delta_seconds();
+
+	for (entity, mut game_entity, mut transform, mut path, rigid_body, controller, velocity) in query.iter_mut() {
+		if path.replan {
+			continue;
+		}
+
+		let speed = match game_entity.running {
+			true => game_entity.run_speed.unwrap_or(DEFAULT_RUN_SPEED),
+			false => game_entity.walk_speed.unwrap_or(DEFAULT_WALK_SPEED),
+		};
+
+		let mut arrived = false;
+		let (mut direction, mut distance) = (Vec3::ZERO, 0.0);
+
+		while !arrived {
+			(direction, distance) = planar_offset(transform.translation, path.waypoints[path.next]);
+
+			if distance > ARRIVAL_DISTANCE.max(speed * delta) {
+				break;
+			}
+
+			if path.next + 1 < path.waypoints.len() {
+				path.next += 1;
+				path.closest = f32::INFINITY;
+			} else {
+				arrived = true;
+			}
+		}
+
+		let step = match arrived {
+			true => Vec3::ZERO,
+			false => direction * speed,
+		};
+
+		let moving = !arrived;
+
+		if game_entity.move_intent.move_forward != moving {
+			game_entity.move_intent.move_forward = moving;
+		}
+
+		if moving {
+			game_entity.yaw = direction.x.atan2(-direction.z);
+			transform.rotation = Quat::from_rotation_y(2.0 * PI - game_entity.yaw);
+		}
+
+		match (rigid_body, controller, velocity) {
+			(_, Some(mut controller), _) => {
+				controller.translation = Some(step * delta);
+			},
+			(Some(RigidBody::Dynamic), _, Some(mut velocity)) => {
+				velocity.linvel.x = step.x;
+				velocity.linvel.z = step.z;
+			},
+			(Some(RigidBody::Dynamic), _, None) => {
+				commands.entity(entity).insert(Velocity {
+					linvel: step,
+					..default()
+				});
+			},
+			_ => {
+				transform.translation += step * delta;
+			},
+		}
+
+		if arrived {
+			commands.entity(entity).remove::<(TargetPosition, NavigationPath)>();
+
+			continue;
+		}
+
+		// Blocked by something that isn't part of the navigation mesh
+		if distance < path.closest - ARRIVAL_DISTANCE / 4.0 {
+			path.closest = distance;
+			path.stuck_for = 0.0;
+		} else {
+			path.stuck_for += delta;
+		}
+
+		if path.stuck_for > STUCK_TIME {
+			log::info!("[{}] stuck, planning again", game_entity.entity_id);
+
+			path.replan = true;
+		}
+	}
 }
 
  
 pub fn handle_cycle(
 	mut commands: Commands,
-	mut npcs: Query<(Entity, &mut MoveCycle, &GameEntity), Without<TargetPosition>>,
+	mut npcs: Query<(Entity, &mut MoveCycle, &GameEntity), (Without<TargetPosition>, Without<Behavior>)>,
 ) {
 	for (entity, mut cycle, game_entity) in &mut npcs {
 		let mut entity_commands = commands.entity(entity);
@@ -52,9 +268,89 @@ pub fn handle_cycle(
 
 		let target_position = TargetPosition {
 			x: point.x as f32,
+			y: point.y as f32,
 			z: point.z as f32,
 		};
 
 		entity_commands.insert(target_position);
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::navmesh::NavPolygon;
+	use crate::path_finding::PolygonMesh;
+
+	#[test]
+	fn plans_over_the_baked_mesh_then_the_grid() {
+		let grid = vec![
+			NavigationMeshComponent {
+				left_up: Point { x: 0, z: 0, y: 0 },
+				right_down: Point { x: 3, z: 0, y: 0 },
+				right_up: Point { x: 3, z: 0, y: 0 },
+				left_down: Point { x: 0, z: 0, y: 0 },
+			}
+		];
+
+		let mut navigation = Navigation::default();
+
+		assert_eq!(plan_path(&navigation, &[], Vec3::ZERO, Vec3::new(5.0, 0.0, 5.0)), Ok(vec![Vec3::ZERO, Vec3::new(5.0, 0.0, 5.0)]));
+		assert_eq!(plan_path(&navigation, &grid, Vec3::ZERO, Vec3::new(2.0, 0.0, 0.0)).unwrap().len(), 3);
+
+		navigation.hash = Some(0);
+		navigation.mesh = PolygonMesh::new(vec![NavPolygon {
+			vertices: vec![[-1.0, 0.0, -1.0], [-1.0, 0.0, 1.0], [3.0, 0.0, 1.0], [3.0, 0.0, -1.0]],
+		}]);
+
+		assert_eq!(plan_path(&navigation, &grid, Vec3::ZERO, Vec3::new(2.0, 0.0, 0.0)), Ok(vec![Vec3::ZERO, Vec3::new(2.0, 0.0, 0.0)]));
+	}
+
+	#[test]
+	fn stops_after_giving_up_on_a_target() {
+		let mut world = World::new();
+		world.insert_resource(Navigation::default());
+
+		let mut game_entity = GameEntity {
+			entity_id: "orc".to_string(),
+			..Default::default()
+		};
+		game_entity.move_intent.move_forward = true;
+
+		let entity = world.spawn((
+			game_entity,
+			Transform::default(),
+			TargetPosition { x: 5.0, y: 0.0, z: 0.0 },
+			NavigationPath {
+				waypoints: vec![Vec3::ZERO, Vec3::new(5.0, 0.0, 0.0)],
+				replans: MAX_REPLANS,
+				replan: true,
+				..default()
+			},
+			RigidBody::Dynamic,
+			Velocity {
+				linvel: Vec3::new(2.0, -1.0, 0.0),
+				..default()
+			},
+		)).id();
+
+		let mut schedule = Schedule::new();
+		schedule.add_system(plan_paths);
+		schedule.run(&mut world);
+
+		let entity = world.entity(entity);
+
+		assert!(!entity.contains::<NavigationPath>());
+		assert!(!entity.contains::<TargetPosition>());
+		assert!(!entity.get::<GameEntity>().unwrap().move_intent.move_forward);
+		assert_eq!(entity.get::<Velocity>().unwrap().linvel, Vec3::new(0.0, -1.0, 0.0));
+	}
+
+	#[test]
+	fn measures_distance_to_waypoints_along_the_ground() {
+		let (direction, distance) = planar_offset(Vec3::new(1.0, 0.0, 1.0), Vec3::new(1.0, 5.0, 4.0));
+
+		assert_eq!(direction, Vec3::Z);
+		assert_eq!(distance, 3.0);
+	}
+}
@@ -1,16 +1,126 @@
-use pathfinding::prelude::bfs;
+use bevy::prelude::Transform;
+use bevy::prelude::Vec2;
+use bevy::prelude::Vec3;
+use bevy::utils::HashMap;
+use pathfinding::prelude::astar;
 
 use std::fmt;
+use crate::navmesh::NavPolygon;
+use crate::terrain::TerrainHeights;
 use crate::types::NavigationMeshComponent;
 use crate::types::Point;
 
-fn find_current_mesh<'a>(p: &Point, navigation_mesh_components: &'a Vec<NavigationMeshComponent>) -> &'a NavigationMeshComponent {
+/// How far a point off the navigation mesh is moved onto it
+pub const MAX_SNAP_DISTANCE: f32 = 2.0;
+
+/// Path costs are distances in thousandths, so that they are integers
+const COST_SCALE: f32 = 1000.0;
+
+/// Highest step between polygons that share an edge
+const MAX_STEP: f32 = 1.0;
+
+/// Points closer than this to an edge are on it
+const PORTAL_EPSILON: f32 = 0.001;
+
+/// Size of the grid cells used to find polygons that may share edges
+const BUCKET_SIZE: f32 = 16.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathError {
+	/// The point is further than `MAX_SNAP_DISTANCE` from the navigation mesh
+	OutsideMesh(Point),
+	/// Source and target are on parts of the mesh that aren't connected
+	NoPath,
+}
+
+impl fmt::Display for PathError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			PathError::OutsideMesh(point) => write!(f, "{:?} is outside the navigation mesh", point),
+			PathError::NoPath => write!(f, "no path on the navigation mesh"),
+		}
+	}
+}
+
+impl std::error::Error for PathError {}
+
+fn contains(mesh: &NavigationMeshComponent, x: i32, z: i32) -> bool {
+	contains_halves(mesh, x * 2, z * 2)
+}
+
+/// Like `contains`, with `x` and `z` in half units
+fn contains_halves(mesh: &NavigationMeshComponent, x: i32, z: i32) -> bool {
+	let aabb = mesh.aabb();
+
+	x >= aabb.min.x * 2 && x <= aabb.max.x * 2 && z >= aabb.min.z * 2 && z <= aabb.max.z * 2
+}
+
+/// Whether the step between two neighbouring grid points stays on the
+/// mesh: inside one component, or crossing into another where they touch.
+/// Both halves of the step are then inside a component holding its end.
+fn walkable_step(a: &Point, b: &Point, navigation_mesh_components: &[NavigationMeshComponent]) -> bool {
+	let (middle_x, middle_z) = (a.x + b.x, a.z + b.z);
+
+	let reaches_middle = |p: &Point| navigation_mesh_components
+		.iter()
+		.any(|mesh| contains(mesh, p.x, p.z) && contains_halves(mesh, middle_x, middle_z));
+
+	reaches_middle(a) && reaches_middle(b)
+}
+
+/// The point on the navigation mesh at `x`, `z`, with the height of the
+/// component closest to `y` where components overlap
+fn walkable_point(x: i32, z: i32, y: i32, navigation_mesh_components: &[NavigationMeshComponent]) -> Option<Point> {
 	navigation_mesh_components
 		.iter()
-		.find(|mesh| {
-			p.x >= mesh.left_up.x && p.x <= mesh.right_down.x &&
-			p.z >= mesh.left_up.z && p.z <= mesh.right_down.z
-		}).unwrap()
+		.filter(|mesh| contains(mesh, x, z))
+		.map(|mesh| mesh.left_up.y)
+		.min_by_key(|mesh_y| (mesh_y - y).abs())
+		.map(|y| Point { x, z, y })
+}
+
+/// The closest point on the navigation mesh, if there is one within
+/// `MAX_SNAP_DISTANCE`
+pub fn snap_to_mesh(p: &Point, navigation_mesh_components: &[NavigationMeshComponent]) -> Option<Point> {
+	if let Some(point) = walkable_point(p.x, p.z, p.y, navigation_mesh_components) {
+		return Some(point);
+	}
+
+	navigation_mesh_components
+		.iter()
+		.map(|mesh| {
+			let aabb = mesh.aabb();
+
+			Point {
+				x: p.x.clamp(aabb.min.x, aabb.max.x),
+				z: p.z.clamp(aabb.min.z, aabb.max.z),
+				y: mesh.left_up.y,
+			}
+		})
+		.map(|point| (planar_distance(p, &point), point))
+		.filter(|(distance, _)| *distance <= MAX_SNAP_DISTANCE)
+		.min_by(|(a, _), (b, _)| a.total_cmp(b))
+		.map(|(_, point)| point)
+}
+
+fn planar_distance(a: &Point, b: &Point) -> f32 {
+	(((a.x - b.x).pow(2) + (a.z - b.z).pow(2)) as f32).sqrt()
+}
+
+fn cost(a: &Point, b: &Point) -> u32 {
+	let distance = Vec3::new((a.x - b.x) as f32, (a.y - b.y) as f32, (a.z - b.z) as f32).length();
+
+	(distance * COST_SCALE).round() as u32
+}
+
+/// Octile distance, the length of the shortest path on an empty grid. It
+/// never overestimates the cost of steps, which are rounded the same way.
+fn heuristic(p: &Point, dst: &Point) -> u32 {
+	let dx = (p.x - dst.x).unsigned_abs();
+	let dz = (p.z - dst.z).unsigned_abs();
+	let diagonal = cost(&Point { x: 0, z: 0, y: 0 }, &Point { x: 1, z: 1, y: 0 });
+
+	dx.min(dz) * diagonal + dx.abs_diff(dz) * COST_SCALE as u32
 }
 
 pub struct AABB {
@@ -50,51 +160,476 @@ pub fn aabb_collision(a: &AABB, b: &AABB) -> bool {
         && a.min.z <= b.max.z && a.max.z >= b.min.z
 }
 
+impl NavigationMeshComponent {
+	/// The component as a polygon with its four corners, counter-clockwise
+	/// seen from above
+	pub fn polygon(&self) -> NavPolygon {
+		let vertex = |point: &Point| [point.x as f32, point.y as f32, point.z as f32];
+
+		NavPolygon {
+			vertices: vec![
+				vertex(&self.left_up),
+				vertex(&self.left_down),
+				vertex(&self.right_down),
+				vertex(&self.right_up),
+			],
+		}
+	}
+}
+
+/// Whether the components share an edge that can be walked through
 pub fn shapes_collision(a: &NavigationMeshComponent, b: &NavigationMeshComponent) -> bool {
-    let aabb_a = a.aabb();
-    let aabb_b = b.aabb();
-    aabb_collision(&aabb_a, &aabb_b)
+	shared_edge(&a.polygon(), &b.polygon()).is_some()
+}
+
+/// Twice the signed area of the triangle in the xz plane, positive when `c`
+/// is to the right of `a` to `b` seen from above
+fn triangle_area2(a: Vec3, b: Vec3, c: Vec3) -> f32 {
+	(b.x - a.x) * (c.z - a.z) - (c.x - a.x) * (b.z - a.z)
+}
+
+fn planar(v: Vec3) -> Vec2 {
+	Vec2::new(v.x, v.z)
+}
+
+fn edges(polygon: &NavPolygon) -> impl Iterator<Item = (Vec3, Vec3)> + '_ {
+	let vertices = &polygon.vertices;
+
+	(0..vertices.len()).map(move |i| (Vec3::from(vertices[i]), Vec3::from(vertices[(i + 1) % vertices.len()])))
+}
+
+/// The part of an edge of `a` that lies on an edge of `b`, as the left and
+/// right end seen when walking from `a` into `b`
+fn shared_edge(a: &NavPolygon, b: &NavPolygon) -> Option<(Vec3, Vec3)> {
+	for (a0, a1) in edges(a) {
+		let length = planar(a1 - a0).length();
+
+		if length < PORTAL_EPSILON {
+			continue;
+		}
+
+		let direction = planar(a1 - a0) / length;
+
+		for (b0, b1) in edges(b) {
+			let off_line = |p: Vec3| direction.perp_dot(planar(p - a0)).abs() > PORTAL_EPSILON;
+
+			if off_line(b0) || off_line(b1) {
+				continue;
+			}
+
+			// Neighbouring convex polygons run along a shared edge in opposite
+			// directions
+			let (t0, t1) = (direction.dot(planar(b0 - a0)), direction.dot(planar(b1 - a0)));
+
+			if t0 <= t1 {
+				continue;
+			}
+
+			let (start, end) = (t1.max(0.0), t0.min(length));
+
+			if end - start < PORTAL_EPSILON {
+				continue;
+			}
+
+			let on_a = |t: f32| a0.lerp(a1, t / length);
+			let on_b = |t: f32| b1.lerp(b0, (t - t1) / (t0 - t1));
+
+			if (on_a(start).y - on_b(start).y).abs() > MAX_STEP || (on_a(end).y - on_b(end).y).abs() > MAX_STEP {
+				continue;
+			}
+
+			return Some((on_a(end), on_a(start)));
+		}
+	}
+
+	None
+}
+
+fn polygon_contains(polygon: &NavPolygon, p: Vec3) -> bool {
+	// Inside is to the left of every edge of a counter-clockwise polygon
+	polygon.vertices.len() >= 3 && edges(polygon).all(|(a, b)| triangle_area2(a, b, p) <= PORTAL_EPSILON)
+}
+
+/// Height of the polygon at the point's x and z, from the triangle of a fan
+/// over its vertices that holds the point
+fn polygon_height(polygon: &NavPolygon, p: Vec3) -> f32 {
+	let vertices: Vec<Vec3> = polygon.vertices.iter().map(|vertex| Vec3::from(*vertex)).collect();
+
+	for i in 1..vertices.len().saturating_sub(1) {
+		let (a, b, c) = (vertices[0], vertices[i], vertices[i + 1]);
+		let area = triangle_area2(a, b, c);
+
+		if area.abs() < f32::EPSILON {
+			continue;
+		}
+
+		let u = triangle_area2(b, c, p) / area;
+		let v = triangle_area2(c, a, p) / area;
+		let w = 1.0 - u - v;
+
+		if u >= -PORTAL_EPSILON && v >= -PORTAL_EPSILON && w >= -PORTAL_EPSILON {
+			return u * a.y + v * b.y + w * c.y;
+		}
+	}
+
+	vertices.iter().map(|vertex| vertex.y).sum::<f32>() / vertices.len().max(1) as f32
+}
+
+/// The closest point of the polygon's outline in the xz plane
+fn closest_on_outline(polygon: &NavPolygon, p: Vec3) -> Option<Vec3> {
+	edges(polygon)
+		.map(|(a, b)| {
+			let edge = planar(b - a);
+			let t = match edge.length_squared() {
+				length if length > 0.0 => (edge.dot(planar(p - a)) / length).clamp(0.0, 1.0),
+				_ => 0.0,
+			};
+
+			a.lerp(b, t)
+		})
+		.min_by(|a, b| planar(*a - p).length_squared().total_cmp(&planar(*b - p).length_squared()))
+}
+
+/// Shortest line through the portals from the first to the last, which are
+/// both the path's ends. It only turns at portal ends, and only where it
+/// has to.
+fn string_pull(portals: &[(Vec3, Vec3)]) -> Vec<Vec3> {
+	let mut points = vec![portals[0].0];
+
+	let (mut apex, mut left, mut right) = (portals[0].0, portals[0].0, portals[0].1);
+	let (mut left_index, mut right_index) = (0, 0);
+	let mut i = 1;
+
+	while i < portals.len() {
+		let (portal_left, portal_right) = portals[i];
+
+		// Narrow the funnel from the right, unless that crosses the left side
+		if triangle_area2(apex, right, portal_right) <= 0.0 {
+			if apex == right || triangle_area2(apex, left, portal_right) > 0.0 {
+				right = portal_right;
+				right_index = i;
+			} else {
+				points.push(left);
+
+				// The funnel starts again from the corner
+				apex = left;
+				right = apex;
+				right_index = left_index;
+				i = left_index + 1;
+
+				continue;
+			}
+		}
+
+		if triangle_area2(apex, left, portal_left) >= 0.0 {
+			if apex == left || triangle_area2(apex, right, portal_left) < 0.0 {
+				left = portal_left;
+				left_index = i;
+			} else {
+				points.push(right);
+
+				apex = right;
+				left = apex;
+				left_index = right_index;
+				i = right_index + 1;
+
+				continue;
+			}
+		}
+
+		i += 1;
+	}
+
+	let end = portals[portals.len() - 1].0;
+
+	if points.last() != Some(&end) {
+		points.push(end);
+	}
+
+	points
+}
+
+/// An edge shared by two polygons
+#[derive(Debug, Clone)]
+struct Portal {
+	to: usize,
+	left: Vec3,
+	right: Vec3,
+}
+
+impl Portal {
+	fn middle(&self) -> Vec3 {
+		(self.left + self.right) / 2.0
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum PortalNode {
+	Start,
+	Portal(usize),
+	Goal,
+}
+
+/// Convex polygons connected by the edges they share. Paths are searched
+/// over the portals between polygons and then pulled tight with the funnel
+/// algorithm, so they are straight lines that only turn at corners.
+#[derive(Debug, Clone, Default)]
+pub struct PolygonMesh {
+	polygons: Vec<NavPolygon>,
+	portals: Vec<Portal>,
+	/// Portals leading out of each polygon
+	outgoing: Vec<Vec<usize>>,
+}
+
+impl PolygonMesh {
+	pub fn new(polygons: Vec<NavPolygon>) -> Self {
+		// Only polygons in the same buckets of a coarse grid are compared
+		let mut buckets: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+
+		for (index, polygon) in polygons.iter().enumerate() {
+			let (min, max) = polygon.vertices.iter().fold(
+				(Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+				|(min, max), vertex| (min.min(Vec2::new(vertex[0], vertex[2])), max.max(Vec2::new(vertex[0], vertex[2]))),
+			);
+			let (min, max) = (((min - PORTAL_EPSILON) / BUCKET_SIZE).floor(), ((max + PORTAL_EPSILON) / BUCKET_SIZE).floor());
+
+			for x in min.x as i32..=max.x as i32 {
+				for z in min.y as i32..=max.y as i32 {
+					buckets.entry((x, z)).or_default().push(index);
+				}
+			}
+		}
+
+		let mut pairs: Vec<(usize, usize)> = buckets
+			.values()
+			.flat_map(|indices| indices.iter().enumerate().flat_map(move |(i, a)| indices[i + 1..].iter().map(move |b| (*a, *b))))
+			.collect();
+
+		pairs.sort_unstable();
+		pairs.dedup();
+
+		let mut portals = Vec::new();
+		let mut outgoing = vec![Vec::new(); polygons.len()];
+
+		for (a, b) in pairs {
+			if let Some((left, right)) = shared_edge(&polygons[a], &polygons[b]) {
+				outgoing[a].push(portals.len());
+				portals.push(Portal { to: b, left, right });
+
+				outgoing[b].push(portals.len());
+				portals.push(Portal { to: a, left: right, right: left });
+			}
+		}
+
+		PolygonMesh {
+			polygons,
+			portals,
+			outgoing,
+		}
+	}
+
+	pub fn polygons(&self) -> &[NavPolygon] {
+		&self.polygons
+	}
+
+	/// The polygon under the point, preferring the one at the closest
+	/// height, or the closest point within `MAX_SNAP_DISTANCE` of the mesh
+	fn locate(&self, p: Vec3) -> Option<(usize, Vec3)> {
+		let under = self.polygons
+			.iter()
+			.enumerate()
+			.filter(|(_, polygon)| polygon_contains(polygon, p))
+			.map(|(index, polygon)| (index, Vec3::new(p.x, polygon_height(polygon, p), p.z)))
+			.min_by(|(_, a), (_, b)| (a.y - p.y).abs().total_cmp(&(b.y - p.y).abs()));
+
+		if under.is_some() {
+			return under;
+		}
+
+		self.polygons
+			.iter()
+			.enumerate()
+			.filter_map(|(index, polygon)| closest_on_outline(polygon, p).map(|point| (index, point)))
+			.map(|(index, point)| (planar(point - p).length(), index, point))
+			.filter(|(distance, _, _)| *distance <= MAX_SNAP_DISTANCE)
+			.min_by(|(a, _, _), (b, _, _)| a.total_cmp(b))
+			.map(|(_, index, point)| (index, point))
+	}
+
+	/// Shortest path over the polygons from `src` to `dst` as the points
+	/// where it turns, both ends included. Ends off the mesh are moved onto
+	/// it like in `find_path`.
+	pub fn find_path(&self, src: Vec3, dst: Vec3) -> Result<Vec<Vec3>, PathError> {
+		let outside = |p: Vec3| PathError::OutsideMesh(Point { x: p.x.round() as i32, z: p.z.round() as i32, y: p.y.round() as i32 });
+
+		let (src_polygon, src) = self.locate(src).ok_or_else(|| outside(src))?;
+		let (dst_polygon, dst) = self.locate(dst).ok_or_else(|| outside(dst))?;
+
+		let position = |node: &PortalNode| match node {
+			PortalNode::Start => src,
+			PortalNode::Portal(portal) => self.portals[*portal].middle(),
+			PortalNode::Goal => dst,
+		};
+
+		let scaled = |distance: f32| (distance * COST_SCALE) as u32;
+
+		let result = astar(
+			&PortalNode::Start,
+			|node| {
+				let polygon = match node {
+					PortalNode::Start => src_polygon,
+					PortalNode::Portal(portal) => self.portals[*portal].to,
+					PortalNode::Goal => return Vec::new(),
+				};
+
+				let from = position(node);
+
+				self.outgoing[polygon]
+					.iter()
+					.map(|portal| PortalNode::Portal(*portal))
+					.chain((polygon == dst_polygon).then_some(PortalNode::Goal))
+					.map(|next| (next, scaled(from.distance(position(&next)))))
+					.collect::<Vec<_>>()
+			},
+			|node| scaled(position(node).distance(dst)),
+			|node| *node == PortalNode::Goal,
+		);
+
+		let (nodes, _) = result.ok_or(PathError::NoPath)?;
+
+		let portals: Vec<(Vec3, Vec3)> = std::iter::once((src, src))
+			.chain(nodes.iter().filter_map(|node| match node {
+				PortalNode::Portal(portal) => Some((self.portals[*portal].left, self.portals[*portal].right)),
+				_ => None,
+			}))
+			.chain(std::iter::once((dst, dst)))
+			.collect();
+
+		Ok(string_pull(&portals))
+	}
 }
 	
 
 
 
-fn next_possible_points(p: &Point, navigation_mesh_components: &Vec<NavigationMeshComponent>) -> Vec<Point> {
-	let current_mesh = find_current_mesh(p, navigation_mesh_components);
+/// Walkable neighbours of a point with the cost of stepping to them.
+/// Diagonal steps need both orthogonal neighbours to be walkable, so paths
+/// don't cut corners of obstacles, and no step may cross a gap between
+/// components.
+fn next_possible_points(p: &Point, navigation_mesh_components: &[NavigationMeshComponent]) -> Vec<(Point, u32)> {
+	let walkable = |dx: i32, dz: i32| walkable_point(p.x + dx, p.z + dz, p.y, navigation_mesh_components);
 
-	let relevant_meshes = navigation_mesh_components
-		.iter()
-		.filter(|mesh| shapes_collision(current_mesh, mesh));
-
-	let mut possible_points = vec![
-		Point { x: p.x, z: p.z + 1, y: p.y },
-		Point { x: p.x + 1, z: p.z + 1, y: p.y },
-		Point { x: p.x + 1, z: p.z, y: p.y },
-		Point { x: p.x + 1, z: p.z - 1, y: p.y },
-		Point { x: p.x, z: p.z - 1, y: p.y },
-		Point { x: p.x - 1, z: p.z - 1, y: p.y },
-		Point { x: p.x - 1, z: p.z, y: p.y },
-		Point { x: p.x - 1, z: p.z + 1, y: p.y },
-	];
+	let mut possible_points = Vec::new();
+
+	for (dx, dz) in [(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)] {
+		let point = match walkable(dx, dz) {
+			Some(point) => point,
+			None => continue,
+		};
+
+		if dx != 0 && dz != 0 && (walkable(dx, 0).is_none() || walkable(0, dz).is_none()) {
+			continue;
+		}
+
+		if !walkable_step(p, &point, navigation_mesh_components) {
+			continue;
+		}
+
+		let step_cost = cost(p, &point);
+
+		possible_points.push((point, step_cost));
+	}
 
 	possible_points
 }
 
-// DOTO handle multiple navigation meshes
+/// Shortest path over the navigation mesh from `src` to `dst`, both moved
+/// onto the mesh first if they are slightly off it. Points of the path are
+/// at the height of the mesh.
 pub fn find_path(
-	mesh_components: &Vec<NavigationMeshComponent>,
+	mesh_components: &[NavigationMeshComponent],
 	src: &Point,
 	dst: &Point,
-) -> Option<Vec<Point>> {
-	let result = bfs(
-		src, 
+) -> Result<Vec<Point>, PathError> {
+	let src = snap_to_mesh(src, mesh_components).ok_or_else(|| PathError::OutsideMesh(src.clone()))?;
+	let dst = snap_to_mesh(dst, mesh_components).ok_or_else(|| PathError::OutsideMesh(dst.clone()))?;
+
+	let result = astar(
+		&src, 
 		|p| next_possible_points(p, mesh_components), 
-		|p| *p == *dst
+		|p| heuristic(p, &dst),
+		|p| p.x == dst.x && p.z == dst.z
 	);
 
-	result
+	result.map(|(path, _)| path).ok_or(PathError::NoPath)
 }
 
+/// Navigation grid over a terrain placed at `transform`: a strip for each
+/// run of grid cells between two rows whose corners are all no steeper
+/// than `max_slope`. The y of a strip is the terrain height where it starts.
+pub fn terrain_navigation_mesh(
+	terrain: &TerrainHeights,
+	transform: &Transform,
+	max_slope: f32,
+) -> Vec<NavigationMeshComponent> {
+	let corners = [[-0.5, -0.5], [0.5, -0.5], [-0.5, 0.5], [0.5, 0.5]]
+		.map(|[x, z]| transform.transform_point(Vec3::new(x * terrain.size[0], 0.0, z * terrain.size[1])));
+
+	let min_x = corners.iter().map(|c| c.x).fold(f32::INFINITY, f32::min).ceil() as i32;
+	let max_x = corners.iter().map(|c| c.x).fold(f32::NEG_INFINITY, f32::max).floor() as i32;
+	let min_z = corners.iter().map(|c| c.z).fold(f32::INFINITY, f32::min).ceil() as i32;
+	let max_z = corners.iter().map(|c| c.z).fold(f32::NEG_INFINITY, f32::max).floor() as i32;
+
+	let to_local = transform.compute_affine().inverse();
+
+	// Height of every grid point that isn't too steep, row by row
+	let rows: Vec<Vec<Option<i32>>> = (min_z..=max_z)
+		.map(|z| (min_x..=max_x)
+			.map(|x| {
+				let local = to_local.transform_point3(Vec3::new(x as f32, transform.translation.y, z as f32));
+
+				let walkable = terrain
+					.normal_at(local.x, local.z)
+					.map(|normal| (transform.rotation * (normal / transform.scale)).normalize())
+					.is_some_and(|normal| normal.angle_between(Vec3::Y) <= max_slope);
+
+				walkable.then(|| {
+					let height = terrain.height_at(local.x, local.z).unwrap_or_default();
+
+					transform.transform_point(Vec3::new(local.x, height, local.z)).y.round() as i32
+				})
+			})
+			.collect())
+		.collect();
+
+	let mut components = Vec::new();
+
+	for (z, pair) in (min_z..).zip(rows.windows(2)) {
+		let mut strip: Option<(i32, i32)> = None;
+
+		// One past the end closes the last strip of the rows
+		for (x, i) in (min_x..=max_x + 1).zip(0..) {
+			let y = pair[0].get(i).copied().flatten().filter(|_| pair[1].get(i).copied().flatten().is_some());
+
+			match (strip, y) {
+				(None, Some(y)) => strip = Some((x, y)),
+				(Some((start, y)), None) => {
+					components.push(NavigationMeshComponent {
+						left_up: Point { x: start, z, y },
+						right_down: Point { x: x - 1, z: z + 1, y },
+						right_up: Point { x: x - 1, z, y },
+						left_down: Point { x: start, z: z + 1, y },
+					});
+
+					strip = None;
+				},
+				_ => {}
+			}
+		}
+	}
+
+	components
+}
 
 #[cfg(test)]
 mod tests {
@@ -146,59 +681,186 @@ mod tests {
 
 		let path = find_path(&navigation_mesh, &src, &target);
 
-		println!("{:?}", path);
+		assert_eq!(path.unwrap().last(), Some(&target));
+	}
 
-		
-		
-		
-		
+	#[test]
+	fn find_path_around_object() {
+		let navigation_mesh = vec![
+			NavigationMeshComponent {
+				left_up: Point { x: 0, z: 0, y: 0 },
+				right_up: Point { x: 1, z: 0, y: 0 },
+				left_down: Point { x: 0, z: 1, y: 0 },
+				right_down: Point { x: 1, z: 1, y: 0 },
+			},
+			NavigationMeshComponent {
+				left_up: Point { x: 1, z: 0, y: 0 },
+				right_up: Point { x: 2, z: 0, y: 0 },
+				left_down: Point { x: 1, z: 3, y: 0 },
+				right_down: Point { x: 2, z: 3, y: 0 },
+			},
+			NavigationMeshComponent {
+				left_up: Point { x: 0, z: 2, y: 0 },
+				right_up: Point { x: 1, z: 2, y: 0 },
+				left_down: Point { x: 0, z: 3, y: 0 },
+				right_down: Point { x: 1, z: 3, y: 0 },
+			},
+		];
 
+		let src = Point { x: 0, z: 0, y: 0 };
+		let target = Point { x: 0, z: 3, y: 0 };
 
+		let path = find_path(&navigation_mesh, &src, &target);
 
+		// Not along x = 0, where the object is between the first and the
+		// last component
+		assert_eq!(path.unwrap(), vec![
+			Point { x: 0, z: 0, y: 0 },
+			Point { x: 1, z: 1, y: 0 },
+			Point { x: 1, z: 2, y: 0 },
+			Point { x: 0, z: 3, y: 0 },
+		]);
+	}
 
-		
+	#[test]
+	fn find_path_through_gap_in_wall() {
+		// A wall along x = 3 with a gap at z = 4
+		let navigation_mesh = vec![
+			NavigationMeshComponent {
+				left_up: Point { x: 0, z: 0, y: 0 },
+				right_up: Point { x: 2, z: 0, y: 0 },
+				left_down: Point { x: 0, z: 4, y: 0 },
+				right_down: Point { x: 2, z: 4, y: 0 },
+			},
+			NavigationMeshComponent {
+				left_up: Point { x: 2, z: 4, y: 0 },
+				right_up: Point { x: 4, z: 4, y: 0 },
+				left_down: Point { x: 2, z: 4, y: 0 },
+				right_down: Point { x: 4, z: 4, y: 0 },
+			},
+			NavigationMeshComponent {
+				left_up: Point { x: 4, z: 0, y: 0 },
+				right_up: Point { x: 6, z: 0, y: 0 },
+				left_down: Point { x: 4, z: 4, y: 0 },
+				right_down: Point { x: 6, z: 4, y: 0 },
+			},
+		];
+
+		let path = find_path(&navigation_mesh, &Point { x: 2, z: 0, y: 0 }, &Point { x: 4, z: 0, y: 0 }).unwrap();
+
+		assert!(path.contains(&Point { x: 3, z: 4, y: 0 }));
+		assert_eq!(path.last(), Some(&Point { x: 4, z: 0, y: 0 }));
 	}
 
-	// #[test]
-	// fn find_path_around_object() {
-	// 	let navigation_mesh = vec![
-	// 		NavigationMeshComponent {
-	// 			left_up: Point { x: 0, z: 0, y: 0 },
-	// 			right_up: Point { x: 1, z: 0, y: 0 },
-	// 			left_down: Point { x: 0, z: 1, y: 0 },
-	// 			right_down: Point { x: 1, z: 1, y: 0 },
-	// 		},
-	// 		NavigationMeshComponent {
-	// 			left_up: Point { x: 1, z: 0, y: 0 },
-	// 			right_up: Point { x: 2, z: 0, y: 0 },
-	// 			left_down: Point { x: 1, z: 3, y: 0 },
-	// 			right_down: Point { x: 2, z: 3, y: 0 },
-	// 		},
-	// 		NavigationMeshComponent {
-	// 			left_up: Point { x: 0, z: 2, y: 0 },
-	// 			right_up: Point { x: 1, z: 2, y: 0 },
-	// 			left_down: Point { x: 0, z: 3, y: 0 },
-	// 			right_down: Point { x: 1, z: 3, y: 0 },
-	// 		},
-	// 	];
+	#[test]
+	fn snaps_points_near_the_mesh_and_rejects_far_ones() {
+		let navigation_mesh = vec![
+			NavigationMeshComponent {
+				left_up: Point { x: 0, z: 0, y: 2 },
+				right_down: Point { x: 5, z: 5, y: 2 },
+				right_up: Point { x: 5, z: 0, y: 2 },
+				left_down: Point { x: 0, z: 5, y: 2 },
+			}
+		];
+
+		let path = find_path(&navigation_mesh, &Point { x: -1, z: 2, y: 0 }, &Point { x: 3, z: 2, y: 0 }).unwrap();
 
-	// 	let src = Point { x: 0, z: 0, y: 0 };
-	// 	let target = Point { x: 0, z: 3, y: 0 };
+		assert_eq!(path.first(), Some(&Point { x: 0, z: 2, y: 2 }));
+		assert_eq!(path.last(), Some(&Point { x: 3, z: 2, y: 2 }));
 
-	// 	let path = find_path(&navigation_mesh, &src, &target);
+		let far = Point { x: 20, z: 2, y: 0 };
 
-	// 	println!("{:?}", path);
+		assert_eq!(find_path(&navigation_mesh, &Point { x: 1, z: 1, y: 2 }, &far), Err(PathError::OutsideMesh(far)));
+	}
 
-	// 	assert_eq!(path.unwrap(), vec![
-	// 		Point { x: 0, z: 0, y: 0 },
-	// 		Point { x: 0, z: 1, y: 0 },
-	// 		Point { x: 0, z: 2, y: 0 },
-	// 		Point { x: 0, z: 3, y: 0 },
-	// 	]);
-		
-	
-	
-	// }
+	#[test]
+	fn reports_unconnected_meshes() {
+		let navigation_mesh = vec![
+			NavigationMeshComponent {
+				left_up: Point { x: 0, z: 0, y: 0 },
+				right_down: Point { x: 2, z: 2, y: 0 },
+				right_up: Point { x: 2, z: 0, y: 0 },
+				left_down: Point { x: 0, z: 2, y: 0 },
+			},
+			NavigationMeshComponent {
+				left_up: Point { x: 10, z: 0, y: 0 },
+				right_down: Point { x: 12, z: 2, y: 0 },
+				right_up: Point { x: 12, z: 0, y: 0 },
+				left_down: Point { x: 10, z: 2, y: 0 },
+			},
+		];
+
+		let path = find_path(&navigation_mesh, &Point { x: 1, z: 1, y: 0 }, &Point { x: 11, z: 1, y: 0 });
+
+		assert_eq!(path, Err(PathError::NoPath));
+	}
+
+	#[test]
+	fn leaves_steep_terrain_out_of_the_navigation_mesh() {
+		// Flat on the left, a cliff up to 10 between x = 0 and x = 1
+		let terrain = TerrainHeights {
+			size: [4.0, 2.0],
+			resolution: 5,
+			heights: [0.0, 0.0, 0.0, 10.0, 10.0].repeat(5),
+		};
+
+		let navigation_mesh = terrain_navigation_mesh(&terrain, &Transform::from_xyz(10.0, 1.0, 0.0), std::f32::consts::FRAC_PI_4);
+
+		// Two strips between each of the rows z = -1, 0 and 1
+		assert_eq!(navigation_mesh.len(), 4);
+		assert_eq!(navigation_mesh[3].left_up, Point { x: 12, z: 0, y: 11 });
+		assert_eq!(navigation_mesh[2], NavigationMeshComponent {
+			left_up: Point { x: 8, z: 0, y: 1 },
+			right_down: Point { x: 9, z: 1, y: 1 },
+			right_up: Point { x: 9, z: 0, y: 1 },
+			left_down: Point { x: 8, z: 1, y: 1 },
+		});
+	}
+
+	fn polygon(vertices: &[[f32; 2]]) -> NavPolygon {
+		NavPolygon {
+			vertices: vertices.iter().map(|[x, z]| [*x, 0.0, *z]).collect(),
+		}
+	}
+
+	#[test]
+	fn pulls_paths_tight_around_corners() {
+		// An L of two corridors meeting at x = 2, z = 10..12
+		let mesh = PolygonMesh::new(vec![
+			polygon(&[[0.0, 0.0], [0.0, 12.0], [2.0, 12.0], [2.0, 0.0]]),
+			polygon(&[[2.0, 10.0], [2.0, 12.0], [12.0, 12.0], [12.0, 10.0]]),
+		]);
+
+		let path = mesh.find_path(Vec3::new(1.0, 0.0, 1.0), Vec3::new(11.0, 0.0, 11.0)).unwrap();
+
+		assert_eq!(path, vec![
+			Vec3::new(1.0, 0.0, 1.0),
+			Vec3::new(2.0, 0.0, 10.0),
+			Vec3::new(11.0, 0.0, 11.0),
+		]);
+
+		let path = mesh.find_path(Vec3::new(11.0, 0.0, 11.0), Vec3::new(1.0, 0.0, 1.0)).unwrap();
+
+		assert_eq!(path[1], Vec3::new(2.0, 0.0, 10.0));
+	}
+
+	#[test]
+	fn walks_straight_across_polygons_of_any_shape() {
+		let mesh = PolygonMesh::new(vec![
+			polygon(&[[0.0, 0.0], [0.0, 4.0], [4.0, 4.0]]),
+			polygon(&[[0.0, 4.0], [0.0, 8.0], [4.0, 8.0], [6.0, 6.0], [4.0, 4.0]]),
+		]);
+
+		let path = mesh.find_path(Vec3::new(1.0, 0.0, 3.0), Vec3::new(3.0, 0.0, 7.0)).unwrap();
+
+		assert_eq!(path, vec![Vec3::new(1.0, 0.0, 3.0), Vec3::new(3.0, 0.0, 7.0)]);
+
+		// Moved onto the mesh from just outside it
+		let path = mesh.find_path(Vec3::new(1.0, 0.0, 3.0), Vec3::new(7.0, 0.0, 6.0)).unwrap();
+
+		assert_eq!(path.last(), Some(&Vec3::new(6.0, 0.0, 6.0)));
+		assert_eq!(mesh.find_path(Vec3::new(1.0, 0.0, 3.0), Vec3::new(20.0, 0.0, 6.0)), Err(PathError::OutsideMesh(Point { x: 20, z: 6, y: 0 })));
+	}
 
 	#[test]
 	fn test_is_connected() {
@@ -7,6 +7,7 @@ use crate::console_plugin::Console;
 use crate::math::compute_new_angle;
 use crate::math::rotate_vec;
 use crate::types::GameEntity;
+use crate::types::NavigationPath;
 use crate::types::PlayerCamera;
 use crate::types::You;
 use crate::types::Menu;
@@ -85,8 +86,10 @@ pub fn handle_mouse_input(
 	}
 }
 
+/// Moves entities by their move intent. Entities following a path set it
+/// to animate and are moved by `npc::follow_paths` instead.
 pub fn move_game_entity(
-	mut query: Query<(&mut Transform, &GameEntity)>,
+	mut query: Query<(&mut Transform, &GameEntity), Without<NavigationPath>>,
 	console: Res<Console>,
 	time: Res<Time>,
 ) {
@@ -0,0 +1,435 @@
+use std::f32::consts::FRAC_PI_4;
+
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::render::render_resource::TextureFormat;
+use bevy_rapier3d::prelude::*;
+
+use crate::map::MapShapeType;
+use crate::map::MapTerrain;
+use crate::map_materials::map_color;
+use crate::map_materials::scale_uvs;
+use crate::path_finding::terrain_navigation_mesh;
+use crate::types::MapMaterials;
+use crate::types::MapShapeSource;
+use crate::types::NavigationMesh;
+use crate::types::NeedsTerrain;
+use crate::types::Terrain;
+
+/// Largest resolution used when a terrain does not set one
+const MAX_DEFAULT_RESOLUTION: u32 = 512;
+
+pub struct TerrainPlugin;
+
+impl Plugin for TerrainPlugin {
+	fn build(&self, app: &mut App) {
+		app.add_system(build_terrains);
+	}
+}
+
+/// Reads a pixel as RGBA from 0 to 1. Values are used as stored, sRGB images
+/// are not converted to linear.
+fn texel(image: &Image, x: u32, y: u32) -> Option<[f32; 4]> {
+	let (channels, channel_size) = match image.texture_descriptor.format {
+		TextureFormat::R8Unorm => (1, 1),
+		TextureFormat::Rg8Unorm => (2, 1),
+		TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => (4, 1),
+		TextureFormat::R16Uint => (1, 2),
+		TextureFormat::Rg16Uint => (2, 2),
+		TextureFormat::Rgba16Uint => (4, 2),
+		TextureFormat::Rgba32Float => (4, 4),
+		_ => return None,
+	};
+
+	let index = (y * image.texture_descriptor.size.width + x) as usize;
+	let bytes = image.data.get(index * channels * channel_size..(index + 1) * channels * channel_size)?;
+
+	let mut rgba = [0.0, 0.0, 0.0, 1.0];
+
+	for (value, channel) in rgba.iter_mut().zip(bytes.chunks_exact(channel_size)) {
+		*value = match channel {
+			[value] => *value as f32 / 255.0,
+			[low, high] => u16::from_le_bytes([*low, *high]) as f32 / 65535.0,
+			[a, b, c, d] => f32::from_le_bytes([*a, *b, *c, *d]),
+			_ => unreachable!(),
+		};
+	}
+
+	Some(rgba)
+}
+
+/// Bilinearly filtered pixel at `u` and `v` from 0 to 1
+fn sample(image: &Image, u: f32, v: f32) -> [f32; 4] {
+	let size = image.texture_descriptor.size;
+
+	let x = u.clamp(0.0, 1.0) * size.width.saturating_sub(1) as f32;
+	let y = v.clamp(0.0, 1.0) * size.height.saturating_sub(1) as f32;
+
+	let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+	let (x1, y1) = ((x0 + 1).min(size.width.saturating_sub(1)), (y0 + 1).min(size.height.saturating_sub(1)));
+	let (tx, ty) = (x.fract(), y.fract());
+
+	let pixel = |x, y| texel(image, x, y).unwrap_or_default();
+	let (top_left, top_right) = (pixel(x0, y0), pixel(x1, y0));
+	let (bottom_left, bottom_right) = (pixel(x0, y1), pixel(x1, y1));
+
+	let mut rgba = [0.0; 4];
+
+	for (index, value) in rgba.iter_mut().enumerate() {
+		let top = top_left[index] + (top_right[index] - top_left[index]) * tx;
+		let bottom = bottom_left[index] + (bottom_right[index] - bottom_left[index]) * tx;
+
+		*value = top + (bottom - top) * ty;
+	}
+
+	rgba
+}
+
+/// Grid of terrain heights centered on the terrain's position
+#[derive(Clone, Debug)]
+pub struct TerrainHeights {
+	/// Width along x and depth along z
+	pub size: [f32; 2],
+	/// Vertices along each side
+	pub resolution: usize,
+	/// Heights row by row from -z to +z, each row from -x to +x
+	pub heights: Vec<f32>,
+}
+
+impl TerrainHeights {
+	/// Samples the red channel of a heightmap, the top of the image being -z
+	pub fn from_heightmap(image: &Image, size: [f32; 2], height_scale: f32, resolution: usize) -> Self {
+		let resolution = resolution.max(2);
+		let last = (resolution - 1) as f32;
+
+		let heights = (0..resolution * resolution)
+			.map(|index| {
+				let (column, row) = (index % resolution, index / resolution);
+
+				sample(image, column as f32 / last, row as f32 / last)[0] * height_scale
+			})
+			.collect();
+
+		TerrainHeights { size, resolution, heights }
+	}
+
+	fn cell_size(&self) -> Vec2 {
+		let last = (self.resolution - 1) as f32;
+
+		Vec2::new(self.size[0] / last, self.size[1] / last)
+	}
+
+	fn vertex(&self, column: usize, row: usize) -> Vec3 {
+		let cell_size = self.cell_size();
+
+		Vec3::new(
+			column as f32 * cell_size.x - self.size[0] / 2.0,
+			self.heights[row * self.resolution + column],
+			row as f32 * cell_size.y - self.size[1] / 2.0,
+		)
+	}
+
+	/// Height at a point relative to the terrain's position, None outside of
+	/// the terrain
+	pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+		let cell_size = self.cell_size();
+		let column = (x + self.size[0] / 2.0) / cell_size.x;
+		let row = (z + self.size[1] / 2.0) / cell_size.y;
+		let last = (self.resolution - 1) as f32;
+
+		if !(0.0..=last).contains(&column) || !(0.0..=last).contains(&row) {
+			return None;
+		}
+
+		let (column0, row0) = (column.floor().min(last - 1.0) as usize, row.floor().min(last - 1.0) as usize);
+		let (tx, tz) = (column - column0 as f32, row - row0 as f32);
+
+		let height = |column: usize, row: usize| self.heights[row * self.resolution + column];
+		let top = height(column0, row0) + (height(column0 + 1, row0) - height(column0, row0)) * tx;
+		let bottom = height(column0, row0 + 1) + (height(column0 + 1, row0 + 1) - height(column0, row0 + 1)) * tx;
+
+		Some(top + (bottom - top) * tz)
+	}
+
+	/// Surface normal at a point relative to the terrain's position
+	pub fn normal_at(&self, x: f32, z: f32) -> Option<Vec3> {
+		let center = self.height_at(x, z)?;
+		let cell_size = self.cell_size();
+
+		let height = |x: f32, z: f32| self.height_at(x, z).unwrap_or(center);
+		let dx = (height(x + cell_size.x, z) - height(x - cell_size.x, z)) / (2.0 * cell_size.x);
+		let dz = (height(x, z + cell_size.y) - height(x, z - cell_size.y)) / (2.0 * cell_size.y);
+
+		Some(Vec3::new(-dx, 1.0, -dz).normalize())
+	}
+
+	/// Triangle mesh of the terrain, with vertex colors when given
+	pub fn mesh(&self, colors: Option<Vec<[f32; 4]>>) -> Mesh {
+		let resolution = self.resolution;
+		let last = (resolution - 1) as f32;
+
+		let mut positions = Vec::with_capacity(resolution * resolution);
+		let mut normals = Vec::with_capacity(resolution * resolution);
+		let mut uvs = Vec::with_capacity(resolution * resolution);
+
+		for row in 0..resolution {
+			for column in 0..resolution {
+				let vertex = self.vertex(column, row);
+
+				positions.push(vertex.to_array());
+				normals.push(self.normal_at(vertex.x, vertex.z).unwrap_or(Vec3::Y).to_array());
+				uvs.push([column as f32 / last, row as f32 / last]);
+			}
+		}
+
+		let mut indices = Vec::with_capacity((resolution - 1) * (resolution - 1) * 6);
+
+		for row in 0..resolution - 1 {
+			for column in 0..resolution - 1 {
+				let index = (row * resolution + column) as u32;
+				let below = index + resolution as u32;
+
+				indices.extend([index, below, index + 1, index + 1, below, below + 1]);
+			}
+		}
+
+		let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+		mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+		mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+		mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+
+		if let Some(colors) = colors {
+			mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+		}
+
+		mesh.set_indices(Some(Indices::U32(indices)));
+
+		mesh
+	}
+
+	/// Heightfield collider matching the mesh
+	pub fn collider(&self) -> Collider {
+		let resolution = self.resolution;
+
+		// Rapier wants the heights column by column, with rows along z
+		let heights = (0..resolution * resolution)
+			.map(|index| self.heights[(index % resolution) * resolution + index / resolution])
+			.collect();
+
+		Collider::heightfield(heights, resolution, resolution, Vec3::new(self.size[0], 1.0, self.size[1]))
+	}
+}
+
+/// Vertex colors blending the base colors of the layer materials by the
+/// channels of the splat map
+fn splat_colors(heights: &TerrainHeights, splat_map: &Image, layer_colors: &[Color]) -> Vec<[f32; 4]> {
+	let resolution = heights.resolution;
+	let last = (resolution - 1) as f32;
+
+	(0..resolution * resolution)
+		.map(|index| {
+			let (column, row) = (index % resolution, index / resolution);
+			let weights = sample(splat_map, column as f32 / last, row as f32 / last);
+			let total: f32 = weights.iter().take(layer_colors.len()).sum();
+
+			if total <= 0.0 {
+				return layer_colors.first().copied().unwrap_or(Color::WHITE).as_linear_rgba_f32();
+			}
+
+			let mut color = [0.0; 4];
+
+			for (weight, layer_color) in weights.iter().zip(layer_colors) {
+				for (value, layer_value) in color.iter_mut().zip(layer_color.as_linear_rgba_f32()) {
+					*value += layer_value * weight / total;
+				}
+			}
+
+			color
+		})
+		.collect()
+}
+
+/// Builds the mesh, collider and navigation mesh of terrains once their
+/// images have loaded
+fn build_terrains(
+	mut commands: Commands,
+	query: Query<(Entity, &NeedsTerrain, &MapShapeSource, &GlobalTransform)>,
+	images: Res<Assets<Image>>,
+	asset_server: Res<AssetServer>,
+	mut meshes: ResMut<Assets<Mesh>>,
+	mut materials: ResMut<Assets<StandardMaterial>>,
+	map_materials: Res<MapMaterials>,
+) {
+	for (entity, needs_terrain, source, global_transform) in query.iter() {
+		let terrain = match &source.map_shape.shape {
+			MapShapeType::Terrain(terrain) => terrain,
+			_ => continue,
+		};
+
+		let failed = std::iter::once(&needs_terrain.heightmap)
+			.chain(needs_terrain.splat_map.iter())
+			.any(|handle| asset_server.get_load_state(handle) == LoadState::Failed);
+
+		if failed {
+			log::error!("terrain {} failed to load its images", source.map_shape.id);
+
+			commands.entity(entity).remove::<NeedsTerrain>();
+
+			continue;
+		}
+
+		let heightmap = match images.get(&needs_terrain.heightmap) {
+			Some(heightmap) => heightmap,
+			None => continue,
+		};
+
+		let splat_map = match &needs_terrain.splat_map {
+			Some(handle) => match images.get(handle) {
+				Some(splat_map) => Some(splat_map),
+				None => continue,
+			},
+			None => None,
+		};
+
+		log::info!("building terrain {}", source.map_shape.id);
+
+		let resolution = terrain.resolution
+			.unwrap_or_else(|| heightmap.texture_descriptor.size.width.min(MAX_DEFAULT_RESOLUTION));
+
+		let heights = TerrainHeights::from_heightmap(heightmap, terrain.size, terrain.height_scale, resolution as usize);
+
+		let mut entity_commands = commands.entity(entity);
+
+		entity_commands.remove::<NeedsTerrain>();
+
+		entity_commands.insert((
+			meshes.add(terrain_mesh(terrain, &heights, splat_map, &map_materials)),
+			terrain_material(terrain, &map_materials, &mut materials),
+			NavigationMesh {
+				components: terrain_navigation_mesh(&heights, &global_transform.compute_transform(), terrain.max_slope.unwrap_or(FRAC_PI_4))
+			},
+		));
+
+		if terrain.collider != Some(false) && source.map_shape.collider != Some(false) {
+			entity_commands.insert((
+				heights.collider(),
+				RigidBody::Fixed,
+			));
+		}
+
+		entity_commands.insert(Terrain { heights });
+	}
+}
+
+fn terrain_mesh(
+	terrain: &MapTerrain,
+	heights: &TerrainHeights,
+	splat_map: Option<&Image>,
+	map_materials: &MapMaterials,
+) -> Mesh {
+	let layer_colors: Vec<Color> = terrain.layers
+		.iter()
+		.flatten()
+		.map(|layer| {
+			map_materials.definitions
+				.get(layer)
+				.and_then(|material| material.base_color.as_deref())
+				.map(map_color)
+				.unwrap_or(Color::WHITE)
+		})
+		.collect();
+
+	let colors = match splat_map {
+		Some(splat_map) if !layer_colors.is_empty() => Some(splat_colors(heights, splat_map, &layer_colors)),
+		_ => None,
+	};
+
+	let mut mesh = heights.mesh(colors);
+
+	let uv_scale = terrain.material
+		.as_ref()
+		.and_then(|name| map_materials.definitions.get(name))
+		.and_then(|material| material.uv_scale);
+
+	if let Some(uv_scale) = uv_scale {
+		scale_uvs(&mut mesh, uv_scale);
+	}
+
+	mesh
+}
+
+fn terrain_material(
+	terrain: &MapTerrain,
+	map_materials: &MapMaterials,
+	materials: &mut Assets<StandardMaterial>,
+) -> Handle<StandardMaterial> {
+	if let Some(name) = &terrain.material {
+		match map_materials.handles.get(name) {
+			Some(handle) => return handle.clone(),
+			None => log::error!("terrain references unknown material `{}`", name),
+		}
+	}
+
+	// Vertex colors of the layers are multiplied with the base color
+	let base_color = match terrain.layers {
+		Some(_) => Color::WHITE,
+		None => Color::rgb(0.3, 0.5, 0.3),
+	};
+
+	materials.add(StandardMaterial {
+		base_color,
+		..Default::default()
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use bevy::render::render_resource::Extent3d;
+	use bevy::render::render_resource::TextureDimension;
+
+	use super::*;
+
+	/// Heightmap rising from black on the left to white on the right
+	fn ramp(width: u32) -> Image {
+		let data = (0..width * 2)
+			.flat_map(|index| {
+				let value = (index % width) as f32 / (width - 1) as f32;
+
+				[value, value, value, 1.0]
+			})
+			.flat_map(f32::to_le_bytes)
+			.collect();
+
+		Image::new(
+			Extent3d { width, height: 2, depth_or_array_layers: 1 },
+			TextureDimension::D2,
+			data,
+			TextureFormat::Rgba32Float,
+		)
+	}
+
+	#[test]
+	fn samples_heights_from_the_heightmap() {
+		let heights = TerrainHeights::from_heightmap(&ramp(5), [8.0, 8.0], 2.0, 3);
+
+		assert_eq!(heights.heights, vec![0.0, 1.0, 2.0, 0.0, 1.0, 2.0, 0.0, 1.0, 2.0]);
+		assert_eq!(heights.height_at(-4.0, 0.0), Some(0.0));
+		assert_eq!(heights.height_at(2.0, 3.0), Some(1.5));
+		assert_eq!(heights.height_at(4.5, 0.0), None);
+
+		let normal = heights.normal_at(0.0, 0.0).unwrap();
+
+		assert!(normal.x < 0.0 && normal.z.abs() < 1e-6);
+	}
+
+	#[test]
+	fn builds_a_mesh_of_the_grid() {
+		let heights = TerrainHeights::from_heightmap(&ramp(5), [8.0, 8.0], 2.0, 4);
+		let mesh = heights.mesh(None);
+
+		assert_eq!(mesh.count_vertices(), 16);
+		assert_eq!(mesh.indices().unwrap().len(), 3 * 3 * 6);
+	}
+}
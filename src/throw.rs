@@ -3,6 +3,7 @@ use bevy::utils::FloatOrd;
 use bevy_rapier3d::prelude::*;
 
 use crate::*;
+use crate::types::Behavior;
 use crate::types::BulletProperties;
 use crate::types::GameAssets;
 use crate::types::Lifetime;
@@ -22,7 +23,7 @@ impl Plugin for TowerPlugin {
 
 fn tower_shooting(
     mut commands: Commands,
-    mut towers: Query<(Entity, &mut Tower, &GlobalTransform)>,
+    mut towers: Query<(Entity, &mut Tower, &GlobalTransform), Without<Behavior>>,
     targets: Query<&GlobalTransform, With<Target>>,
     bullet_assets: Res<GameAssets>,
     time: Res<Time>,
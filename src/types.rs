@@ -1,15 +1,63 @@
+use std::sync::Arc;
+
+use bevy::asset::HandleId;
 use bevy::gltf::Gltf;
 use bevy::utils::HashMap;
 
 use bevy::prelude::*;
+use bevy_rapier3d::prelude::Collider;
 use crate::map::*;
 
 #[derive(Clone, Component)]
 pub struct You;
 
+/// Debug mesh to show in dev builds for an entity whose template has no
+/// asset, shaped like its collider
+#[derive(Clone, Component)]
+pub struct NeedsPlaceholder {
+	pub collider: Option<MapEntityCollider>,
+}
+
+/// Colliders to build from the meshes of a glTF scene, as children of the
+/// entity holding the scene
 #[derive(Clone, Component)]
 pub struct AddCollidingMesh {
-	pub glft: Handle<Gltf>,
+	pub scene: Handle<Scene>,
+	/// Transform of the scene relative to the entity
+	pub scene_transform: Transform,
+	pub collision_mesh: MapCollisionMesh,
+}
+
+/// A cuboid collider fitting the meshes of a glTF scene, for the
+/// `AABB` template collider
+#[derive(Clone, Component)]
+pub struct AddBoundingCollider {
+	pub scene: Handle<Scene>,
+	/// Transform of the scene relative to the entity
+	pub scene_transform: Transform,
+	pub padding: f32,
+}
+
+/// Bounds of an `AABB` collider relative to the entity, padding included
+#[derive(Clone, Component)]
+pub struct ComputedBounds {
+	pub min: Vec3,
+	pub max: Vec3,
+}
+
+/// Debug mesh showing the `ComputedBounds` of its parent
+#[derive(Clone, Component)]
+pub struct BoundsOverlay;
+
+/// Colliders built from glTF scenes with their transforms in the scene and
+/// the triangles they were built from, by scene and settings, so that every
+/// instance of an asset shares them
+#[derive(Clone, Resource, Default)]
+pub struct CollisionMeshCache {
+	pub colliders: HashMap<(HandleId, MapCollisionMesh), Vec<(Transform, Collider, Arc<Vec<[Vec3; 3]>>)>>,
+	/// Bounds of the meshes of a scene in scene space, None for scenes
+	/// without meshes
+	pub bounds: HashMap<HandleId, Option<(Vec3, Vec3)>>,
 }
 
 #[derive(Clone, Component)]
@@ -18,12 +66,67 @@ pub struct NeedsTemplate {
 	pub map_enitity: MapEntity
 }
 
+#[derive(Clone, Component)]
+pub struct MapEntitySource {
+	pub map_entity: MapEntity
+}
+
+#[derive(Clone, Component)]
+pub struct MapShapeSource {
+	pub map_shape: MapShape
+}
+
+#[derive(Clone, Component)]
+pub struct MapLightSource {
+	pub light: Light
+}
+
+/// Prefab instance from the map, the parent of the instance's parts
+#[derive(Clone, Component)]
+pub struct MapPrefabInstanceSource {
+	pub instance: MapPrefabInstance
+}
+
+/// Entity, shape or light spawned as part of a prefab instance
+#[derive(Clone, Component)]
+pub struct PrefabPart;
+
+/// Camera settings from the map, kept on the spawned player camera
+#[derive(Clone, Component)]
+pub struct MapCameraSource {
+	pub map_camera: MapCamera
+}
+
 #[derive(Clone, Component)]
 pub struct NeedsAsset {
 	pub asset: String,
-	pub add_colliding_mesh: bool,
+	pub collision_mesh: Option<MapCollisionMesh>,
+	/// Padding of an `AABB` collider fitted to the asset
+	pub bounding_collider: Option<f32>,
 	pub initial_transform: Option<[f32; 3]>,
-	pub initial_rotation_y: Option<f32>,
+	pub initial_rotation: Option<[f32; 3]>,
+	pub materials: Option<std::collections::BTreeMap<String, String>>,
+}
+
+/// Terrain shape waiting for its images to load
+#[derive(Clone, Component)]
+pub struct NeedsTerrain {
+	pub heightmap: Handle<Image>,
+	pub splat_map: Option<Handle<Image>>,
+}
+
+/// Heights of a built terrain, for placing things on it
+#[derive(Clone, Component)]
+pub struct Terrain {
+	pub heights: crate::terrain::TerrainHeights,
+}
+
+/// Map materials to put on the meshes of a glTF scene once it has spawned,
+/// by glTF material name
+#[derive(Clone, Component)]
+pub struct MaterialOverrides {
+	pub gltf: Handle<Gltf>,
+	pub overrides: std::collections::BTreeMap<String, String>,
 }
 
 #[derive(Clone, Component, Default)]
@@ -45,7 +148,81 @@ pub struct StopAnimation;
 
 #[derive(Clone, Resource, Default)]
 pub struct MapTemplates {
-	pub templates: HashMap<String, MapTemplate>
+	/// Templates with their `extends` chain resolved
+	pub templates: HashMap<String, MapTemplate>,
+	/// Templates as written in the map
+	pub definitions: HashMap<String, MapTemplate>,
+}
+
+impl MapTemplates {
+	/// Adds or replaces a template definition. Returns the names of all
+	/// templates whose resolved form changed, including derived ones.
+	pub fn insert(&mut self, template: MapTemplate) -> Vec<String> {
+		self.definitions.insert(template.name.clone(), template);
+		self.resolve()
+	}
+
+	pub fn remove(&mut self, name: &str) -> Vec<String> {
+		self.definitions.remove(name);
+		self.resolve()
+	}
+
+	pub fn for_entity(&self, entity: &MapEntity) -> Option<MapTemplate> {
+		self.templates
+			.get(&entity.template)
+			.map(|template| template.for_entity(entity))
+	}
+
+	fn resolve(&mut self) -> Vec<String> {
+		let mut resolved = HashMap::new();
+
+		for name in self.definitions.keys() {
+			let template = match resolve_template(name, |n| self.definitions.get(n)) {
+				Ok(template) => template,
+				Err(err) => {
+					log::warn!("{}", err);
+
+					self.definitions[name].clone()
+				}
+			};
+
+			resolved.insert(name.clone(), template);
+		}
+
+		let mut changed: Vec<String> = resolved
+			.iter()
+			.filter(|(name, template)| self.templates.get(*name) != Some(template))
+			.map(|(name, _)| name.clone())
+			.collect();
+
+		changed.extend(
+			self.templates
+				.keys()
+				.filter(|name| !resolved.contains_key(*name))
+				.cloned()
+		);
+
+		self.templates = resolved;
+
+		changed
+	}
+}
+
+/// Materials of the map by name. Shapes and entities share the handles, so
+/// editing a material changes everything that uses it.
+#[derive(Clone, Resource, Default)]
+pub struct MapMaterials {
+	pub definitions: HashMap<String, MapMaterial>,
+	pub handles: HashMap<String, Handle<StandardMaterial>>,
+	/// Textures of materials with a `uv_scale`, which need a repeating
+	/// sampler once they have loaded
+	pub pending_repeat: Vec<Handle<Image>>,
+}
+
+/// Prefabs of the map by name
+#[derive(Clone, Resource, Default)]
+pub struct MapPrefabs {
+	pub definitions: HashMap<String, MapPrefab>,
 }
 
 #[derive(Clone, Default)]
@@ -73,6 +250,12 @@ pub struct AssetPacks {
 	pub asset_packs: HashMap<String, AssetPack>
 }
 
+/// Sent when the glTF file of an asset pack changed on disk and the pack was
+/// rebuilt
+pub struct AssetPackReloaded {
+	pub asset: String,
+}
+
 #[derive(Clone, Default)]
 pub struct MoveIntent {
 	pub move_forward: bool,
@@ -94,6 +277,8 @@ pub struct GameEntity {
 	pub run_animation: Option<String>,
 	pub reload_animation: Option<String>,
 	pub shoot_animation: Option<String>,
+	pub walk_speed: Option<f32>,
+	pub run_speed: Option<f32>,
 	pub max_health: f32,
 	pub curr_health: f32,
 	pub move_intent: MoveIntent,
@@ -167,16 +352,30 @@ pub struct MoveToLocation {
 pub enum GameState {
     Splash,
     Menu,
+	/// Preloads the assets of the map before the game starts
+	Loading,
     Game,
-	GameOver
+	GameOver,
+	MapError
 }
 
 impl Default for GameState {
 	fn default() -> Self {
-		GameState::Game
+		GameState::Loading
 	}
 }
 
+/// Assets of the map preloaded in `GameState::Loading`. The handles are
+/// kept so the assets stay loaded during the game.
+#[derive(Clone, Resource, Default)]
+pub struct LoadingAssets {
+	pub total: usize,
+	pub pending: Vec<(MapAsset, HandleUntyped)>,
+	pub loaded: Vec<(MapAsset, HandleUntyped)>,
+	/// Assets that could not be loaded, with the reason
+	pub failed: Vec<(MapAsset, String)>,
+}
+
 // One of the two settings that can be set through the menu. It will be a resource in the app
 #[derive(Resource, Debug, Component, PartialEq, Eq, Clone, Copy)]
 pub enum DisplayQuality {
@@ -222,8 +421,79 @@ pub struct Fps;
 #[derive(Clone, Component, Default)]
 pub struct TargetPosition {
 	pub x: f32,
+	pub y: f32,
 	pub z: f32
 }
+
+/// Waypoints an entity follows to its `TargetPosition`
+#[derive(Clone, Component, Default)]
+pub struct NavigationPath {
+	pub waypoints: Vec<Vec3>,
+	pub next: usize,
+	/// Closest the entity got to the next waypoint
+	pub closest: f32,
+	/// Time since it last got closer
+	pub stuck_for: f32,
+	/// Paths planned to the same target after getting stuck
+	pub replans: u32,
+	pub replan: bool,
+}
+
+/// What the behavior tree of an NPC wants it to do
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BehaviorIntent {
+	Idle,
+	MoveTo {
+		target: Vec3,
+		run: bool,
+	},
+	Patrol,
+	Attack {
+		target: Vec3,
+	},
+}
+
+impl Default for BehaviorIntent {
+	fn default() -> Self {
+		BehaviorIntent::Idle
+	}
+}
+
+/// What the nodes of a behavior tree remember between ticks. Nodes are
+/// identified by their position in a pre-order walk of the tree.
+#[derive(Clone, Debug, Default)]
+pub struct BehaviorState {
+	/// Nodes ticked last, from the root to the node that decided the tick
+	pub active: Vec<usize>,
+	/// When running `Wait` nodes started
+	pub started: HashMap<usize, f32>,
+	/// When `Cooldown` nodes let their child run again
+	pub cooldowns: HashMap<usize, f32>,
+	pub intent: BehaviorIntent,
+}
+
+/// Behavior tree an NPC runs, from its template
+#[derive(Clone, Component, Debug)]
+pub struct Behavior {
+	pub tree: MapBehavior,
+	/// Where the entity was placed in the map
+	pub home: Vec3,
+	pub state: BehaviorState,
+}
+
+impl Behavior {
+	pub fn new(tree: MapBehavior, home: Vec3) -> Self {
+		Behavior {
+			tree,
+			home,
+			state: BehaviorState::default(),
+		}
+	}
+}
+
+/// Text listing the active behavior node of every NPC
+#[derive(Component)]
+pub struct BehaviorOverlay;
 	
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Point {
@@ -232,6 +502,7 @@ pub struct Point {
 	pub y: i32,
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct NavigationMeshComponent {
 	pub left_up: Point,
 	pub right_down: Point,
@@ -239,6 +510,36 @@ pub struct NavigationMeshComponent {
 	pub left_down: Point,
 }
 
+/// Walkable area contributed by a map shape
+#[derive(Clone, Component, Default)]
+pub struct NavigationMesh {
+	pub components: Vec<NavigationMeshComponent>
+}
+
+/// Triangles of a collider built by `add_collisions`, in the collider's
+/// space, that the navigation mesh is baked from
+#[derive(Clone, Component)]
+pub struct NavigationSource {
+	pub triangles: Arc<Vec<[Vec3; 3]>>,
+}
+
+/// Navigation settings of the map and the mesh baked with them
+#[derive(Clone, Resource, Default)]
+pub struct Navigation {
+	pub settings: Option<MapNavigation>,
+	/// Hash of the geometry and settings the mesh was baked from
+	pub hash: Option<u64>,
+	pub mesh: crate::path_finding::PolygonMesh,
+}
+
+/// The `NavigationMesh` baked from the map's static colliders
+#[derive(Clone, Component)]
+pub struct BakedNavigationMesh;
+
+/// Debug mesh showing the baked navigation mesh
+#[derive(Clone, Component)]
+pub struct NavigationOverlay;
+
 #[derive(Clone, Component, Default)]
 pub struct NPC;
 